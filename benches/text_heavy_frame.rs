@@ -0,0 +1,31 @@
+// How long a frame of 500 text nodes takes, run with `cargo bench --bench text_heavy_frame`.
+use std::time::Instant;
+
+use z71200_runtime::ui::draw::{DisplayOption, Tag};
+use z71200_runtime::ui::testing::{Headless, Tree, frac, px};
+
+fn main() {
+    let mut tree = Tree::new();
+    tree.enter()
+        .op(Tag::Width, &[frac(1.0)])
+        .op(Tag::Height, &[frac(1.0)])
+        .push((Tag::Display, DisplayOption::FlexColumn as usize));
+    for i in 0..500 {
+        tree.enter()
+            .op(Tag::Height, &[px(14.0)])
+            .text(px(0.0), px(0.0), &format!("Row {} of the list", i))
+            .leave();
+    }
+    tree.leave();
+    let mut headless = Headless::new(800, 600);
+
+    for _ in 0..3 {
+        headless.frame(&tree).unwrap(); /* warm the font caches */
+    }
+    let frames = 50;
+    let start = Instant::now();
+    for _ in 0..frames {
+        headless.frame(&tree).unwrap();
+    }
+    println!("500 text nodes: {:.2?} per frame", start.elapsed() / frames);
+}
@@ -5,6 +5,7 @@ import struct
 import ctypes
 import ctypes.util
 import mmap
+import base64
 import sys
 from time import sleep
 
@@ -20,6 +21,7 @@ print("Sock Name:", os.environ["z71200_SOCK"])
 assert os.environ["z71200_PROTOCOL_VERSION"] == str(EXPECTED_PROTOCOL)
 
 MACHINE_WORD = (sys.maxsize.bit_length() + 1) // 8
+RING_CAP = 1_024 * 4
 
 # Client
 def _open_sem(path, libc):
@@ -48,8 +50,13 @@ def _sem_post(sem, libc):
 class Z71200Context:
     def __init__(self) -> None:
         # Socket communication
-        self.sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
-        self.sock.connect(os.environ["z71200_SOCK"])
+        sock_addr = os.environ["z71200_SOCK"]
+        if sock_addr.startswith("tcp://"):
+            host, port = sock_addr[len("tcp://"):].rsplit(":", 1)
+            self.sock = socket.create_connection((host.strip("[]"), int(port)))
+        else:
+            self.sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+            self.sock.connect(sock_addr)
         # Open libc and setup interop
         libc_path = ctypes.util.find_library("c")
         if libc_path is None: raise Exception("C Library not found")
@@ -66,9 +73,10 @@ class Z71200Context:
         # Open shared file
         ## (it is important that the mmap object and buf object are stored on this class to avoid GC cleaning it up)
         LEN = 1_024 * 32
+        FILE_LEN = LEN + 16 + RING_CAP # data region, then the patch ring's head, tail, and bytes
         fd = _open_shared_memory(os.environ["z71200_SHM"], self.libc)
-        self.mm = mmap.mmap(fd, LEN, mmap.MAP_SHARED, mmap.PROT_READ | mmap.PROT_WRITE)
-        self.buf = (ctypes.c_char * LEN).from_buffer(self.mm)
+        self.mm = mmap.mmap(fd, FILE_LEN, mmap.MAP_SHARED, mmap.PROT_READ | mmap.PROT_WRITE)
+        self.buf = (ctypes.c_char * FILE_LEN).from_buffer(self.mm)
         self.shm_base = ctypes.addressof(self.buf)
 
         # Get pointers into mmaped file
@@ -76,6 +84,9 @@ class Z71200Context:
         DATA_OFF = VERSION_OFF + 8 # 64 bit
         self.version_ptr = ctypes.cast(self.shm_base + VERSION_OFF, ctypes.POINTER(ctypes.c_uint64))
         self.data_ptr = ctypes.cast(self.shm_base + DATA_OFF, ctypes.POINTER(ctypes.c_uint8))
+        self.ring_head_ptr = ctypes.cast(self.shm_base + LEN, ctypes.POINTER(ctypes.c_uint64))
+        self.ring_tail_ptr = ctypes.cast(self.shm_base + LEN + 8, ctypes.POINTER(ctypes.c_uint64))
+        self.ring_ptr = ctypes.cast(self.shm_base + LEN + 16, ctypes.POINTER(ctypes.c_uint8))
 
     def unsafe_read_version(self): # unsafe because assumes lock is held
         return struct.unpack("<I", ctypes.string_at(ctypes.addressof(self.version_ptr.contents), 4))[0]
@@ -94,6 +105,17 @@ class Z71200Context:
     def redraw(self):
         _sem_post(self.sem_ready, self.libc)
 
+    def queue_patch(self, data, loc): # like safe_write without the lock, applied on the next redraw()
+        assert isinstance(data, (bytes, bytearray, memoryview))
+        padding = -len(data) % MACHINE_WORD
+        record = struct.pack("<QQ", loc, len(data)) + bytes(data) + bytes(padding)
+        head, tail = self.ring_head_ptr[0], self.ring_tail_ptr[0]
+        if RING_CAP - (head - tail) < len(record): return False # full, use safe_write instead
+        for i, b in enumerate(record):
+            self.ring_ptr[(head + i) % RING_CAP] = b
+        self.ring_head_ptr[0] = head + len(record) # only once the record is complete
+        return True
+
     def safe_read(self, loc, n):
         _sem_wait(self.sem_lock, self.libc)
         assert self.unsafe_read_version() == EXPECTED_PROTOCOL
@@ -127,8 +149,16 @@ class Z71200Context:
         # before anything else on the socket. So after sending a `ask` type obj
         # you are guaranteed that the next thing on the socket is the response.
         assert obj["kind"] == "ask"
+        return self.ask_like(obj)
+
+    def ask_like(self, obj):
+        # messages like `tree` are answered the same way as an `ask`
         self.send(obj)
-        return self.recv()
+        resp = self.recv()
+        while resp.get("kind") == "ping": # a ping may have been queued before our ask was read
+            self.send({"kind": "pong"})
+            resp = self.recv()
+        return resp
 
     def recv(self): # block waiting for msgs
         size = struct.unpack('<I', self.recv_exact(4))[0] # little-endian u32 indicating message size
@@ -143,9 +173,34 @@ def into_ask(name, **args):
     if resp['kind'] == 'error': raise Exception(resp['error'])
     return resp['return']
 
-def aloc(n): return into_ask("aloc", n=n)
+def batch(*calls): # each call is (name, {args}), returns the list of their returns
+    resp = ctx.ask({'kind': 'batch', 'calls': [{'fn': name, 'args': args} for name, args in calls]});
+    if resp['kind'] == 'error': raise Exception(f"call {resp['index']}: {resp['error']}", resp['returns'])
+    return resp['returns']
+
+def aloc(n, align=None): return into_ask("aloc", n=n, align=align)
 def dealoc(ptr): return into_ask("dealoc", ptr=ptr)
+def compact(): return {old: new for old, new in into_ask("compact")}
 def set_root(ptr): return into_ask("set_root", ptr=ptr)
+def register_root(name, ptr): return into_ask("register_root", name=name, ptr=ptr)
+def set_root_named(name): return into_ask("set_root_named", name=name)
+def validate_tree(ptr): return into_ask("validate_tree", ptr=ptr) # {'ok': True} or {'ok': False, 'error': ..., 'at_offset': ...}
+def register_library(id, ptr, len): return into_ask("register_library", id=id, ptr=ptr, len=len)
+def set_fullscreen(mode): return into_ask("set_fullscreen", mode=mode) # 'borderless', 'exclusive' or 'off'
+def set_title(title): return into_ask("set_title", title=title)
+def set_cursor_visible(visible): return into_ask("set_cursor_visible", visible=visible)
+def set_cursor_grab(mode): return into_ask("set_cursor_grab", mode=mode) # 'none', 'confined' or 'locked'
+def set_cursor(cursor): return into_ask("set_cursor", cursor=cursor) # None to let the elements decide again
+def list_fonts(): return into_ask("list_fonts")
+def dump_state(path): return into_ask("dump_state", path=path) # also writes path + '.json'
+def hit_test(x, y): return into_ask("hit_test", x=x, y=y) # topmost element first
+def set_window_constraints(min_width=None, min_height=None, max_width=None, max_height=None):
+    return into_ask("set_window_constraints", min_width=min_width, min_height=min_height, max_width=max_width, max_height=max_height)
+
+def send_tree(data: bytes, root):
+    # push a whole arena over the socket instead of writing it into shared memory
+    resp = ctx.ask_like({'kind': 'tree', 'bytes': base64.b64encode(data).decode('ascii'), 'root': root})
+    if resp['kind'] == 'error': raise Exception(resp['error'])
 
 def write_tagged_word(ptr, tag, word):
     if word is None: word = 0xdeadbeefb00bee30
@@ -186,16 +241,20 @@ def pxs(v): return ('pxs', v)
 def rems(v): return ('rems', v)
 def frac(v): return ('frac', v)
 def auto(): return ('auto', None)
+def vw(v): return ('vw', v) # fraction of the window's width
+def vh(v): return ('vh', v) # fraction of the window's height
 def write_length(cursor, v):
     if v[0] == 'pxs': return  write_tagged_word(cursor, 1, float(v[1]))
     elif v[0] == 'rems': return  write_tagged_word(cursor, 2, float(v[1]))
     elif v[0] == 'frac': return  write_tagged_word(cursor, 3, float(v[1]))
     elif v[0] == 'auto': return  write_tagged_word(cursor, 4, None)
+    elif v[0] == 'vw': return  write_tagged_word(cursor, 81, float(v[1]))
+    elif v[0] == 'vh': return  write_tagged_word(cursor, 82, float(v[1]))
     else: raise Exception("Unknown value for length parameter.")
 
 def write_either_literal(cursor, v):
     if v[0] in ['rgb', 'rgba', 'hsv', 'hsva']: return write_color(cursor, v)
-    if v[0] in ['pxs', 'rems', 'frac', 'auto']: return write_length(cursor, v)
+    if v[0] in ['pxs', 'rems', 'frac', 'auto', 'vw', 'vh']: return write_length(cursor, v)
     else: raise Exception("Unknown value for length parameter.")
 
 def color(c):
@@ -203,6 +262,42 @@ def color(c):
         cursor = write_tagged_word(cursor, 21, None)
         return write_color(cursor, c)
     return f
+def color_transition(ms, c): # fades to c over ms milliseconds
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 78, ms)
+        return write_color(cursor, c)
+    return f
+def stroke_width(width): # pxs(0) fills again
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 79, None)
+        return write_length(cursor, width)
+    return f
+def linear_gradient(x0, y0, x1, y1, c0, c1):
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 80, None)
+        for length in (x0, y0, x1, y1): cursor = write_length(cursor, length)
+        cursor = write_color(cursor, c0)
+        return write_color(cursor, c1)
+    return f
+def clip_rounded_rect(radius): # the rest of the element and its children, frac(0.5) for a circle
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 91, None)
+        return write_length(cursor, radius)
+    return f
+def opacity(x): # 0 to 1, the rest of the element and its children
+    return lambda cursor: write_tagged_word(cursor, 93, float(x))
+def clear_color(c): # in the root element, before its first child
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 75, None)
+        return write_color(cursor, c)
+    return f
+def outline(width, c, offset):
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 53, None)
+        cursor = write_length(cursor, width)
+        cursor = write_color(cursor, c)
+        return write_length(cursor, offset)
+    return f
 def rect(x, y, width, height):
     def f(cursor):
         cursor = write_tagged_word(cursor, 11, None)
@@ -220,6 +315,12 @@ def rounded_rect( x, y, width, height, r):
         cursor = write_length(cursor, height)
         return write_length(cursor, r)
     return f
+def border_radius(tl, tr, br, bl): # for the rounded_rects after it, auto() keeps their own radius
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 92, None)
+        for corner in (tl, tr, br, bl): cursor = write_length(cursor, corner)
+        return cursor
+    return f
 
 ## Path
 def begin_path(): return lambda cursor: write_tagged_word(cursor, 13, None)
@@ -264,6 +365,18 @@ def arc_to(tx, ty, x, y, r):
         return write_length(cursor, r)
     return f
 def close_path(): return lambda cursor: write_tagged_word(cursor, 20, None)
+def line(x0, y0, x1, y1):
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 76, None)
+        cursor = write_length(cursor, x0)
+        cursor = write_length(cursor, y0)
+        cursor = write_length(cursor, x1)
+        return write_length(cursor, y1)
+    return f
+
+# Library
+def library_call(id): return lambda cursor: write_tagged_word(cursor, 47, id)
+def library_return(): return lambda cursor: write_tagged_word(cursor, 48, None)
 
 # Layout
 def write_width(cursor, w):
@@ -285,27 +398,67 @@ def write_margin(cursor, left, top, right, bottom):
     cursor = write_length(cursor, top)
     cursor = write_length(cursor, right)
     return write_length(cursor, bottom)
+def write_padding_side(cursor, side, length): # side is 'left', 'top', 'right' or 'bottom'
+    cursor = write_tagged_word(cursor, 65 + ['left', 'top', 'right', 'bottom'].index(side), None)
+    return write_length(cursor, length)
+def write_margin_side(cursor, side, length):
+    cursor = write_tagged_word(cursor, 69 + ['left', 'top', 'right', 'bottom'].index(side), None)
+    return write_length(cursor, length)
+def write_key(cursor, key): return write_tagged_word(cursor, 74, key)
 def write_display(cursor, display_option): return write_tagged_word(cursor, 26, display_option)
-def write_gap(cursor, gw, gh):
+def write_align_items(cursor, align_items): return write_tagged_word(cursor, 54, align_items)
+def write_grid_columns(cursor, count): return write_tagged_word(cursor, 86, count)
+def write_grid_rows(cursor, count): return write_tagged_word(cursor, 87, count)
+def write_justify_items(cursor, align_items): return write_tagged_word(cursor, 88, align_items)
+ALIGN_CONTENT = ['start', 'end', 'center', 'stretch', 'space-between', 'space-evenly', 'space-around']
+def write_justify_content(cursor, name): return write_tagged_word(cursor, 89, ALIGN_CONTENT.index(name))
+def write_align_content(cursor, name): return write_tagged_word(cursor, 90, ALIGN_CONTENT.index(name))
+def write_gap(cursor, main, cross):
     cursor = write_tagged_word(cursor, 27, None)
-    cursor = write_length(cursor, gw)
-    cursor = write_length(cursor, gh)
+    cursor = write_length(cursor, main)
+    cursor = write_length(cursor, cross)
     return cursor
 
 # Mouse
 def cursor_default(): return ('cursor', 'default')
 def cursor_pointer(): return ('cursor', 'pointer')
+def cursor_named(name): return ('cursor', name) # any of HOVER_CURSORS
+HOVER_CURSORS = ['default', 'pointer', 'text', 'wait', 'progress', 'crosshair', 'move', 'grab', 'grabbing', 'not-allowed', 'help']
 def mouse_cursor(c):
     def f(cursor):
         if c[1] == "default":
            return write_tagged_word(cursor, 45, None)
         elif c[1] == "pointer":
             return  write_tagged_word(cursor, 46, None)
+        elif c[1] in HOVER_CURSORS:
+            return write_tagged_word(cursor, 84, HOVER_CURSORS.index(c[1]))
         raise Exception("Unknown cursor type", c)
     return f
 
 # Text
 def write_font_size(cursor, size): return write_tagged_word(cursor, 42, float(size))
+def write_font_size_em(cursor, factor): return write_tagged_word(cursor, 64, float(factor))
+def write_font_size_from_reg(cursor, reg): # the size is whatever register `reg` holds
+    cursor = write_tagged_word(cursor, 98, None)
+    return write_tagged_word(cursor, 37, reg)
+def write_tab_width(cursor, columns): return write_tagged_word(cursor, 55, columns)
+def write_text_color_run(cursor, offset, c):
+    cursor = write_tagged_word(cursor, 60, offset)
+    return write_color(cursor, c)
+def write_outline_dash(cursor, dash, gap):
+    cursor = write_tagged_word(cursor, 63, None)
+    cursor = write_length(cursor, dash)
+    return write_length(cursor, gap)
+def write_inline_box(cursor, offset, w, h):
+    cursor = write_tagged_word(cursor, 61, offset)
+    cursor = write_length(cursor, w)
+    return write_length(cursor, h)
+def write_pixel_snap_text(cursor, on): return write_tagged_word(cursor, 59, 1 if on else 0)
+def write_word_break(cursor, option): return write_tagged_word(cursor, 58, option) # 0 normal, 1 break all, 2 keep all
+def write_white_space(cursor, option): return write_tagged_word(cursor, 97, option) # 0 preserve, 1 collapse runs into a space
+def write_baseline_shift(cursor, shift):
+    cursor = write_tagged_word(cursor, 56, None)
+    return write_length(cursor, shift)
 def write_font_alignment(cursor, alignment): return write_tagged_word(cursor, 43, alignment)
 def write_font_family(cursor, ptr):
     cursor = write_tagged_word(cursor, 44, None)
@@ -315,10 +468,29 @@ def write_font_family(cursor, ptr):
 # >>  Higher Level Components
 ## Event map and generic handler
 GLOBAL_CALLBACK_MAP = {}
+NAMED_CALLBACK_MAP = {}
+VIEWPORT_CALLBACK = None # gets (scroll_y, height) whenever the visible part of the root changes
+FOCUS_CALLBACK = None # gets True or False when the window gains or loses focus
+FILE_DROP_CALLBACK = None # gets the path of each file dropped onto the window
 def handle_event(obj):
+    if obj.get('kind') == 'ping': return ctx.send({'kind': 'pong'})
+    if obj.get('kind') == 'viewport':
+        if VIEWPORT_CALLBACK is not None: VIEWPORT_CALLBACK(obj['scroll_y'], obj['height'])
+        return
+    if obj.get('kind') == 'focus':
+        if FOCUS_CALLBACK is not None: FOCUS_CALLBACK(obj['focused'])
+        return
+    if obj.get('kind') == 'file_drop':
+        if FILE_DROP_CALLBACK is not None: FILE_DROP_CALLBACK(obj['path'])
+        return
+    name = obj.get('name', None)
+    if name is not None:
+        if name in NAMED_CALLBACK_MAP: NAMED_CALLBACK_MAP[name]()
+        return
     id = obj.get('evt_id', None)
     if id is None: return;
     if id not in GLOBAL_CALLBACK_MAP: return;
+    if 'scroll_y' in obj: return GLOBAL_CALLBACK_MAP[id](obj['scroll_y'])
     GLOBAL_CALLBACK_MAP[id]()
 
 ## Deal with event modification
@@ -327,6 +499,16 @@ def write_cond_evt(cursor, fn, tag):
     cursor = write_tagged_word(cursor, 39, len(GLOBAL_CALLBACK_MAP))
     GLOBAL_CALLBACK_MAP[len(GLOBAL_CALLBACK_MAP)] = fn
     return cursor
+def write_scroll_report(cursor, fn): # fn gets how far the element is scrolled down
+    cursor = write_tagged_word(cursor, 73, len(GLOBAL_CALLBACK_MAP))
+    GLOBAL_CALLBACK_MAP[len(GLOBAL_CALLBACK_MAP)] = fn
+    return cursor
+def write_evt_from_reg(cursor, reg): # the id is whatever register `reg` holds, e.g. the index of a list row
+    cursor = write_tagged_word(cursor, 85, None)
+    return write_tagged_word(cursor, 37, reg)
+def write_named_evt(cursor, name_ptr):
+    cursor = write_tagged_word(cursor, 57, None)
+    return write_tagged_word(cursor, 41, name_ptr)
 ## Deal with style modification
 def _branch(cursor, f, tag, n):
     cursor = write_tagged_word(cursor, tag, MACHINE_WORD * 2 * n)
@@ -337,17 +519,42 @@ def _branch_w_default(cursor, f, d_f, tag, n):
     cursor = f(cursor)
     cursor = write_tagged_word(cursor, 32, MACHINE_WORD * 2 * n)
     return d_f(cursor)
+def _word_branch(cursor, tag, word, f, n): # the word is after the jump, the jump counts from after it
+    cursor = write_tagged_word(cursor, tag, MACHINE_WORD * 2 * n)
+    cursor = write_tagged_word(cursor, 0, word)
+    return f(cursor)
+def _button_branch(cursor, button, f, n): return _word_branch(cursor, 83, button, f, n)
+def button(n): return ('button', n) # a conditional state, while gamepad button n is held e.g. (button(0), ('rgb', ...))
+KEYS = {'enter': 0x28, 'escape': 0x29, 'backspace': 0x2A, 'tab': 0x2B, 'space': 0x2C, 'delete': 0x4C,
+        'right': 0x4F, 'left': 0x50, 'down': 0x51, 'up': 0x52} # USB HID usages, 'a' to 'z' are 0x04 up
+def key(name): return KEYS[name] if name in KEYS else 0x04 + ord(name) - ord('a')
+def key_down(k): return ('key_down', key(k)) # a conditional state for the frame after the key went down
+def key_up(k): return ('key_up', key(k))
+def typed(ch): return ('typed', ord(ch)) # the frame after the character was typed, for text entry
+def write_key_evt(cursor, fn, v): # fires fn on a key_down, key_up or typed, in the element the client focused
+    tag = {'key_down': 94, 'key_up': 95, 'typed': 96}[v[0]]
+    cursor = write_tagged_word(cursor, tag, MACHINE_WORD * 2)
+    cursor = write_tagged_word(cursor, 0, v[1])
+    cursor = write_tagged_word(cursor, 39, len(GLOBAL_CALLBACK_MAP))
+    GLOBAL_CALLBACK_MAP[len(GLOBAL_CALLBACK_MAP)] = fn
+    return cursor
 def write_cond_style(cursor, v, style_f, n): # v is either ('rgb', bytes) or ('hover', ('rgb', bytes)) or ('hover', ('rgb', bytes), ('rgb', bytes))
     if not isinstance(v[1], tuple): return style_f(v)(cursor)
     if len(v) == 2: #nodefault
         if v[0] == 'hover':   return _branch(cursor, style_f(v[1]), 28, n)
         if v[0] == 'pressed': return _branch(cursor, style_f(v[1]), 29, n)
         if v[0] == 'clicked': return _branch(cursor, style_f(v[1]), 30, n)
+        if v[0] == 'focus_visible': return _branch(cursor, style_f(v[1]), 62, n)
+        if v[0][0] == 'button': return _button_branch(cursor, v[0][1], style_f(v[1]), n)
+        if v[0][0] == 'key_down': return _word_branch(cursor, 94, v[0][1], style_f(v[1]), n)
+        if v[0][0] == 'key_up': return _word_branch(cursor, 95, v[0][1], style_f(v[1]), n)
+        if v[0][0] == 'typed': return _word_branch(cursor, 96, v[0][1], style_f(v[1]), n)
         raise Exception("Unknown conditional state in", v)
     if len(v) == 3: #w/default
         if v[0] == 'hover':   return _branch_w_default(cursor, style_f(v[1]), style_f(v[2]), 28, n)
         if v[0] == 'pressed': return _branch_w_default(cursor, style_f(v[1]), style_f(v[2]), 29, n)
         if v[0] == 'clicked': return _branch_w_default(cursor, style_f(v[1]), style_f(v[2]), 30, n)
+        if v[0] == 'focus_visible': return _branch_w_default(cursor, style_f(v[1]), style_f(v[2]), 62, n)
         raise Exception("Unknown conditional state in", v)
     raise Exception('Bad conditional format', v)
 
@@ -429,7 +636,7 @@ def col(children,
 
         cursor = write_padding(cursor, *padding)
         cursor = write_margin(cursor, *margin)
-        cursor = write_gap(cursor, pxs(0), gap)
+        cursor = write_gap(cursor, gap, pxs(0)) # along the column
 
         # Events
         if clicked is not None: cursor = write_cond_evt(cursor, clicked, 30)
@@ -442,6 +649,35 @@ def col(children,
         return cursor
     return f
 
+def grid(children,
+    columns, rows=0,
+    w=auto(), h=auto(),
+    padding=(pxs(0), pxs(0), pxs(0), pxs(0)),
+    margin=(pxs(0), pxs(0), pxs(0), pxs(0)),
+    gap=(pxs(0), pxs(0)), # between columns, between rows
+    justify_content=None, align_content=None
+):
+    def f(cursor):
+        # Layout
+        cursor = write_tagged_word(cursor, 9, None) # Enter
+        cursor = write_width(cursor, w)
+        cursor = write_height(cursor, h)
+        cursor = write_display(cursor, 3) # Grid
+        cursor = write_grid_columns(cursor, columns)
+        cursor = write_grid_rows(cursor, rows)
+
+        cursor = write_padding(cursor, *padding)
+        cursor = write_margin(cursor, *margin)
+        cursor = write_gap(cursor, *gap)
+        if justify_content is not None: cursor = write_justify_content(cursor, justify_content)
+        if align_content is not None: cursor = write_align_content(cursor, align_content)
+
+        for c in children: cursor = c(cursor)
+
+        cursor = write_tagged_word(cursor, 10, None) # Leave
+        return cursor
+    return f
+
 def span(text_ptr, x=pxs(0), y=pxs(0), w=auto(), text_color=rgb('000000'), alignment="start", size=None, font_family=None):
     def f(cursor):
         # Layout
@@ -469,6 +705,16 @@ def span(text_ptr, x=pxs(0), y=pxs(0), w=auto(), text_color=rgb('000000'), align
         return cursor
     return f
 
+def text_at(x, y, max_width, text_ptr):
+    def f(cursor):
+        cursor = write_tagged_word(cursor, 77, None) # TextAt, drawn without taking room
+        cursor = write_length(cursor, x)
+        cursor = write_length(cursor, y)
+        cursor = write_length(cursor, max_width)
+        cursor = text_ptr.write_ref(cursor) # writes the ptr
+        return cursor
+    return f
+
 class TextPtr:
     def __init__(self, text) -> None:
         self.str_ptr = aloc_tagged_str(text)
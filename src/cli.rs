@@ -1,4 +1,9 @@
-use clap::Parser;
+use std::{ffi::OsString, path::PathBuf};
+
+use clap::{
+    CommandFactory, FromArgMatches, Parser, ValueEnum,
+    builder::{PossibleValuesParser, TypedValueParser},
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "z71200")]
@@ -6,6 +11,249 @@ use clap::Parser;
     about = "Launches the z71200 UI runtime with required context injected into your target programme."
 )]
 pub struct Cli {
-    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    /// Create the window with a transparent background (needs a compositor).
+    #[arg(long)]
+    pub transparent: bool,
+
+    /// Create the window without title bar and borders.
+    #[arg(long)]
+    pub no_decorations: bool,
+
+    /// Image (png, jpeg, ...) shown as the window's icon in the title bar and taskbar.
+    #[arg(long)]
+    pub icon: Option<PathBuf>,
+
+    /// Stacking level of the window relative to other windows.
+    #[arg(long, value_enum, default_value_t = WindowLevelArg::Normal)]
+    pub window_level: WindowLevelArg,
+
+    /// Smallest width (in physical pixels) the window can be resized to.
+    #[arg(long, requires = "min_height")]
+    pub min_width: Option<u32>,
+
+    /// Smallest height (in physical pixels) the window can be resized to.
+    #[arg(long, requires = "min_width")]
+    pub min_height: Option<u32>,
+
+    /// Largest width (in physical pixels) the window can be resized to.
+    #[arg(long, requires = "max_height")]
+    pub max_width: Option<u32>,
+
+    /// Largest height (in physical pixels) the window can be resized to.
+    #[arg(long, requires = "max_width")]
+    pub max_height: Option<u32>,
+
+    /// Lay out and draw in logical pixels and scale the finished frame to the display once, instead of scaling every length.
+    #[arg(long)]
+    pub logical_coordinates: bool,
+
+    /// Samples per pixel for anti-aliasing edges on the GPU. More look smoother, especially thin diagonal strokes, but the frame takes that many times the memory and fill rate.
+    #[arg(long, default_value_t = 1, value_parser = PossibleValuesParser::new(["1", "2", "4", "8"]).map(|s| s.parse::<usize>().unwrap()))]
+    pub msaa: usize,
+
+    /// Colour space the window is drawn in and colours in the layout are given in. `display-p3` needs a wide-gamut display and driver support, otherwise it falls back to sRGB.
+    #[arg(long, value_enum, default_value_t = ColorSpaceArg::Srgb)]
+    pub color_space: ColorSpaceArg,
+
+    /// Multiplies how far the mouse wheel and trackpad scroll.
+    #[arg(long, default_value_t = 1.0)]
+    pub scroll_speed: f32,
+
+    /// Invert the scroll direction, for when the system doesn't already do it.
+    #[arg(long)]
+    pub natural_scroll: bool,
+
+    /// Don't animate, every change is shown at once. For people sensitive to motion and for screenshots that need a settled frame.
+    #[arg(long)]
+    pub reduce_motion: bool,
+
+    /// Font size in pixels that "Rems" are multiples of and that text has unless the layout sets one. Raise it to scale up all text in the UI, say for low vision.
+    #[arg(long, default_value_t = 16.0, value_parser = font_size)]
+    pub base_font_size: f32,
+
+    /// Log how long each pass of every frame took, with the number of elements and instructions, to find out what makes a big layout slow.
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Outline the box of every element on top of the frame, coloured by how deep it is nested and labelled with its size, to see what the flex layout made of the tree.
+    #[arg(long)]
+    pub debug_layout: bool,
+
+    /// Read gamepads: the d-pad and A work like the arrow keys and Enter, held buttons can be branched on with `ButtonPressed`, and the client gets every button and stick event. Needs a build with the `gamepad` feature.
+    #[arg(long)]
+    pub gamepad: bool,
+
+    /// Address the client connects to, `tcp://host:port` or `unix:///path`. Defaults to a unix socket in /tmp.
+    #[arg(long)]
+    pub listen: Option<String>,
+
+    /// Wait for the client's new-frame signal on a thread of its own instead of polling for it. Polling can add up to 5ms to every frame, this takes a thread that is asleep most of the time.
+    #[arg(long)]
+    pub blocking_sem: bool,
+
+    /// Seconds between liveness pings to the client, 0 turns them off for a client that doesn't answer them. A client that stays silent for three intervals is considered dead.
+    #[arg(long, default_value_t = 10)]
+    pub heartbeat_interval: u64,
+
+    /// Most arguments the layout may push onto the VM stack.
+    #[arg(long, default_value_t = 1024)]
+    pub max_stack_depth: usize,
+
+    /// Most registers the layout may set within one scope.
+    #[arg(long, default_value_t = 1024)]
+    pub max_registers: usize,
+
+    /// How deep library calls may be nested.
+    #[arg(long, default_value_t = 64)]
+    pub max_call_depth: usize,
+
+    /// Most bytes a single "aloc" may ask for. Without it an allocation is only bounded by the free space in the shared file.
+    #[arg(long)]
+    pub max_alloc: Option<usize>,
+
+    /// Render the layout in this file instead of launching a programme, for debugging. The file holds the bytes of the shared file's data region, as sent in a "tree" message.
+    #[arg(long)]
+    pub tree: Option<PathBuf>,
+
+    /// Offset of the root `Enter` in the `--tree` file.
+    #[arg(long, default_value_t = 0, requires = "tree")]
+    pub root: usize,
+
+    /// Run the `--tree` headless with the input from this file instead of opening a window, one JSON object per frame. Prints the messages the client would get and a summary of each frame as JSON lines.
+    #[arg(long, requires = "tree")]
+    pub script: Option<PathBuf>,
+
+    /// Another programme to launch against the same window and shared file, with a socket of its own. Its arguments run up to a `;`, which most shells need escaped: `--also python3 -u producer.py \;`. Repeat it for more programmes.
+    #[arg(
+        long,
+        num_args = 1..,
+        allow_hyphen_values = true,
+        value_terminator = ";",
+        value_name = "COMMAND"
+    )]
+    also: Vec<String>, /* every `--also` run together, `try_parse_args_from` keeps them apart */
+
+    #[arg(skip)]
+    also_commands: Vec<Vec<String>>,
+
+    /// The programme to launch, its arguments are passed on as they are.
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required_unless_present = "tree"
+    )]
     pub command: Vec<String>,
 }
+impl Cli {
+    /// Parses the arguments of the process. Use it instead of `Parser::parse`, which can't tell
+    /// the programmes of several `--also` apart.
+    pub fn parse_args() -> Self {
+        Self::try_parse_args_from(std::env::args_os()).unwrap_or_else(|err| err.exit())
+    }
+
+    pub fn try_parse_args_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(args)?;
+        let mut cli =
+            Self::from_arg_matches(&matches).map_err(|err| err.format(&mut Self::command()))?;
+        cli.also_commands = matches
+            .get_occurrences::<String>("also")
+            .map(|runs| runs.map(|run| run.cloned().collect()).collect())
+            .unwrap_or_default();
+        Ok(cli)
+    }
+
+    /// The programmes to launch, `command` first and then each `--also`.
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        std::iter::once(&self.command)
+            .chain(&self.also_commands)
+            .filter(|run| !run.is_empty())
+            .cloned()
+            .collect()
+    }
+}
+
+/// A font size in pixels, which only makes sense positive and finite.
+fn font_size(s: &str) -> Result<f32, String> {
+    let size = s.parse::<f32>().map_err(|err| err.to_string())?;
+    if !size.is_finite() || size <= 0.0 {
+        return Err(format!("a font size must be more than 0 pixels, got {}", s));
+    }
+    Ok(size)
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum WindowLevelArg {
+    AlwaysOnBottom,
+    Normal,
+    AlwaysOnTop,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ColorSpaceArg {
+    Srgb,
+    DisplayP3,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn also_launches_more_programmes() {
+        let cli = Cli::try_parse_args_from([
+            "z71200",
+            "--also",
+            "python3",
+            "-u",
+            "producer.py",
+            ";",
+            "--also",
+            "./logger",
+            ";",
+            "python3",
+            "ui.py",
+            "--",
+            "--flag",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.commands(),
+            [
+                vec!["python3", "ui.py", "--", "--flag"],
+                vec!["python3", "-u", "producer.py"],
+                vec!["./logger"],
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_base_font_size_that_isnt_positive() {
+        let parse = |size: &str| Cli::try_parse_from(["z71200", "--base-font-size", size, "ui"]);
+        assert_eq!(parse("20").unwrap().base_font_size, 20.0);
+        for size in ["0", "-4", "NaN", "inf", "big"] {
+            assert!(parse(size).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn heartbeat_is_on_unless_turned_off() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["z71200"], args, &["ui"]].concat());
+        assert_eq!(parse(&[]).unwrap().heartbeat_interval, 10);
+        assert_eq!(
+            parse(&["--heartbeat-interval", "0"])
+                .unwrap()
+                .heartbeat_interval,
+            0
+        );
+    }
+
+    #[test]
+    fn one_programme_keeps_its_arguments() {
+        let cli = Cli::try_parse_from(["z71200", "node", "client.js", "--", "-v"]).unwrap();
+        assert_eq!(cli.commands(), [vec!["node", "client.js", "--", "-v"]]);
+    }
+}
@@ -0,0 +1,8 @@
+// The runtime as a library, so the tests in `tests/` can draw trees with the harness in
+// `ui::testing`. The executable in `main.rs` is built on top of it.
+pub mod cli;
+pub mod ll_aloc;
+pub mod process;
+pub mod shm;
+pub mod sock;
+pub mod ui;
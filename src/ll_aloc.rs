@@ -8,6 +8,8 @@ aloc (n):
     alloc new node after `...` set the new node to be free and the old node to not be free
     rewire so that
         found_node -> new_node -> old_rhs_node (if exist)
+    unless what is left after `...` couldn't hold a header and a word of data, then the found
+    node is handed over whole and the few bytes past `...` are slack until it is freed
 
 free (ptr):
     the given ptr is what is returned from aloc and so just after the `next` u32 ie.
@@ -26,15 +28,66 @@ free> ** ~~~~~~~~~~~ ___________..
 //TODO: The three pointer kids we have for a block (ie block_ptr, data_ptr, free_ptr) could be captured in the type system better (ie. writing a block head to a free ptr upgrades that location to a block_ptr)
 
 use anyhow::{Result, anyhow};
+use std::sync::LazyLock;
+use tracing::error;
 
-const WORD: usize = core::mem::size_of::<usize>(); // 4 of 8
+pub const WORD: usize = core::mem::size_of::<usize>(); // 4 of 8
+const PAGE: usize = 4096;
 const IS_FREE_BYTE_OFF: usize = 0;
+const ALIGN_BYTE_OFF: usize = IS_FREE_BYTE_OFF + 1; // log2 of the data's alignment, 0 means WORD. Lives in the padding.
+const CANARY_SLACK_BYTE_OFF: usize = ALIGN_BYTE_OFF + 1; // u16, bytes from the end of the requested size to the end of the block while canaries are on. Also in the padding.
 const NEXT_PTR_BYTE_OFF: usize = IS_FREE_BYTE_OFF + 1 + (WORD - 1); // skip first byte and align to the next word
 const DATA_PTR_BYTE_OFF: usize = NEXT_PTR_BYTE_OFF + WORD; // size is word
 
 // compile time sanity
 const HEADER_SIZE: usize = DATA_PTR_BYTE_OFF;
+// A free block split off after an allocation has to be able to hold at least this much, a smaller
+// remainder stays with the allocation instead of becoming a block nothing fits in.
+const MIN_SPLIT: usize = HEADER_SIZE + WORD;
 const _: () = assert!(HEADER_SIZE % WORD == 0);
+const _: () = assert!(CANARY_SLACK_BYTE_OFF + 2 <= NEXT_PTR_BYTE_OFF);
+
+// With `z71200_HEAP_CANARY=1` every allocation gets these bytes written right after the requested
+// size, a client writing past its allocation is then caught the next time the block is read.
+const CANARY: [u8; 8] = [0xDE, 0xAD, 0xBE, 0xEF, 0xDE, 0xAD, 0xBE, 0xEF];
+static CANARY_ON: LazyLock<bool> =
+    LazyLock::new(|| std::env::var("z71200_HEAP_CANARY").is_ok_and(|x| x == "1"));
+
+/// `aloc` found no free block big enough. Kept apart from other errors so the client can be told
+/// how much would have fit and free something up.
+#[derive(Debug, Clone, Copy)]
+pub struct OutOfMemory {
+    pub requested: usize,
+    pub available: usize, /* the most bytes one `aloc` could get right now */
+    pub file_len: usize,
+}
+impl std::fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Insuficent remaining space to allocate {} bytes in file with total size {} bytes, at most {} bytes fit",
+            self.requested, self.file_len, self.available
+        )
+    }
+}
+impl std::error::Error for OutOfMemory {}
+
+/// A block `compact` moved: its data was at `old` and is at `new` now, `len` bytes of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Moved {
+    pub old: usize,
+    pub new: usize,
+    pub len: usize,
+}
+
+/// Where `off`, anywhere in the data of a block before `compact`, is after it. Offsets outside
+/// the blocks that moved stay.
+pub fn moved_offset(moves: &[Moved], off: usize) -> usize {
+    match moves.iter().find(|m| (m.old..m.old + m.len).contains(&off)) {
+        Some(m) => off - m.old + m.new,
+        None => off,
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 struct BlockHeadView {
@@ -42,28 +95,37 @@ struct BlockHeadView {
     is_free: bool,
     next_off: usize,
     data_off: usize,
+    align: usize,
 }
 
-unsafe fn from_block_off(block_off: usize, file_start: *const u8) -> Result<BlockHeadView> {
+unsafe fn from_block_off(block_off: usize, file_start: *mut u8) -> Result<BlockHeadView> {
     unsafe {
         let block_ptr = file_start.add(block_off);
 
         let is_free = *block_ptr.add(IS_FREE_BYTE_OFF) == 1;
+        let align = match *block_ptr.add(ALIGN_BYTE_OFF) {
+            0 => WORD,
+            log2 => 1 << log2,
+        };
 
         let next_field_contents: usize = usize::from_le_bytes(
             std::slice::from_raw_parts(block_ptr.add(NEXT_PTR_BYTE_OFF), WORD).try_into()?,
         );
-        let data_off = block_off + DATA_PTR_BYTE_OFF;
-        return Ok(BlockHeadView {
+        let block = BlockHeadView {
             off: block_off,
             is_free,
             next_off: next_field_contents,
-            data_off,
-        });
+            data_off: block_off + DATA_PTR_BYTE_OFF,
+            align,
+        };
+        if *CANARY_ON && !is_free {
+            check_canary(&block, file_start);
+        }
+        return Ok(block);
     }
 }
 
-unsafe fn from_data_off(data_off: usize, file_start: *const u8) -> Result<BlockHeadView> {
+unsafe fn from_data_off(data_off: usize, file_start: *mut u8) -> Result<BlockHeadView> {
     unsafe {
         let block_off = data_off - DATA_PTR_BYTE_OFF;
         return from_block_off(block_off, file_start);
@@ -92,6 +154,8 @@ unsafe fn write_new_block(
 
     // do the write
     unsafe { *new_free_ptr = if is_free { 1u8 } else { 0u8 } };
+    unsafe { *loc.add(ALIGN_BYTE_OFF) = 0u8 };
+    unsafe { std::ptr::write_bytes(loc.add(CANARY_SLACK_BYTE_OFF), 0, 2) };
 
     let next_ptr_as_slice: &mut [u8] =
         unsafe { std::slice::from_raw_parts_mut(new_next_ptr, WORD) };
@@ -102,13 +166,11 @@ unsafe fn write_new_block(
         is_free,        /* wrote that data */
         next_off: next, /* we wrote that data */
         data_off: new_data_off,
+        align: WORD, /* default alignment */
     })
 }
 
-unsafe fn next_from_block(
-    block_off: usize,
-    file_start: *const u8,
-) -> Result<Option<BlockHeadView>> {
+unsafe fn next_from_block(block_off: usize, file_start: *mut u8) -> Result<Option<BlockHeadView>> {
     let cur = unsafe { from_block_off(block_off, file_start)? };
     if cur.next_off == 0 {
         return Ok(None);
@@ -129,6 +191,100 @@ unsafe fn set_next_off(block_off: usize, next_off: usize, file_start: *mut u8) -
     Ok(())
 }
 
+unsafe fn set_align(block_off: usize, align: usize, file_start: *mut u8) -> Result<()> {
+    let log2 = if align == WORD {
+        0
+    } else {
+        align.trailing_zeros() as u8
+    };
+    unsafe { *(file_start.add(block_off + ALIGN_BYTE_OFF)) = log2 };
+    Ok(())
+}
+
+// The canary goes right after the `n` bytes the client asked for. Where that is is remembered as
+// the distance to the end of the block, which stays small however big the allocation is.
+unsafe fn write_canary(block: &BlockHeadView, n: usize, file_start: *mut u8) {
+    let Some(slack) = block
+        .next_off
+        .checked_sub(block.data_off + n)
+        .and_then(|slack| u16::try_from(slack).ok())
+    else {
+        return; /* no room behind the data the header can describe, go without */
+    };
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            slack.to_le_bytes().as_ptr(),
+            file_start.add(block.off + CANARY_SLACK_BYTE_OFF),
+            2,
+        );
+        std::ptr::copy_nonoverlapping(
+            CANARY.as_ptr(),
+            file_start.add(block.data_off + n),
+            CANARY.len(),
+        );
+    }
+}
+
+unsafe fn canary_slack(block_off: usize, file_start: *const u8) -> usize {
+    unsafe {
+        u16::from_le_bytes([
+            *file_start.add(block_off + CANARY_SLACK_BYTE_OFF),
+            *file_start.add(block_off + CANARY_SLACK_BYTE_OFF + 1),
+        ])
+    }
+    .into()
+}
+
+// Logs the block if its canary was overwritten, then puts the canary back so it is reported once.
+unsafe fn check_canary(block: &BlockHeadView, file_start: *mut u8) {
+    let slack = unsafe { canary_slack(block.off, file_start) };
+    if slack == 0 {
+        return; /* allocated without a canary */
+    }
+    let n = block.next_off - block.data_off - slack;
+
+    let canary =
+        unsafe { std::slice::from_raw_parts(file_start.add(block.data_off + n), CANARY.len()) };
+    if canary != CANARY {
+        error!(
+            "Heap corruption: the block at offset {} ({} bytes) was written past its end, canary is {:x?}",
+            block.data_off, n, canary
+        );
+        unsafe { write_canary(block, n, file_start) };
+    }
+}
+
+// `compact` gave the live block at `block_off` `by` more bytes at its end, its canary is as far
+// from the end as it was plus those.
+unsafe fn grow_canary_slack(block_off: usize, by: usize, file_start: *mut u8) {
+    let slack = unsafe { canary_slack(block_off, file_start) };
+    if slack == 0 {
+        return;
+    }
+    // dropping the canary beats pointing it at the wrong bytes
+    let grown = u16::try_from(slack + by).unwrap_or(0);
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            grown.to_le_bytes().as_ptr(),
+            file_start.add(block_off + CANARY_SLACK_BYTE_OFF),
+            2,
+        );
+    }
+}
+
+// The first offset from `start` on where a block can go so its data is `align` aligned. Unless
+// that is `start` itself the space in front must fit the header of a free padding block.
+fn aligned_block_off(start: usize, align: usize, file_start: *const u8) -> usize {
+    let base = file_start as usize;
+    let mut data_addr = align_up(base + start + DATA_PTR_BYTE_OFF, align);
+    if data_addr != base + start + DATA_PTR_BYTE_OFF
+        && data_addr - base - DATA_PTR_BYTE_OFF < start + HEADER_SIZE
+    {
+        data_addr += align;
+    }
+    data_addr - base - DATA_PTR_BYTE_OFF
+}
+
 fn check_alignment_is_ok(ptr: *const u8) -> Result<()> {
     if (ptr as usize) % WORD != 0 {
         return Err(anyhow!(
@@ -145,6 +301,10 @@ fn align_up(n: usize, alignment: usize) -> usize {
     (n + alignment - 1) & !(alignment - 1)
 }
 
+/// Writes the first block, one free block over the whole file.
+///
+/// # Safety
+/// `file_start` has to point at the start of the mapped file, which no one else writes to.
 pub unsafe fn init(file_start: *mut u8) -> Result<()> {
     check_alignment_is_ok(file_start)?;
     unsafe { write_new_block(0, true, 0, file_start) }?;
@@ -152,7 +312,14 @@ pub unsafe fn init(file_start: *mut u8) -> Result<()> {
 }
 
 // we are going to rely on unallocated memory being zeros...
-pub unsafe fn aloc(n: usize, file_start: *mut u8, file_end: *const u8) -> Result<usize> {
+/// # Safety
+/// `file_start` to `file_end` has to be the mapped file, set up with `init` and locked.
+pub unsafe fn aloc(
+    n: usize,
+    align: usize,
+    file_start: *mut u8,
+    file_end: *const u8,
+) -> Result<usize> {
     // scan through all the blocks until we find either:
     //  1) one set to free of sufficent size
     //  2) one with nullptr next_ptr with enough space at the end of the file
@@ -163,6 +330,16 @@ pub unsafe fn aloc(n: usize, file_start: *mut u8, file_end: *const u8) -> Result
             n
         ));
     }
+    // the alignment is of the address, which is only the same in the client's mapping because
+    // both map the file page aligned. So don't promise more than a page.
+    if !align.is_power_of_two() || align < WORD || align > PAGE {
+        return Err(anyhow!(
+            "alignment must be a power of two between {} and {}, received {}",
+            WORD,
+            PAGE,
+            align
+        ));
+    }
 
     // for sanity, check if file_start is aligned
     check_alignment_is_ok(file_start)?;
@@ -170,36 +347,78 @@ pub unsafe fn aloc(n: usize, file_start: *mut u8, file_end: *const u8) -> Result
     // at the very top we can modify n such that it is well aligned, and then run the normal routine
     // this will allocate some extra space invisibly, but the contract with the caller is that atleast (!) n bytes become available
     // if they make a write of size n, and their implementation writes some more data for alignment, it's okay, since we've set the alignment correctly here.
+    let requested = n;
+    let n = if *CANARY_ON { n + CANARY.len() } else { n };
     let n = align_up(n, WORD);
 
+    let mut available = 0; /* for the error if nothing fits */
     let mut cur_block = Some(unsafe { from_block_off(0, file_start) }?);
     while let Some(cur) = cur_block {
         // get the size to the next block or the end of the file
-        let size: usize = {
-            let ptr = if cur.next_off == 0 {
-                file_end
+        let block_end = if cur.next_off == 0 {
+            file_end
+        } else {
+            unsafe { file_start.add(cur.next_off) }
+        };
+        if cur.is_free {
+            let extra = if *CANARY_ON { CANARY.len() } else { 0 };
+            /* the last block always leaves a free block behind, the others may be taken whole */
+            let overhead = if cur.next_off == 0 {
+                2 * HEADER_SIZE
             } else {
-                unsafe { file_start.add(cur.next_off) }
+                HEADER_SIZE
             };
-            unsafe { size(file_start.add(cur.off), ptr)? }
-        };
+            available = available.max(
+                unsafe { size(file_start.add(cur.off), block_end)? }
+                    .saturating_sub(overhead + extra)
+                    / WORD
+                    * WORD,
+            );
+        }
+
+        // where the block has to start for its data to be aligned. If that isn't the start of `cur`,
+        // `cur` stays behind as a free padding block.
+        let block_off = aligned_block_off(cur.off, align, file_start);
 
         // check if this block fits the allocation
-        // adding HEADER_SIZE because we need space for
-        //  1) the header we are going to write
-        if size > (n + HEADER_SIZE) && cur.is_free {
+        // we need space for
+        //  1) the header of the block itself
+        //  2) the header of the free block we are going to write after the data, unless what is
+        //     left is too small to be worth a block. The last block is always split, the free
+        //     block after it is the one that runs to the end of the file.
+        let block_size = if (unsafe { file_start.add(block_off) } as *const u8) < block_end {
+            unsafe { size(file_start.add(block_off), block_end)? }
+        } else {
+            0
+        };
+        let split = cur.next_off == 0 || block_size >= n + HEADER_SIZE + MIN_SPLIT;
+        let fits = if cur.next_off == 0 {
+            block_size >= n + 2 * HEADER_SIZE
+        } else {
+            block_size >= n + HEADER_SIZE
+        };
+        if fits && cur.is_free {
             // fits and it is free.
-            // let new_bloc_loc = unsafe { cur.data_ptr.add(n) as *mut u8 };
-            let new_block_off = cur.data_off + n;
-
-            unsafe { write_new_block(new_block_off, true, cur.next_off, file_start) }?;
-            unsafe { set_free_flag(cur.off, false, file_start) }?;
-            unsafe { set_next_off(cur.off, new_block_off, file_start) }?;
-            // we wrote a block at the end of our newly allocated memory
-            // we marked this block as not free
+            let next_off = if split {
+                let new_block_off = block_off + DATA_PTR_BYTE_OFF + n;
+                unsafe { write_new_block(new_block_off, true, cur.next_off, file_start) }?;
+                new_block_off
+            } else {
+                cur.next_off /* the slack up to it stays zeroed, `dealoc` gives it back */
+            };
+            let block = unsafe { write_new_block(block_off, false, next_off, file_start) }?;
+            unsafe { set_align(block_off, align, file_start) }?;
+            if *CANARY_ON {
+                unsafe { write_canary(&block, requested, file_start) };
+            }
+            if block_off != cur.off {
+                unsafe { set_next_off(cur.off, block_off, file_start) }?; /* `cur` stays free */
+            }
+            // we wrote a block at the end of our newly allocated memory (if there was room for one)
+            // we wrote this block (at the start of `cur` or after its padding) as not free
             // we wired up this block to point to the new block
-            // we are done, return the data_ptr of the cur block!
-            return Ok(cur.data_off);
+            // we are done, return the data_ptr of the block!
+            return Ok(block.data_off);
         }
 
         // walk the list if we don't find a fitting region
@@ -207,13 +426,16 @@ pub unsafe fn aloc(n: usize, file_start: *mut u8, file_end: *const u8) -> Result
     }
 
     // We are here because we exhausted the list, this means there is no space :(
-    Err(anyhow!(
-        "Insuficent remaining space to allocate {} bytes in file with total size {} bytes",
-        n,
-        unsafe { size(file_start, file_end)? }
-    ))
+    Err(OutOfMemory {
+        requested,
+        available,
+        file_len: unsafe { size(file_start, file_end)? },
+    }
+    .into())
 }
 
+/// # Safety
+/// Like `aloc`, `off` is checked against the blocks before anything is written.
 pub unsafe fn dealoc(off: usize, file_start: *mut u8, file_end: *const u8) -> Result<()> {
     let mut block = unsafe { from_data_off(off, file_start) }?;
     unsafe { set_free_flag(block.off, true, file_start) }?;
@@ -263,6 +485,77 @@ pub unsafe fn dealoc(off: usize, file_start: *mut u8, file_end: *const u8) -> Re
     Ok(())
 }
 
+// Moves every live block towards the start of the file so all free memory ends up in one block at
+// the end. Blocks keep the alignment they were allocated with. Returns every block that moved,
+// whoever holds offsets into those blocks (the client) has to rewrite them.
+/// # Safety
+/// Like `aloc`.
+pub unsafe fn compact(file_start: *mut u8, file_end: *const u8) -> Result<Vec<Moved>> {
+    check_alignment_is_ok(file_start)?;
+
+    let mut remap = Vec::new();
+    let mut write_off = 0; /* where the next live block goes */
+    let mut prev_live: Option<usize> = None;
+    let mut cur_block = Some(unsafe { from_block_off(0, file_start) }?);
+    while let Some(cur) = cur_block {
+        // read the next block before we possibly overwrite its header by moving `cur`
+        cur_block = unsafe { next_from_block(cur.off, file_start)? };
+        if cur.is_free {
+            continue;
+        }
+
+        // a live block is always followed by another, aloc writes one right after the data
+        let len = cur.next_off - cur.off;
+        let mut block_off = aligned_block_off(write_off, cur.align, file_start);
+        if block_off > cur.off {
+            // the gap in front of `cur` is too small for a padding block, so it stays where it is
+            block_off = cur.off;
+        }
+
+        if block_off != cur.off {
+            // the regions may overlap, `copy` is a memmove
+            unsafe { std::ptr::copy(file_start.add(cur.off), file_start.add(block_off), len) };
+            remap.push(Moved {
+                old: cur.data_off,
+                new: block_off + DATA_PTR_BYTE_OFF,
+                len: len - DATA_PTR_BYTE_OFF,
+            });
+        }
+        if block_off != write_off {
+            // zero what is left in front of the block, it is free memory now
+            unsafe { std::ptr::write_bytes(file_start.add(write_off), 0, block_off - write_off) };
+            if block_off - write_off >= HEADER_SIZE {
+                unsafe { write_new_block(write_off, true, block_off, file_start) }?;
+            } else {
+                // too small for a header, the live block in front owns the sliver instead
+                let prev = prev_live.ok_or(anyhow!(
+                    "Block at offset {} has no room for its header",
+                    cur.off
+                ))?;
+                unsafe { set_next_off(prev, block_off, file_start) }?;
+                if *CANARY_ON {
+                    unsafe { grow_canary_slack(prev, block_off - write_off, file_start) };
+                }
+            }
+        }
+        unsafe { set_next_off(block_off, block_off + len, file_start) }?;
+        prev_live = Some(block_off);
+        write_off = block_off + len;
+    }
+
+    // everything behind the live blocks is one free block, zeroed like the rest of free memory
+    unsafe {
+        std::ptr::write_bytes(
+            file_start.add(write_off),
+            0,
+            size(file_start.add(write_off), file_end)?,
+        )
+    };
+    unsafe { write_new_block(write_off, true, 0, file_start) }?;
+
+    Ok(remap)
+}
+
 // fn print_memory(memory: *const u8, offset: usize, n: usize) {
 //     println!("{:?}", unsafe {
 //         std::slice::from_raw_parts(memory.add(offset), n)
@@ -298,3 +591,188 @@ pub unsafe fn dealoc(off: usize, file_start: *mut u8, file_end: *const u8) -> Re
 
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{Layout, alloc_zeroed, dealloc};
+
+    use super::{
+        CANARY, HEADER_SIZE, MIN_SPLIT, Moved, PAGE, WORD, aloc, canary_slack, check_canary,
+        compact, dealoc, from_block_off, from_data_off, init, moved_offset, next_from_block,
+        write_canary,
+    };
+
+    /// A zeroed, page aligned file with the allocator set up in it.
+    struct Arena {
+        start: *mut u8,
+        layout: Layout,
+    }
+    impl Arena {
+        fn new(len: usize) -> Self {
+            let layout = Layout::from_size_align(len, PAGE).unwrap();
+            let start = unsafe { alloc_zeroed(layout) };
+            assert!(!start.is_null());
+            unsafe { init(start) }.unwrap();
+            Self { start, layout }
+        }
+
+        fn addr(&self, off: usize) -> usize {
+            self.start as usize + off
+        }
+
+        fn end(&self) -> *const u8 {
+            unsafe { self.start.add(self.layout.size()) }
+        }
+
+        fn aloc(&self, n: usize, align: usize) -> usize {
+            unsafe { aloc(n, align, self.start, self.end()) }.unwrap()
+        }
+
+        fn dealoc(&self, off: usize) {
+            unsafe { dealoc(off, self.start, self.end()) }.unwrap();
+        }
+
+        fn compact(&self) -> Vec<Moved> {
+            unsafe { compact(self.start, self.end()) }.unwrap()
+        }
+    }
+    impl Drop for Arena {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.start, self.layout) };
+        }
+    }
+
+    #[test]
+    fn compact_moves_offsets_by_their_own_block() {
+        let arena = Arena::new(4 * PAGE);
+        let first = arena.aloc(64, 8);
+        let moved = arena.aloc(64, 8);
+        let aligned = arena.aloc(1024, PAGE); /* on the next page either way, so it stays */
+        arena.dealoc(first);
+
+        let remap = arena.compact();
+        assert_eq!(
+            remap,
+            vec![Moved {
+                old: moved,
+                new: first,
+                len: 64
+            }]
+        );
+        assert_eq!(moved_offset(&remap, moved + 8), first + 8);
+        assert_eq!(
+            moved_offset(&remap, aligned + 8),
+            aligned + 8,
+            "past the moved block but not in it"
+        );
+    }
+
+    #[test]
+    fn aligns_the_data() {
+        let arena = Arena::new(8 * PAGE);
+        arena.aloc(24, WORD); /* so the next block doesn't start aligned */
+        for align in [16, 32, 64, PAGE] {
+            let off = arena.aloc(24, align);
+            assert_eq!(arena.addr(off) % align, 0, "aligned to {}", align);
+        }
+        assert!(unsafe { aloc(8, 48, arena.start, arena.end()) }.is_err());
+        assert!(unsafe { aloc(8, 2 * PAGE, arena.start, arena.end()) }.is_err());
+    }
+
+    #[test]
+    fn splits_a_free_block_only_if_the_rest_can_be_used() {
+        let arena = Arena::new(PAGE);
+        let hole = arena.aloc(64, WORD);
+        let _after = arena.aloc(64, WORD);
+        let _last = arena.aloc(64, WORD);
+        arena.dealoc(hole);
+
+        // Leaves exactly `MIN_SPLIT` behind the allocation, a header and a word.
+        let n = 64 - MIN_SPLIT;
+        assert_eq!(arena.aloc(n, WORD), hole);
+        assert_eq!(arena.aloc(WORD, WORD), hole + n + HEADER_SIZE);
+    }
+
+    #[test]
+    fn keeps_a_remainder_too_small_to_split() {
+        let arena = Arena::new(PAGE);
+        let hole = arena.aloc(64, WORD);
+        let _after = arena.aloc(64, WORD);
+        let last = arena.aloc(64, WORD);
+        arena.dealoc(hole);
+
+        // One word short of a split, the allocation gets the whole hole.
+        let n = 64 - MIN_SPLIT + WORD;
+        assert_eq!(arena.aloc(n, WORD), hole);
+        assert!(arena.aloc(WORD, WORD) > last, "not in the sliver behind it");
+
+        // Freed again, the slack goes back with it.
+        arena.dealoc(hole);
+        assert_eq!(arena.aloc(64, WORD), hole);
+    }
+
+    #[test]
+    fn leaves_no_tiny_free_blocks() {
+        let arena = Arena::new(4 * PAGE);
+        let offs: Vec<_> = (1..40).map(|i| arena.aloc(i * 3, WORD)).collect();
+        for &off in offs.iter().step_by(2) {
+            arena.dealoc(off);
+        }
+        // Sizes that don't fit the holes exactly, so most of them get split.
+        for i in 1..40 {
+            arena.aloc(i * 5 % 64 + 1, WORD);
+        }
+
+        let mut block = Some(unsafe { from_block_off(0, arena.start) }.unwrap());
+        while let Some(cur) = block {
+            if cur.is_free && cur.next_off != 0 {
+                assert!(
+                    cur.next_off - cur.off >= MIN_SPLIT,
+                    "free block at {} is only {} bytes",
+                    cur.off,
+                    cur.next_off - cur.off
+                );
+            }
+            block = unsafe { next_from_block(cur.off, arena.start) }.unwrap();
+        }
+    }
+
+    #[test]
+    fn compact_keeps_blocks_aligned() {
+        let arena = Arena::new(PAGE);
+        let first = arena.aloc(64, WORD);
+        let aligned = arena.aloc(100, 64);
+        unsafe { std::ptr::write_bytes(arena.start.add(aligned), 0xAB, 100) };
+        arena.dealoc(first);
+
+        let remap = arena.compact();
+        let now = moved_offset(&remap, aligned);
+        assert!(now < aligned, "moved towards the start");
+        assert_eq!(arena.addr(now) % 64, 0);
+        let data = unsafe { std::slice::from_raw_parts(arena.start.add(now), 100) };
+        assert!(data.iter().all(|&b| b == 0xAB));
+
+        // All the free memory is behind it, in one piece.
+        let rest = arena.aloc(PAGE - now - 100 - 3 * HEADER_SIZE, WORD);
+        assert!(rest > now);
+    }
+
+    #[test]
+    fn canaries_allocations_bigger_than_a_u16() {
+        let arena = Arena::new(32 * PAGE);
+        let n = u16::MAX as usize + 100;
+        let off = arena.aloc(n + CANARY.len(), WORD);
+        let block = unsafe { from_data_off(off, arena.start) }.unwrap();
+        let canary =
+            || unsafe { std::slice::from_raw_parts(arena.start.add(off + n), CANARY.len()) };
+
+        unsafe { write_canary(&block, n, arena.start) };
+        assert_ne!(unsafe { canary_slack(block.off, arena.start) }, 0);
+        assert_eq!(canary(), CANARY);
+
+        // Written past the end, the check finds the spot again and puts the canary back.
+        unsafe { *arena.start.add(off + n) = 0 };
+        unsafe { check_canary(&block, arena.start) };
+        assert_eq!(canary(), CANARY);
+    }
+}
@@ -1,22 +1,35 @@
-use clap::Parser;
-use cli::Cli;
-use memmap2::MmapMut;
-use process::{handle_sock_msg, spawn_foreign_process};
 use serde_json::json;
-use shm::SemMutex;
-use tracing::Level;
+use tracing::{Level, error, info, warn};
 use tracing_subscriber::FmtSubscriber;
-use ui::start;
+use winit::window::WindowLevel;
+use z71200_runtime::cli::{Cli, ColorSpaceArg, WindowLevelArg};
+use z71200_runtime::process::{
+    Leftovers, PROTOCOL_VERSION, ProcessHandle, create_shm, handle_sock_msg, shm_path, sock_addr,
+    spawn_foreign_process,
+};
+use z71200_runtime::ui::{
+    AppConfig, ColorSpaceOption, Libraries, TreeBuffer, Vdom, Vdoms,
+    command::UiCommandSender,
+    draw::{FiredEvent, VmLimits},
+    dump::read_dump_libraries,
+    gamepad::{GamepadEvent, GamepadEventKind},
+    load_icon,
+    script::{parse_script, run_script},
+    start,
+};
 
-mod cli;
-mod ll_aloc;
-mod process;
-mod shm;
-mod sock;
-mod ui;
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::broadcast::{self, error::RecvError};
 
-use anyhow::Result;
-use std::sync::{Arc, Mutex};
+/// How many events may wait to be sent to the sockets. Past that the oldest are dropped, a client
+/// that is far behind is better off with the latest state than with a backlog of stale hovers.
+const EVENT_QUEUE: usize = 1024;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -30,66 +43,290 @@ async fn main() -> Result<()> {
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
     // Cli:
-    let cli = Cli::parse();
+    let cli = Cli::parse_args();
+    let config = AppConfig {
+        transparent: cli.transparent,
+        decorations: !cli.no_decorations,
+        window_level: match cli.window_level {
+            WindowLevelArg::AlwaysOnBottom => WindowLevel::AlwaysOnBottom,
+            WindowLevelArg::Normal => WindowLevel::Normal,
+            WindowLevelArg::AlwaysOnTop => WindowLevel::AlwaysOnTop,
+        },
+        icon: cli.icon.as_deref().and_then(|path| {
+            load_icon(path)
+                .inspect_err(|err| warn!("Continuing without a window icon. {:#}", err))
+                .ok()
+        }),
+        min_size: cli.min_width.zip(cli.min_height),
+        max_size: cli.max_width.zip(cli.max_height),
+        logical_coordinates: cli.logical_coordinates,
+        msaa: cli.msaa,
+        color_space: match cli.color_space {
+            ColorSpaceArg::Srgb => ColorSpaceOption::Srgb,
+            ColorSpaceArg::DisplayP3 => ColorSpaceOption::DisplayP3,
+        },
+        scroll_speed: cli.scroll_speed,
+        natural_scroll: cli.natural_scroll,
+        reduce_motion: cli.reduce_motion,
+        profile: cli.profile,
+        debug_layout: cli.debug_layout,
+        gamepad: cli.gamepad,
+        vm_limits: VmLimits {
+            max_stack: cli.max_stack_depth,
+            max_registers: cli.max_registers,
+            max_call_depth: cli.max_call_depth,
+        },
+        base_font_size: cli.base_font_size,
+    };
+
+    // Offline: draw a tree from a file, nothing is launched and there is no client to talk to.
+    if let Some(path) = cli.tree.as_deref() {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Couldn't read the tree file {}", path.display()))?;
+        let tree = TreeBuffer::from_bytes(&bytes, cli.root)?;
+        let vdoms: Vdoms = Arc::new(Mutex::new(Vdom::new(cli.root, tree)));
+        /* a dump made with `dump_state` has the libraries it uses next to it */
+        let libraries: Libraries =
+            Arc::new(Mutex::new(read_dump_libraries(path)?.unwrap_or_default()));
+
+        // Headless, the frames go to stdout as JSON lines instead of to a window.
+        if let Some(script) = cli.script.as_deref() {
+            let text = std::fs::read_to_string(script)
+                .with_context(|| format!("Couldn't read the script {}", script.display()))?;
+            let steps = parse_script(&text)?;
+            return run_script(
+                800,
+                450,
+                &config,
+                vdoms,
+                libraries,
+                &steps,
+                |event: FiredEvent| println!("{}", event_message(event)),
+                |frame| println!("{}", frame),
+            );
+        }
+
+        let (_tx_refresh, rx_refresh) = tokio::sync::mpsc::channel(1);
+        let (_tx_ui, rx_ui) = tokio::sync::mpsc::unbounded_channel();
+        start(
+            800,
+            450,
+            "z71200-runtime",
+            config,
+            vdoms,
+            libraries,
+            |event: FiredEvent| info!("Fired {:?}", event),
+            rx_refresh,
+            rx_ui,
+        );
+        return Ok(());
+    }
 
     // Main:
-    let vdoms: Arc<Mutex<(Option<usize>, Option<Arc<SemMutex<MmapMut>>>)>> =
-        Arc::new(Mutex::new((None, None)));
+    let vdoms: Vdoms = Arc::new(Mutex::new(Vdom::default()));
+    let libraries: Libraries = Arc::new(Mutex::new(HashMap::new()));
     let (tx_refresh, rx_refresh) = tokio::sync::mpsc::channel(1);
-    let (tx_broadcast, mut rx_broadcast) = tokio::sync::mpsc::channel::<String>(1);
+    let (tx_broadcast, mut rx_broadcast) = broadcast::channel::<String>(EVENT_QUEUE);
+    let (tx_ui, rx_ui) = tokio::sync::mpsc::unbounded_channel();
+    let ui_sender = UiCommandSender::new(tx_ui, tx_refresh.clone());
+
+    // Every programme gets its own socket, they all share the one file and window.
+    let commands = cli.commands();
+    let sock_addrs = (0..commands.len())
+        .map(|i| sock_addr(cli.listen.as_deref(), i))
+        .collect::<Result<Vec<_>>>()?;
+
+    // A Ctrl+C or a `kill` would end the process without unwinding, the shared memory and the
+    // socket files have to be removed by hand first and the programmes killed, which dropping
+    // their handles does.
+    let leftovers = Leftovers {
+        shm_path: shm_path(),
+        sock_addrs: sock_addrs.clone(),
+    };
+    let leftovers_1 = leftovers.clone();
+    let children: Arc<Mutex<Vec<ProcessHandle>>> = Arc::new(Mutex::new(Vec::new()));
+    let children_1 = children.clone();
+    tokio::task::spawn(async move {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(err) => {
+                warn!("Not cleaning up on SIGTERM. {:#}", err);
+                return;
+            }
+        };
+        let code = tokio::select! {
+            _ = tokio::signal::ctrl_c() => 130,
+            _ = terminate.recv() => 143,
+        };
+        info!("Interrupted, stopping the programmes and removing the shared memory and socket.");
+        children_1.lock().unwrap().clear();
+        leftovers_1.remove();
+        std::process::exit(code);
+    });
+
+    let heartbeat =
+        (cli.heartbeat_interval > 0).then(|| Duration::from_secs(cli.heartbeat_interval));
 
     let vdoms_1 = vdoms.clone();
+    let libraries_1 = libraries.clone();
+    let vm_limits = config.vm_limits;
+    let max_alloc = cli.max_alloc;
     let foreign_process_task = tokio::task::spawn(async move {
-        let handle = spawn_foreign_process(&cli.command).unwrap();
-        let shm_guard = handle.shm_guard.clone();
-        let sock_guard = handle.sock_guard.clone();
-        let mut sock_guard_1 = sock_guard.clone();
+        let shm_guard = create_shm(cli.blocking_sem).unwrap();
+        let handles = commands
+            .iter()
+            .zip(&sock_addrs)
+            .enumerate()
+            .map(|(i, (run, sock_addr))| spawn_foreign_process(run, i, sock_addr, &shm_path()))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let mut sock_guards = handles
+            .iter()
+            .map(|handle| handle.sock_guard.clone())
+            .collect::<Vec<_>>();
+        children.lock().unwrap().extend(handles);
 
-        let shm_guard_1 = shm_guard.clone();
-        let vdoms_1 = vdoms_1.clone();
-        let vdoms_2 = vdoms_1.clone();
-        tokio::task::spawn(async move {
-            sock_guard
-                .start(
-                    move |msg| handle_sock_msg(&shm_guard_1, &vdoms_1, msg),
-                    move || {
-                        /*let tx_quit_1 = tx_quit_1.clone();
-                        async move { tx_quit_1.send(()).await.unwrap() } */
-                        async {}
-                    },
-                )
-                .await;
-        });
+        // The messages of all sockets are handled alike, on the same arena and tree.
+        for sock_guard in sock_guards.clone() {
+            let shm_guard_1 = shm_guard.clone();
+            let vdoms_1 = vdoms_1.clone();
+            let libraries_1 = libraries_1.clone();
+            let ui_sender = ui_sender.clone();
+            tokio::task::spawn(async move {
+                sock_guard
+                    .start(
+                        move |msg| {
+                            handle_sock_msg(
+                                &shm_guard_1,
+                                &vdoms_1,
+                                &libraries_1,
+                                vm_limits,
+                                max_alloc,
+                                &ui_sender,
+                                msg,
+                            )
+                        },
+                        move || {
+                            /*let tx_quit_1 = tx_quit_1.clone();
+                            async move { tx_quit_1.send(()).await.unwrap() } */
+                            async {}
+                        },
+                        heartbeat,
+                    )
+                    .await;
+            });
+        }
 
+        let vdoms_2 = vdoms_1.clone();
         loop {
             tokio::select! {
-                data = rx_broadcast.recv() => {
-                    if let Some(data) = data{
-                        sock_guard_1.broadcast(&data).expect("Failed to broadcast -- unrecovrable.");
-                    } else {/* rx channel closed; socket handled through tx_quit in sock_guard already. */}
+                data = rx_broadcast.recv() => match data {
+                    Ok(data) => {
+                        /* every programme hears every event */
+                        for sock_guard in sock_guards.iter_mut() {
+                            sock_guard.broadcast(&data).expect("Failed to broadcast -- unrecovrable.");
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Events came in faster than they were sent, dropped the {} oldest.", n);
+                    }
+                    Err(RecvError::Closed) => {/* the window is gone; socket handled through tx_quit in sock_guard already. */}
                 },
                 mtx = shm_guard.recv() => { /* sem_ready was triggered */
-                    vdoms_2.lock().unwrap().1 = Some(mtx);
+                    if let Err(err) = shm_guard.apply_patches() {
+                        error!("Dropped queued patches. {:#}", err);
+                    }
+                    vdoms_2.lock().unwrap().shm_written(mtx);
                     tx_refresh.send(()).await.expect("Failed to refresh screen -- channel failed.");
                 }
             }
         }
     });
 
-    let handler = move |id: usize| {
-        let tx_broadcast = tx_broadcast.clone();
-        tokio::task::spawn(async move {
-            tx_broadcast
-                .send(
-                    serde_json::to_string(&json!({"kind": "event", "evt_id": id}))
-                        .expect("Couldn't serialise message."),
-                )
-                .await
-                .expect("Failed to broadcast over channel.");
-        });
+    // Runs on the UI thread in the middle of a frame, so it must never wait for the sockets. The
+    // queue drops its oldest events once it is full instead, see `EVENT_QUEUE`.
+    let handler = move |event: FiredEvent| {
+        let msg =
+            serde_json::to_string(&event_message(event)).expect("Couldn't serialise message.");
+        let _ = tx_broadcast.send(msg); /* only fails once the socket side is gone */
     };
 
-    start(800, 450, "z71200-runtime", vdoms, handler, rx_refresh);
+    start(
+        800,
+        450,
+        "z71200-runtime",
+        config,
+        vdoms,
+        libraries,
+        handler,
+        rx_refresh,
+        rx_ui,
+    );
     foreign_process_task.abort();
+    leftovers.remove();
     Ok(())
 }
+
+/// The message the client gets for `event`.
+fn event_message(event: FiredEvent) -> serde_json::Value {
+    match event {
+        FiredEvent::Id(id) => json!({"kind": "event", "evt_id": id}),
+        FiredEvent::Named(name) => json!({"kind": "event", "name": name}),
+        FiredEvent::Scroll { id, scroll_y } => {
+            json!({"kind": "event", "evt_id": id, "scroll_y": scroll_y})
+        }
+        FiredEvent::Viewport(viewport) => json!({
+            "kind": "viewport",
+            "scroll_y": viewport.scroll_y,
+            "height": viewport.height,
+        }),
+        FiredEvent::Focus(focused) => json!({"kind": "focus", "focused": focused}),
+        FiredEvent::FileHover(Some(path)) => {
+            json!({"kind": "file_hover", "path": path.to_string_lossy()})
+        }
+        FiredEvent::FileHover(None) => json!({"kind": "file_hover_cancelled"}),
+        FiredEvent::FileDrop(path) => {
+            json!({"kind": "file_drop", "path": path.to_string_lossy()})
+        }
+        FiredEvent::Gamepad(GamepadEvent { gamepad, kind }) => match kind {
+            GamepadEventKind::Connected => {
+                json!({"kind": "gamepad", "gamepad": gamepad, "event": "connected"})
+            }
+            GamepadEventKind::Disconnected => {
+                json!({"kind": "gamepad", "gamepad": gamepad, "event": "disconnected"})
+            }
+            GamepadEventKind::Pressed(button) => {
+                json!({"kind": "gamepad", "gamepad": gamepad, "event": "pressed", "button": button})
+            }
+            GamepadEventKind::Released(button) => {
+                json!({"kind": "gamepad", "gamepad": gamepad, "event": "released", "button": button})
+            }
+            GamepadEventKind::Axis { axis, value } => json!({
+                "kind": "gamepad",
+                "gamepad": gamepad,
+                "event": "axis",
+                "axis": axis,
+                "value": value,
+            }),
+        },
+        FiredEvent::RenderError { message, trace } => json!({
+            "kind": "render_error",
+            "message": message,
+            "pass": trace.as_ref().map(|trace| trace.pass),
+            "trace": trace.map(|trace| {
+                trace
+                    .words
+                    .iter()
+                    .map(|(tag, word)| json!({"tag": tag.to_string(), "word": word}))
+                    .collect::<Vec<_>>()
+            }),
+        }),
+        FiredEvent::Ready { width, height } => json!({
+            "kind": "ready",
+            "protocol_version": PROTOCOL_VERSION,
+            "shm": shm_path(),
+            "width": width,
+            "height": height,
+        }),
+    }
+}
@@ -1,25 +1,53 @@
 use anyhow::Result;
 use anyhow::anyhow;
+use base64::Engine;
 use libc::getppid;
-use memmap2::MmapMut;
 use serde_json::json;
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::{io::BufRead, process::Stdio};
+use skia_safe::FontMgr;
+use std::{io::BufRead, process::Stdio, time::Duration};
 use tracing::{Level, error, info, span};
+use winit::window::{CursorGrabMode, CursorIcon};
 
 use crate::ll_aloc;
 use crate::shm::DATA_OFF;
 use crate::shm::LEN;
-use crate::shm::SemMutex;
-use crate::{shm::SHMHandle, sock::SockHandle};
+use crate::shm::unlink_shm;
+use crate::ui::command::{FullscreenMode, UiCommand, UiCommandSender};
+use crate::ui::draw::{ElementId, VmLimits, validate};
+use crate::ui::{Libraries, TreeBuffer, Vdoms};
+use crate::{
+    shm::SHMHandle,
+    sock::{SockAddr, SockHandle},
+};
 
 pub const PROTOCOL_VERSION: usize = 1;
 
+/* how long "dump_state" waits for the window, which only takes commands once it is open */
+const DUMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request for more memory than the runtime allows in one piece, an "aloc" over
+/// `--max-alloc` or a "tree" bigger than the shared file's data region. Kept apart from other
+/// errors so the client is told the ceiling.
+#[derive(Debug, Clone, Copy)]
+pub struct TooLarge {
+    pub what: &'static str,
+    pub requested: usize,
+    pub limit: usize,
+}
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes is over the limit of {} bytes",
+            self.what, self.requested, self.limit
+        )
+    }
+}
+impl std::error::Error for TooLarge {}
+
 #[derive(Debug)]
 pub struct ProcessHandle {
     pub child: std::process::Child,
-    pub shm_guard: SHMHandle,
     pub sock_guard: SockHandle,
 }
 impl Drop for ProcessHandle {
@@ -38,14 +66,60 @@ impl Drop for ProcessHandle {
     }
 }
 
-pub fn spawn_foreign_process(run: &Vec<String>) -> Result<ProcessHandle> {
+/// Name of the shared memory object, handed to the client as `z71200_SHM`.
+pub fn shm_path() -> String {
+    let pid: i32 = unsafe { getppid() };
+    format!("/z71200_shm_{}", pid)
+}
+
+/// Where the `index`th programme connects. The first gets `--listen` if given, otherwise and
+/// for the others it's a unix socket named after the pid.
+pub fn sock_addr(listen: Option<&str>, index: usize) -> Result<SockAddr> {
     let pid: i32 = unsafe { getppid() };
+    Ok(match (listen, index) {
+        (Some(listen), 0) => SockAddr::parse(listen)?,
+        (_, 0) => SockAddr::Unix(format!("/tmp/z71200_sock_{}", pid)),
+        (_, index) => SockAddr::Unix(format!("/tmp/z71200_sock_{}_{}", pid, index)),
+    })
+}
+
+/// What a run leaves on the system outside the process: the shared memory object with its
+/// semaphores and the socket files. Nothing of it goes away on its own, so it's removed on the
+/// way out, also when the runtime is stopped by a signal.
+#[derive(Debug, Clone)]
+pub struct Leftovers {
+    pub shm_path: String,
+    pub sock_addrs: Vec<SockAddr>,
+}
+impl Leftovers {
+    /// Safe to call more than once and before anything was created.
+    pub fn remove(&self) {
+        unlink_shm(&self.shm_path);
+        for sock_addr in &self.sock_addrs {
+            sock_addr.remove_socket_file();
+        }
+    }
+}
+
+/// The mmaped file every programme writes its layout into.
+pub fn create_shm(blocking_sem: bool) -> Result<SHMHandle> {
+    let shm_guard = SHMHandle::new(&shm_path());
+    if blocking_sem {
+        return shm_guard.with_blocking_wait();
+    }
+    Ok(shm_guard)
+}
 
-    // Create the socket and mmaped file
-    let socket_path = format!("/tmp/z71200_sock_{}", pid);
-    let shm_path = format!("/z71200_shm_{}", pid);
-    let sock_guard = SockHandle::new(&socket_path)?;
-    let shm_guard = SHMHandle::new(&shm_path);
+/// Launches `run` with the shared file at `shm_path` and a socket of its own at `sock_addr`.
+/// `index` tells its output apart from the other programmes'.
+pub fn spawn_foreign_process(
+    run: &Vec<String>,
+    index: usize,
+    sock_addr: &SockAddr,
+    shm_path: &str,
+) -> Result<ProcessHandle> {
+    // Create the socket
+    let sock_guard = SockHandle::new(sock_addr)?;
 
     // Spawn the programme
     let mut cmd = std::process::Command::new(
@@ -58,15 +132,15 @@ pub fn spawn_foreign_process(run: &Vec<String>) -> Result<ProcessHandle> {
 
     let mut child = cmd
         .env("z71200_PROTOCOL_VERSION", format!("{}", PROTOCOL_VERSION))
-        .env("z71200_SHM", &shm_path)
-        .env("z71200_SEM_READY", format!("{}_sem_ready", &shm_path))
-        .env("z71200_SEM_LOCK", format!("{}_sem_lock", &shm_path))
-        .env("z71200_SOCK", &socket_path)
+        .env("z71200_SHM", shm_path)
+        .env("z71200_SEM_READY", format!("{}_sem_ready", shm_path))
+        .env("z71200_SEM_LOCK", format!("{}_sem_lock", shm_path))
+        .env("z71200_SOCK", sock_addr.to_env())
         .stdout(Stdio::piped()) // Capture stdout
         .stderr(Stdio::piped())
         .spawn()?;
 
-    let span = span!(Level::INFO, "(foreign process)",);
+    let span = span!(Level::INFO, "(foreign process)", index);
     let _guard = span.enter();
 
     // Three threads, one for checking if the programme has exited, one for stdout and one for stderr
@@ -102,18 +176,380 @@ pub fn spawn_foreign_process(run: &Vec<String>) -> Result<ProcessHandle> {
         }
     });
 
-    Ok(ProcessHandle {
-        child,
-        shm_guard,
-        sock_guard,
-    })
+    Ok(ProcessHandle { child, sock_guard })
+}
+
+/// The `fn` and `args` of an ask, `what` names the message in the errors.
+fn ask_call<'m>(
+    msg: &'m serde_json::Map<String, serde_json::Value>,
+    what: &str,
+) -> Result<(&'m str, &'m serde_json::Map<String, serde_json::Value>)> {
+    let fn_name = msg.get("fn").and_then(|x| x.as_str()).ok_or(anyhow!(
+        "Expected {} to have stringy key 'fn' and map key 'args'. Missing 'fn'.",
+        what
+    ))?;
+    let args = msg.get("args").and_then(|x| x.as_object()).ok_or(anyhow!(
+        "Expected {} to have stringy key 'fn' and map key 'args'. Missing 'args'.",
+        what
+    ))?;
+    Ok((fn_name, args))
+}
+
+/// Checks a root for `set_root` and `register_root`. Caught here, the client gets the error for
+/// the call that was wrong rather than an error frame later on.
+fn check_root(ptr: usize) -> Result<()> {
+    if ptr % ll_aloc::WORD != 0 {
+        return Err(anyhow!(
+            "Root 0x{:x} is misaligned, it must be a multiple of {} to point at a tagged word",
+            ptr,
+            ll_aloc::WORD
+        ));
+    }
+    if ptr
+        .checked_add(2 * ll_aloc::WORD)
+        .is_none_or(|end| end > LEN - DATA_OFF)
+    {
+        return Err(anyhow!(
+            "Root 0x{:x} is past the end of the {} byte shared file",
+            ptr,
+            LEN - DATA_OFF
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the function of an ask, the value is what goes in the answer's "return".
+fn handle_ask(
+    shm_handle: &SHMHandle,
+    vdoms: &Vdoms,
+    libraries: &Libraries,
+    vm_limits: VmLimits,
+    max_alloc: Option<usize>,
+    ui: &UiCommandSender,
+    fn_name: &str,
+    args: &serde_json::Map<String, serde_json::Value>,
+) -> Result<serde_json::Value> {
+    match fn_name {
+        "aloc" => {
+            let n = args.get("n").and_then(|x| x.as_u64()).ok_or(anyhow!(
+                "Function 'aloc' expects one parameter 'n : int' -- the number of bytes to alocate"
+            ))?;
+            let align = match args.get("align") {
+                None | Some(serde_json::Value::Null) => ll_aloc::WORD,
+                Some(align) => align.as_u64().ok_or(anyhow!("Function 'aloc' expects the optional parameter 'align : int' -- a power of two the returned ptr is aligned to"))? as usize,
+            };
+
+            if let Some(limit) = max_alloc.filter(|&limit| n as usize > limit) {
+                return Err(TooLarge {
+                    what: "An allocation",
+                    requested: n as usize,
+                    limit,
+                }
+                .into());
+            }
+
+            let mtx = shm_handle.shm_file.clone();
+            let mut file = mtx.lock()?;
+
+            let file_start = unsafe { file.data.as_mut_ptr().add(DATA_OFF) };
+            let file_end = unsafe { file.data.as_ptr().add(LEN) };
+            let out_ptr = unsafe { ll_aloc::aloc(n as usize, align, file_start, file_end) }?;
+
+            Ok(json!(out_ptr))
+        }
+        "dealoc" => {
+            let ptr = args.get("ptr").and_then(|x| x.as_u64()).ok_or(anyhow!(
+                "Function 'dealoc' expects one parameter 'ptr : int' -- offset where to free memory"
+            ))?;
+
+            let mtx = shm_handle.shm_file.clone();
+            let mut file = mtx.lock()?;
+
+            let file_start = unsafe { file.data.as_mut_ptr().add(DATA_OFF) };
+            let file_end = unsafe { file.data.as_ptr().add(LEN) };
+            unsafe { ll_aloc::dealoc(ptr as usize, file_start, file_end) }?;
+
+            Ok(json!(null))
+        }
+        "compact" => {
+            let remap = {
+                let mtx = shm_handle.shm_file.clone();
+                let mut file = mtx.lock()?;
+
+                let file_start = unsafe { file.data.as_mut_ptr().add(DATA_OFF) };
+                let file_end = unsafe { file.data.as_ptr().add(LEN) };
+                unsafe { ll_aloc::compact(file_start, file_end) }?
+            }; /* drop the shm lock first, drawing takes the vdoms lock before it */
+
+            // The roots are the only offsets the runtime holds itself, so move them along.
+            // Offsets into the middle of a block (ie. the root in a bigger allocation) keep their place in it.
+            let move_along = |root: &mut usize| *root = ll_aloc::moved_offset(&remap, *root);
+            let mut lock = vdoms.lock().unwrap();
+            if let Some(root) = lock.shm_root_mut() {
+                move_along(root);
+            }
+            lock.named_roots_mut().for_each(move_along);
+            drop(lock);
+            ui.request_redraw(); /* the tree moved, a settled frame can't be shown again */
+
+            Ok(json!(
+                remap.iter().map(|m| (m.old, m.new)).collect::<Vec<_>>()
+            ))
+        }
+        "set_root" => {
+            let ptr = args.get("ptr").and_then(|x| x.as_u64()).ok_or(anyhow!("Function 'set_root' expects one parameter 'ptr : int' -- offset where the layout begins"))? as usize;
+            check_root(ptr)?;
+            let mut lock = vdoms.lock().unwrap();
+            lock.set_shm_root(ptr, shm_handle.shm_file.clone());
+            drop(lock);
+            ui.request_redraw();
+            Ok(json!(null))
+        }
+        "register_root" => {
+            let name = args.get("name").and_then(|x| x.as_str()).ok_or(anyhow!("Function 'register_root' expects two parameters 'name : str' -- what to call the root, and 'ptr : int' -- offset where its layout begins"))?;
+            let ptr = args.get("ptr").and_then(|x| x.as_u64()).ok_or(anyhow!("Function 'register_root' expects two parameters 'name : str' -- what to call the root, and 'ptr : int' -- offset where its layout begins"))? as usize;
+            check_root(ptr)?;
+            vdoms.lock().unwrap().register_root(name, ptr);
+            Ok(json!(null))
+        }
+        "set_root_named" => {
+            let name = args.get("name").and_then(|x| x.as_str()).ok_or(anyhow!("Function 'set_root_named' expects one parameter 'name : str' -- a root given to 'register_root'"))?;
+            let mut lock = vdoms.lock().unwrap();
+            let ptr = lock
+                .named_root(name)
+                .ok_or(anyhow!("No root is registered as '{}'", name))?;
+            lock.set_shm_root(ptr, shm_handle.shm_file.clone());
+            drop(lock);
+            ui.request_redraw();
+            Ok(json!(null))
+        }
+        "validate_tree" => {
+            let ptr = args.get("ptr").and_then(|x| x.as_u64()).ok_or(anyhow!("Function 'validate_tree' expects one parameter 'ptr : int' -- offset where the layout begins"))? as usize;
+
+            let library = libraries.lock().unwrap(); /* before the shm, like drawing does */
+            let mtx = shm_handle.shm_file.clone();
+            let file = mtx.lock()?;
+
+            let file_start = unsafe { file.data.as_ptr().add(DATA_OFF) };
+            let file_end = unsafe { file.data.as_ptr().add(LEN) };
+            let result = match unsafe { validate(ptr, file_start, file_end, &library, vm_limits) } {
+                Ok(()) => json!({"ok": true}),
+                Err((err, at_offset)) => {
+                    json!({"ok": false, "error": format!("{:#}", err), "at_offset": at_offset})
+                }
+            };
+            Ok(json!(result))
+        }
+        "register_library" => {
+            let expects = "Function 'register_library' expects three parameters 'id : int', 'ptr : int', and 'len : int' -- the library id, and the offset and length in bytes of its body";
+            let id = args
+                .get("id")
+                .and_then(|x| x.as_u64())
+                .ok_or(anyhow!(expects))? as usize;
+            let ptr = args
+                .get("ptr")
+                .and_then(|x| x.as_u64())
+                .ok_or(anyhow!(expects))? as usize;
+            let len = args
+                .get("len")
+                .and_then(|x| x.as_u64())
+                .ok_or(anyhow!(expects))? as usize;
+            if ptr % ll_aloc::WORD != 0 || len % ll_aloc::WORD != 0 {
+                return Err(anyhow!(
+                    "Library body at 0x{:x} with {} bytes isn't made of whole tagged words, both must be multiples of {}",
+                    ptr,
+                    len,
+                    ll_aloc::WORD
+                ));
+            }
+
+            // Copied out so the client can free or reuse the memory, and so the body stays
+            // word aligned for the interpreter.
+            let code = {
+                let mtx = shm_handle.shm_file.clone();
+                let file = mtx.lock()?;
+
+                let data = &file.data[DATA_OFF..LEN];
+                let body = ptr
+                    .checked_add(len)
+                    .and_then(|end| data.get(ptr..end))
+                    .ok_or(anyhow!(
+                        "Library body at 0x{:x} with {} bytes is past the end of the shared file",
+                        ptr,
+                        len
+                    ))?;
+                body.chunks_exact(ll_aloc::WORD)
+                    .map(|word| usize::from_ne_bytes(word.try_into().unwrap()))
+                    .collect::<Vec<usize>>()
+            }; /* drop the shm lock first, like "compact" */
+
+            libraries.lock().unwrap().insert(id, code);
+            ui.request_redraw();
+            Ok(json!(null))
+        }
+        "set_fullscreen" => {
+            let mode = args.get("mode").and_then(|x| x.as_str()).ok_or(anyhow!("Function 'set_fullscreen' expects one parameter 'mode : str' -- one of 'borderless', 'exclusive', or 'off'"))?;
+            let mode = match mode {
+                "borderless" => FullscreenMode::Borderless,
+                "exclusive" => FullscreenMode::Exclusive,
+                "off" => FullscreenMode::Off,
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown fullscreen mode '{}', support one of: ['borderless', 'exclusive', 'off']",
+                        mode
+                    ));
+                }
+            };
+            ui.send(UiCommand::SetFullscreen(mode))?;
+            Ok(json!(null))
+        }
+        "set_cursor_visible" => {
+            let visible = args
+                .get("visible")
+                .and_then(|x| x.as_bool())
+                .ok_or(anyhow!(
+                    "Function 'set_cursor_visible' expects one parameter 'visible : bool'"
+                ))?;
+            ui.send(UiCommand::SetCursorVisible(visible))?;
+            Ok(json!(null))
+        }
+        "set_cursor_grab" => {
+            let mode = args.get("mode").and_then(|x| x.as_str()).ok_or(anyhow!("Function 'set_cursor_grab' expects one parameter 'mode : str' -- one of 'none', 'confined', or 'locked'"))?;
+            let mode = match mode {
+                "none" => CursorGrabMode::None,
+                "confined" => CursorGrabMode::Confined,
+                "locked" => CursorGrabMode::Locked,
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown cursor grab mode '{}', support one of: ['none', 'confined', 'locked']",
+                        mode
+                    ));
+                }
+            };
+            ui.send(UiCommand::SetCursorGrab(mode))?;
+            Ok(json!(null))
+        }
+        "set_window_constraints" => {
+            // Every parameter is optional; a missing width/height pair lifts that constraint.
+            let size_pair = |w_key: &str, h_key: &str| -> Result<Option<(u32, u32)>> {
+                let w = args.get(w_key).and_then(|x| x.as_u64());
+                let h = args.get(h_key).and_then(|x| x.as_u64());
+                match (w, h) {
+                    (Some(w), Some(h)) => Ok(Some((w.try_into()?, h.try_into()?))),
+                    (None, None) => Ok(None),
+                    _ => Err(anyhow!(
+                        "Function 'set_window_constraints' expects '{}' and '{}' to be given together",
+                        w_key,
+                        h_key
+                    )),
+                }
+            };
+            let min_size = size_pair("min_width", "min_height")?;
+            let max_size = size_pair("max_width", "max_height")?;
+            ui.send(UiCommand::SetWindowConstraints { min_size, max_size })?;
+            Ok(json!(null))
+        }
+        "set_title" => {
+            let title = args.get("title").and_then(|x| x.as_str()).ok_or(anyhow!(
+                "Function 'set_title' expects one parameter 'title : str' -- the new window title"
+            ))?;
+            ui.send(UiCommand::SetTitle(title.to_owned()))?;
+            Ok(json!(null))
+        }
+        "set_cursor" => {
+            // `null` hands the cursor back to the elements under the pointer.
+            let cursor = match args.get("cursor") {
+                None | Some(serde_json::Value::Null) => None,
+                Some(cursor) => {
+                    let cursor = cursor.as_str().ok_or(anyhow!("Function 'set_cursor' expects one parameter 'cursor : str | null' -- the name of the cursor, or null to let the elements decide again"))?;
+                    Some(match cursor {
+                        "default" => CursorIcon::Default,
+                        "pointer" => CursorIcon::Pointer,
+                        "text" => CursorIcon::Text,
+                        "wait" => CursorIcon::Wait,
+                        "progress" => CursorIcon::Progress,
+                        "crosshair" => CursorIcon::Crosshair,
+                        "move" => CursorIcon::Move,
+                        "grab" => CursorIcon::Grab,
+                        "grabbing" => CursorIcon::Grabbing,
+                        "not-allowed" => CursorIcon::NotAllowed,
+                        "help" => CursorIcon::Help,
+                        _ => {
+                            return Err(anyhow!(
+                                "Unknown cursor '{}', support one of: ['default', 'pointer', 'text', 'wait', 'progress', 'crosshair', 'move', 'grab', 'grabbing', 'not-allowed', 'help']",
+                                cursor
+                            ));
+                        }
+                    })
+                }
+            };
+            ui.send(UiCommand::SetCursor(cursor))?;
+            Ok(json!(null))
+        }
+        "list_fonts" => {
+            /* the same font manager `draw_text` looks families up in */
+            let font_mgr = FontMgr::new();
+            let mut families = (0..font_mgr.count_families())
+                .map(|i| font_mgr.family_name(i))
+                .collect::<Vec<String>>();
+            families.sort();
+            families.dedup();
+            Ok(json!(families))
+        }
+        "dump_state" => {
+            // Written by the event loop, which has the frame state, once it wakes up. The answer
+            // waits for it, so the files are there when the client hears back or it gets the error.
+            let path = args.get("path").and_then(|x| x.as_str()).ok_or(anyhow!("Function 'dump_state' expects one parameter 'path : str' -- where to write the tree, the rest goes next to it in '<path>.json'"))?;
+            let (tx, rx) = std::sync::mpsc::channel();
+            ui.send(UiCommand::DumpState(path.into(), tx))?;
+            rx.recv_timeout(DUMP_TIMEOUT).map_err(|_| {
+                anyhow!(
+                    "The window didn't write the dump within {} seconds",
+                    DUMP_TIMEOUT.as_secs()
+                )
+            })??;
+            Ok(json!(null))
+        }
+        "hit_test" => {
+            let coord = |name: &str| {
+                args.get(name).and_then(|x| x.as_f64()).ok_or(anyhow!("Function 'hit_test' expects two parameters 'x : float' and 'y : float' -- a point in the same pixels as \"Pxs\""))
+            };
+            let (x, y) = (coord("x")?, coord("y")?);
+            let hits = vdoms.lock().unwrap().hit_test(x as f32, y as f32);
+            let hits = hits
+                .iter()
+                .map(|hit| {
+                    let mut obj = match hit.element {
+                        ElementId::At(at) => json!({"at": at}),
+                        ElementId::Key(key) => json!({"key": key}),
+                    };
+                    obj["x"] = json!(hit.bounds.left);
+                    obj["y"] = json!(hit.bounds.top);
+                    obj["width"] = json!(hit.bounds.width());
+                    obj["height"] = json!(hit.bounds.height());
+                    obj
+                })
+                .collect::<Vec<_>>();
+            Ok(json!(hits))
+        }
+        _ => {
+            return Err(anyhow!(
+                "Unknown 'fn' in message with kind 'ask', found {}",
+                fn_name
+            ));
+        }
+    }
 }
 
 fn handle_sock_msg_falliable(
     shm_handle: &SHMHandle,
-    vdoms: &Arc<Mutex<(Option<usize>, Option<Arc<SemMutex<MmapMut>>>)>>,
+    vdoms: &Vdoms,
+    libraries: &Libraries,
+    vm_limits: VmLimits,
+    max_alloc: Option<usize>,
+    ui: &UiCommandSender,
     msg: serde_json::Map<String, serde_json::Value>,
-) -> Result<Option<String>> {
+) -> Result<Option<serde_json::Value>> {
     /* {kind: 'ask', fn: 'foo', args: {...}} */
     let kind = msg
         .get("kind")
@@ -122,64 +558,116 @@ fn handle_sock_msg_falliable(
 
     match kind {
         "ask" => {
-            let fn_name = msg.get("fn").and_then(|x| x.as_str()).ok_or(anyhow!("Expected message of kind 'ask' to have stringy key 'fn' and map key 'args'. Missing 'fn'."))?;
-            let args = msg.get("args").and_then(|x| x.as_object()).ok_or(anyhow!("Expected message of kind 'ask' to have stringy key 'fn' and map key 'args'. Missing 'args'."))?;
-            match fn_name {
-                "aloc" => {
-                    let n = args.get("n").and_then(|x| x.as_u64()).ok_or(anyhow!("Function 'aloc' expects one parameter 'n : int' -- the number of bytes to alocate"))?;
-
-                    let mtx = shm_handle.shm_file.clone();
-                    let mut file = mtx.lock()?;
-
-                    let file_start = unsafe { file.data.as_mut_ptr().add(DATA_OFF) };
-                    let file_end = unsafe { file.data.as_ptr().add(LEN) };
-                    let out_ptr = unsafe { ll_aloc::aloc(n as usize, file_start, file_end) }?;
-
-                    Ok(Some(serde_json::to_string(
-                        &json!({"kind": "return", "return": out_ptr }),
-                    )?))
-                }
-                "dealoc" => {
-                    let ptr = args.get("ptr").and_then(|x| x.as_u64()).ok_or(anyhow!("Function 'dealoc' expects one parameter 'ptr : int' -- offset where to free memory"))?;
-
-                    let mtx = shm_handle.shm_file.clone();
-                    let mut file = mtx.lock()?;
-
-                    let file_start = unsafe { file.data.as_mut_ptr().add(DATA_OFF) };
-                    let file_end = unsafe { file.data.as_ptr().add(LEN) };
-                    unsafe { ll_aloc::dealoc(ptr as usize, file_start, file_end) }?;
-
-                    Ok(Some(serde_json::to_string(
-                        &json!({"kind": "return", "return": null }),
-                    )?))
-                }
-                "set_root" => {
-                    let ptr = args.get("ptr").and_then(|x| x.as_u64()).ok_or(anyhow!("Function 'set_root' expects one parameter 'ptr : int' -- offset where the layout begins"))?;
-                    let mut lock = vdoms.lock().unwrap();
-                    lock.0 = Some(ptr as usize);
-                    Ok(Some(serde_json::to_string(
-                        &json!({"kind": "return", "return": null }),
-                    )?))
+            let (fn_name, args) = ask_call(&msg, "message of kind 'ask'")?;
+            let out = handle_ask(
+                shm_handle, vdoms, libraries, vm_limits, max_alloc, ui, fn_name, args,
+            )?;
+            Ok(Some(json!({"kind": "return", "return": out })))
+        }
+        "batch" => {
+            /* {kind: 'batch', calls: [{fn: 'foo', args: {...}}, ...]} -- many asks, one round trip */
+            let calls = msg.get("calls").and_then(|x| x.as_array()).ok_or(anyhow!(
+                "Expected message of kind 'batch' to have list key 'calls'."
+            ))?;
+            let mut returns = Vec::with_capacity(calls.len());
+            for (i, call) in calls.iter().enumerate() {
+                let out = call
+                    .as_object()
+                    .ok_or(anyhow!("Expected each call of a 'batch' to be a map"))
+                    .and_then(|call| ask_call(call, "each call of a 'batch'"))
+                    .and_then(|(fn_name, args)| {
+                        handle_ask(
+                            shm_handle, vdoms, libraries, vm_limits, max_alloc, ui, fn_name, args,
+                        )
+                    });
+                match out {
+                    Ok(out) => returns.push(out),
+                    Err(err) => {
+                        // The calls before it did happen, an "aloc" among them has to be known to
+                        // be freed again, so their returns go along with the error.
+                        let mut msg = error_message(&err);
+                        msg["index"] = json!(i);
+                        msg["returns"] = json!(returns);
+                        return Ok(Some(msg));
+                    }
                 }
-                _ => {
-                    return Err(anyhow!(
-                        "Unknown 'fn' in message with kind 'ask', found {}",
-                        fn_name
-                    ));
+            }
+            Ok(Some(json!({"kind": "return", "returns": returns })))
+        }
+        "tree" => {
+            /* {kind: 'tree', bytes: '<base64>', root: N} -- the whole arena, for clients which can't map the shm */
+            let bytes = msg.get("bytes").and_then(|x| x.as_str()).ok_or(anyhow!("Expected message of kind 'tree' to have stringy key 'bytes' and int key 'root'. Missing 'bytes'."))?;
+            let root = msg.get("root").and_then(|x| x.as_u64()).ok_or(anyhow!("Expected message of kind 'tree' to have stringy key 'bytes' and int key 'root'. Missing 'root'."))? as usize;
+            // Checked before decoding, a tree bigger than the data region couldn't be in the file either.
+            let estimate = base64::decoded_len_estimate(bytes.len());
+            if estimate > LEN - DATA_OFF {
+                return Err(TooLarge {
+                    what: "A tree",
+                    requested: estimate,
+                    limit: LEN - DATA_OFF,
                 }
+                .into());
             }
+            let bytes = base64::engine::general_purpose::STANDARD.decode(bytes)?;
+            let tree = TreeBuffer::from_bytes(&bytes, root)?;
+
+            {
+                let mut lock = vdoms.lock().unwrap();
+                lock.set_tree(root, tree);
+            }
+            ui.request_redraw();
+            Ok(Some(json!({"kind": "return", "return": null })))
         }
-        _ => Err(anyhow!("Unknown kind '{}', support one of: ['ask']", kind)),
+        _ => Err(anyhow!(
+            "Unknown kind '{}', support one of: ['ask', 'batch', 'tree']",
+            kind
+        )),
     }
 }
 
 pub fn handle_sock_msg(
     shm_handle: &SHMHandle,
-    vdoms: &Arc<Mutex<(Option<usize>, Option<Arc<SemMutex<MmapMut>>>)>>,
+    vdoms: &Vdoms,
+    libraries: &Libraries,
+    vm_limits: VmLimits,
+    max_alloc: Option<usize>,
+    ui: &UiCommandSender,
     msg: serde_json::Map<String, serde_json::Value>,
 ) -> Option<String> {
-    match handle_sock_msg_falliable(shm_handle, vdoms, msg) {
-        Ok(o) => o,
-        Err(err) => serde_json::to_string(&json!({"kind": "error", "error": err.to_string()})).ok(), /* TODO: log warning here if serealisation fails */
+    // Handed back as is, so the client can check which message an answer belongs to.
+    let id = msg.get("id").cloned();
+    let mut answer = match handle_sock_msg_falliable(
+        shm_handle, vdoms, libraries, vm_limits, max_alloc, ui, msg,
+    ) {
+        Ok(answer) => answer?,
+        Err(err) => error_message(&err),
+    };
+    if let Some(id) = id {
+        answer["id"] = id;
+    }
+    serde_json::to_string(&answer).ok() /* TODO: log warning here if serealisation fails */
+}
+
+/// The message the client gets for `err`.
+fn error_message(err: &anyhow::Error) -> serde_json::Value {
+    match err.downcast_ref::<ll_aloc::OutOfMemory>() {
+        /* so the client can free something and retry without parsing the message */
+        Some(oom) => json!({
+            "kind": "error",
+            "error": err.to_string(),
+            "code": "OOM",
+            "requested": oom.requested,
+            "available": oom.available,
+        }),
+        None => match err.downcast_ref::<TooLarge>() {
+            Some(too_large) => json!({
+                "kind": "error",
+                "error": err.to_string(),
+                "code": "TOO_LARGE",
+                "requested": too_large.requested,
+                "limit": too_large.limit,
+            }),
+            None => json!({"kind": "error", "error": err.to_string()}),
+        },
     }
 }
@@ -1,23 +1,38 @@
 use anyhow::{Result, anyhow};
 use core::panic;
 use libc::{
-    EAGAIN, O_CREAT, O_RDWR, S_IRUSR, S_IWUSR, c_long, ftruncate, sem_open, sem_post, sem_trywait,
-    sem_unlink, sem_wait, shm_open, shm_unlink,
+    EAGAIN, EINTR, O_CREAT, O_RDWR, S_IRUSR, S_IWUSR, c_long, ftruncate, sem_open, sem_post,
+    sem_trywait, sem_unlink, sem_wait, shm_open, shm_unlink,
 };
 use memmap2::{MmapMut, MmapOptions};
 use std::{
     ffi::CString,
     fs::File,
     os::fd::FromRawFd,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicUsize, Ordering},
+    },
     time::Duration,
 };
-use tokio::{io, task};
+use tokio::{
+    io,
+    sync::{Mutex as AsyncMutex, mpsc},
+    task,
+};
+use tracing::error;
 
 use crate::{ll_aloc, process::PROTOCOL_VERSION};
 pub const VERSION_OFF: usize = 0;
 pub const DATA_OFF: usize = VERSION_OFF + size_of::<usize>();
 pub const LEN: usize = 1_024 * 32 /*32 kb*/;
+// The patch ring sits after the data region, so offsets into the data region stay the same.
+pub const RING_OFF: usize = LEN;
+const RING_HEAD_OFF: usize = RING_OFF; /* bytes ever written, only the client moves it */
+const RING_TAIL_OFF: usize = RING_HEAD_OFF + size_of::<usize>(); /* bytes ever applied, only we move it */
+const RING_DATA_OFF: usize = RING_TAIL_OFF + size_of::<usize>();
+const RING_CAP: usize = 1_024 * 4; /* power of two so the counters can wrap around usize */
+pub const FILE_LEN: usize = RING_DATA_OFF + RING_CAP;
 
 /// Create-or-open a POSIX shared-memory object and return the file descriptor
 fn open_shm(c_name: &CString, len: usize) -> std::io::Result<File> {
@@ -170,33 +185,52 @@ impl UnsafeSendSyncRawSem {
     unsafe fn try_wait(&self) -> i32 {
         unsafe { sem_trywait(self.0) }
     }
+
+    unsafe fn wait(&self) -> i32 {
+        unsafe { sem_wait(self.0) }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SHMHandle {
-    sem_ready: UnsafeSendSyncRawSem,      /* sem_ready */
-    pub shm_file: Arc<SemMutex<MmapMut>>, /* sem_lock */
+    sem_ready: UnsafeSendSyncRawSem,                       /* sem_ready */
+    pub shm_file: Arc<SemMutex<MmapMut>>,                  /* sem_lock */
+    ready_rx: Option<Arc<AsyncMutex<mpsc::Receiver<()>>>>, /* see `with_blocking_wait` */
+}
+
+/// The names of the shared memory object and of its two semaphores.
+fn shm_names(toplevel_name: &str) -> (CString, CString, CString) {
+    (
+        CString::new(format!("{toplevel_name}")).unwrap(),
+        CString::new(format!("{toplevel_name}_sem_ready")).unwrap(),
+        CString::new(format!("{toplevel_name}_sem_lock")).unwrap(),
+    )
+}
+
+/// Removes the shared memory object `toplevel_name` and its semaphores from the system. Those
+/// that don't exist are skipped, so it can run more than once.
+pub fn unlink_shm(toplevel_name: &str) {
+    let (shm_name, sem_ready_name, sem_lock_name) = shm_names(toplevel_name);
+    // These fail if the file doesn't exist, but that's okay we just move on silently.
+    unsafe {
+        shm_unlink(shm_name.as_ptr());
+        sem_unlink(sem_lock_name.as_ptr());
+        sem_unlink(sem_ready_name.as_ptr());
+    };
 }
 
 impl SHMHandle {
     pub fn new(toplevel_name: &str) -> Self {
-        let shm_name = CString::new(format!("{toplevel_name}")).unwrap();
-        let sem_ready_name = CString::new(format!("{toplevel_name}_sem_ready")).unwrap();
-        let sem_lock_name = CString::new(format!("{toplevel_name}_sem_lock")).unwrap();
+        let (shm_name, sem_ready_name, sem_lock_name) = shm_names(toplevel_name);
 
         // Delete previous file and sem if it exists
-        // these fail if the file doesn't exist, but that's okay we just move on silently.
-        unsafe {
-            shm_unlink(shm_name.as_ptr());
-            sem_unlink(sem_lock_name.as_ptr());
-            sem_unlink(sem_ready_name.as_ptr());
-        };
+        unlink_shm(toplevel_name);
 
         // Setup Shared Data
         let sem_ready = unsafe { open_sem(&sem_ready_name, 0).unwrap() };
         let sem_lock = unsafe { open_sem(&sem_lock_name, 1).unwrap() };
-        let file = open_shm(&shm_name, LEN).unwrap();
-        let mut mmaped = map_shared(&file, LEN).unwrap();
+        let file = open_shm(&shm_name, FILE_LEN).unwrap();
+        let mut mmaped = map_shared(&file, FILE_LEN).unwrap();
         unsafe {
             init_data(&mut mmaped);
         } // Setup default linked list alocator
@@ -204,14 +238,52 @@ impl SHMHandle {
         Self {
             sem_ready: UnsafeSendSyncRawSem(sem_ready),
             shm_file: Arc::new(SemMutex::new(sem_lock, mmaped)),
+            ready_rx: None,
         }
     }
 
+    /// Wait for `sem_ready` blocking on a thread of its own instead of polling it in `recv`.
+    // Polling backs off up to 5ms between tries, which is added to every frame the client
+    // signals. A blocked thread wakes up the moment the client posts.
+    pub fn with_blocking_wait(mut self) -> Result<Self> {
+        let sem_ready = self.sem_ready;
+        let (tx, rx) = mpsc::channel(1);
+        std::thread::Builder::new()
+            .name(String::from("sem_ready"))
+            .spawn(move || {
+                loop {
+                    if unsafe { sem_ready.wait() } != 0 {
+                        let err = std::io::Error::last_os_error();
+                        if err.raw_os_error() == Some(EINTR) {
+                            continue;
+                        }
+                        error!("Waiting on sem_ready failed, no more frames. {:#}", err);
+                        return;
+                    }
+                    // A signal that is still pending rereads the tree all the same, so if the
+                    // channel is full this one can go.
+                    if let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(()) {
+                        return;
+                    }
+                }
+            })?;
+        self.ready_rx = Some(Arc::new(AsyncMutex::new(rx)));
+        Ok(self)
+    }
+
     pub fn recv(&self) -> impl std::future::Future<Output = Arc<SemMutex<MmapMut>>> {
         let sem_ready = self.sem_ready.clone();
         let shm_file = self.shm_file.clone();
+        let ready_rx = self.ready_rx.clone();
 
         async move {
+            if let Some(ready_rx) = ready_rx {
+                if ready_rx.lock().await.recv().await.is_none() {
+                    std::future::pending::<()>().await; /* the thread gave up and logged why */
+                }
+                return shm_file;
+            }
+
             // Things used in the loop
             const BASE_BACKOFF_US: u64 = 50;
             let mut backoff = Duration::from_micros(BASE_BACKOFF_US);
@@ -246,12 +318,150 @@ impl SHMHandle {
         }
     }
 }
-impl Drop for SHMHandle {
-    fn drop(&mut self) {
-        /* figure out how to unlink the fles, this is tricky because the infinite loop takes self by reference so you have to respond to the external abort on the returned future. */
-        // shm_unlink(self.shm_name.as_ptr());
-        // sem_unlink(self.sem_ready_name.as_ptr());
-        // sem_unlink(self.sem_read_name.as_ptr());
-        // this both may fail if they are unlinked already, but that's fine we just continue silently
+
+// ::: ---- Patch ring, small updates to the data region without the lock ---- :::
+// For layouts that change many times a second the client can queue records of
+// `[offset: usize] [len: usize] [len bytes, padded to a word]` in the ring instead of taking the
+// lock to write them itself. They are applied under the lock once `sem_ready` is posted, so a
+// frame never sees half of an update. The ring is single producer (the client moves the head
+// after writing a record) and single consumer (we move the tail after applying it).
+impl SHMHandle {
+    /// Copies every record queued in the ring into the data region, returns how many there were.
+    pub fn apply_patches(&self) -> Result<usize> {
+        let mut file_lock = self.shm_file.lock()?;
+        apply_ring(&mut file_lock.data)
+    }
+}
+
+/// Applies the records queued in the ring of `file`, the whole shared file, see above.
+fn apply_ring(file: &mut [u8]) -> Result<usize> {
+    assert!(file.len() >= FILE_LEN);
+    let base = file.as_mut_ptr();
+    let (head, tail) = unsafe {
+        (
+            &*(base.add(RING_HEAD_OFF) as *const AtomicUsize),
+            &*(base.add(RING_TAIL_OFF) as *const AtomicUsize),
+        )
+    };
+    let head = head.load(Ordering::Acquire);
+    let mut pos = tail.load(Ordering::Relaxed);
+
+    let read = |pos: usize, dst: *mut u8, n: usize| {
+        for i in 0..n {
+            unsafe {
+                *dst.add(i) = *base.add(RING_DATA_OFF + pos.wrapping_add(i) % RING_CAP);
+            }
+        }
+    };
+
+    let mut applied = 0;
+    let out = loop {
+        let queued = head.wrapping_sub(pos);
+        if queued == 0 {
+            break Ok(applied);
+        }
+        if queued > RING_CAP {
+            break Err(anyhow!(
+                "Patch ring head ({head}) is more than the ring's size ahead of its tail ({pos})."
+            ));
+        }
+
+        let mut record = [0usize; 2];
+        if queued < size_of_val(&record) {
+            break Err(anyhow!("Patch ring ends in the middle of a record header."));
+        }
+        read(pos, record.as_mut_ptr() as *mut u8, size_of_val(&record));
+        let [off, len] = record.map(usize::from_le);
+        /* checked first, `len` comes from the client and the size below can't overflow once it fits */
+        if off.checked_add(len).is_none_or(|end| end > LEN - DATA_OFF) {
+            break Err(anyhow!(
+                "Patch of {len} bytes at 0x{off:x} doesn't fit in the data region."
+            ));
+        }
+        let size = size_of_val(&record) + len.next_multiple_of(size_of::<usize>());
+        if queued < size {
+            break Err(anyhow!(
+                "Patch record at {pos} claims {len} bytes but only {queued} are queued."
+            ));
+        }
+
+        read(
+            pos + size_of_val(&record),
+            unsafe { base.add(DATA_OFF + off) },
+            len,
+        );
+        pos = pos.wrapping_add(size);
+        applied += 1;
+    };
+
+    // A broken record can't be skipped since its length is unknown, so everything queued
+    // after it is dropped with it.
+    tail.store(if out.is_ok() { pos } else { head }, Ordering::Release);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shared file with `records`, `(offset, claimed length, bytes)`, queued in its ring. It is
+    /// kept in words so the ring's counters are aligned like in the real mapping.
+    fn file_with(records: &[(usize, usize, &[u8])]) -> Vec<usize> {
+        let mut file = vec![0usize; FILE_LEN.div_ceil(size_of::<usize>())];
+        let bytes = as_bytes(&mut file);
+        let mut head = 0;
+        for (off, len, data) in records {
+            for word in [off, len] {
+                bytes[RING_DATA_OFF + head..][..size_of::<usize>()]
+                    .copy_from_slice(&word.to_le_bytes());
+                head += size_of::<usize>();
+            }
+            bytes[RING_DATA_OFF + head..][..data.len()].copy_from_slice(data);
+            head += data.len().next_multiple_of(size_of::<usize>());
+        }
+        bytes[RING_HEAD_OFF..][..size_of::<usize>()].copy_from_slice(&head.to_le_bytes());
+        file
+    }
+
+    fn as_bytes(file: &mut [usize]) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(file.as_mut_ptr() as *mut u8, size_of_val(file)) }
+    }
+
+    fn counters(bytes: &[u8]) -> (usize, usize) {
+        let word =
+            |at: usize| usize::from_le_bytes(bytes[at..][..size_of::<usize>()].try_into().unwrap());
+        (word(RING_HEAD_OFF), word(RING_TAIL_OFF))
+    }
+
+    #[test]
+    fn applies_a_record() {
+        let mut file = file_with(&[(16, 5, b"hello"), (40, 2, b"hi")]);
+        let bytes = as_bytes(&mut file);
+        assert_eq!(apply_ring(bytes).unwrap(), 2);
+        assert_eq!(&bytes[DATA_OFF + 16..][..5], b"hello");
+        assert_eq!(&bytes[DATA_OFF + 40..][..2], b"hi");
+        let (head, tail) = counters(bytes);
+        assert_eq!(tail, head, "the ring is empty again");
+        assert_eq!(apply_ring(bytes).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_a_truncated_record() {
+        let mut file = file_with(&[(0, 64, b"only eight")]);
+        let bytes = as_bytes(&mut file);
+        assert!(apply_ring(bytes).is_err());
+        assert!(bytes[DATA_OFF..][..64].iter().all(|b| *b == 0));
+        let (head, tail) = counters(bytes);
+        assert_eq!(tail, head, "the broken record is dropped");
+    }
+
+    #[test]
+    fn rejects_a_record_outside_the_data_region() {
+        for (off, len) in [(LEN - DATA_OFF - 4, 8), (8, usize::MAX), (usize::MAX, 1)] {
+            let mut file = file_with(&[(off, len, &[0xff; 8])]);
+            let bytes = as_bytes(&mut file);
+            assert!(apply_ring(bytes).is_err(), "{len} bytes at 0x{off:x}");
+            assert!(bytes[DATA_OFF..LEN].iter().all(|b| *b == 0));
+        }
     }
 }
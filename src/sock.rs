@@ -1,41 +1,157 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use serde::de::DeserializeOwned;
-use std::{fs, path::Path, sync::Arc};
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::UnixListener,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
     sync::{Mutex, broadcast},
 };
 use tracing::{error, trace, warn};
 
+use crate::shm::LEN;
+
+/// How many heartbeat intervals a client may stay silent before it is considered dead.
+pub const HEARTBEAT_MISSES: u32 = 3;
+
+/// The longest message read off the socket. The biggest a client has reason to send is a "tree"
+/// holding as much as the shared file in base64, a length prefix past this is garbage.
+pub const MAX_FRAME: usize = 2 * LEN;
+
+/// Length-prefixes a message (u32 little-endian byte count, then the utf-8 bytes).
+fn frame(msg: &str) -> Vec<u8> {
+    let bytes = msg.as_bytes(); /* this is utf-8 */
+    let mut buf = Vec::with_capacity(4 + bytes.len());
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+    buf
+}
+
+/// The error answer to a message that couldn't be read. The client may be waiting on it as the
+/// answer to an "ask", so it is sent rather than dropping the message; `id` is the message's own
+/// "id" if it got far enough to have one.
+fn unreadable(error: String, id: Option<&serde_json::Value>) -> String {
+    let mut reply = serde_json::json!({"kind": "error", "error": error});
+    if let Some(id) = id {
+        reply["id"] = id.clone();
+    }
+    reply.to_string()
+}
+
+/// Where the runtime listens for the client. The framing is the same for both.
+#[derive(Debug, Clone)]
+pub enum SockAddr {
+    Unix(String),
+    Tcp(String),
+}
+impl SockAddr {
+    /// Parses `tcp://host:port` or `unix:///path/to/socket`.
+    pub fn parse(addr: &str) -> Result<Self> {
+        if let Some(rest) = addr.strip_prefix("tcp://") {
+            Ok(SockAddr::Tcp(rest.to_owned()))
+        } else if let Some(rest) = addr.strip_prefix("unix://") {
+            Ok(SockAddr::Unix(rest.to_owned()))
+        } else {
+            Err(anyhow!(
+                "Unknown address '{}', expected 'tcp://host:port' or 'unix:///path'",
+                addr
+            ))
+        }
+    }
+
+    /// Deletes the socket file of a unix socket, if it is still there.
+    pub fn remove_socket_file(&self) {
+        if let SockAddr::Unix(path) = self {
+            if let Err(err) = fs::remove_file(path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Couldn't remove the socket file {}. {:#}", path, err);
+                }
+            }
+        }
+    }
+
+    /// The form handed to the client in `z71200_SOCK`, plain path for unix sockets to stay
+    /// compatible with existing clients.
+    pub fn to_env(&self) -> String {
+        match self {
+            SockAddr::Unix(path) => path.clone(),
+            SockAddr::Tcp(addr) => format!("tcp://{}", addr),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SockListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+impl SockListener {
+    async fn accept(&self) -> std::io::Result<Box<dyn SockStream>> {
+        match self {
+            SockListener::Unix(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok(Box::new(stream))
+            }
+            SockListener::Tcp(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                stream.set_nodelay(true)?; /* asks are small and latency bound */
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+trait SockStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> SockStream for T {}
+
 #[derive(Debug, Clone)]
 pub struct SockHandle {
     #[allow(dead_code)]
-    pub name: String,
-    pub listener: Arc<UnixListener>,
+    pub addr: SockAddr,
+    listener: Arc<SockListener>,
     tx: broadcast::Sender<String>,
 }
 impl SockHandle {
-    pub fn new(socket_path: &str) -> Result<Self> {
-        if Path::new(&socket_path).exists() {
-            fs::remove_file(&socket_path).unwrap();
-        }
+    pub fn new(addr: &SockAddr) -> Result<Self> {
         // 1. create a *blocking* std listener
         // 2. flip it to non-blocking and lift it into Tokio
-        let std_listener = std::os::unix::net::UnixListener::bind(socket_path)?;
-        std_listener.set_nonblocking(true).unwrap();
-        let listener = UnixListener::from_std(std_listener)?;
+        let listener = match addr {
+            SockAddr::Unix(socket_path) => {
+                if Path::new(&socket_path).exists() {
+                    fs::remove_file(&socket_path).unwrap();
+                }
+                let std_listener = std::os::unix::net::UnixListener::bind(socket_path)?;
+                std_listener.set_nonblocking(true).unwrap();
+                SockListener::Unix(UnixListener::from_std(std_listener)?)
+            }
+            SockAddr::Tcp(host) => {
+                let std_listener = std::net::TcpListener::bind(host)?;
+                std_listener.set_nonblocking(true).unwrap();
+                SockListener::Tcp(TcpListener::from_std(std_listener)?)
+            }
+        };
 
         let (tx, _rx) = broadcast::channel(100);
 
         Ok(SockHandle {
-            name: socket_path.to_owned(),
+            addr: addr.clone(),
             listener: Arc::new(listener),
             tx,
         })
     }
 
-    pub fn start<F, A, I, J>(&self, cb_sock: F, cb_quit: A) -> impl std::future::Future<Output = ()>
+    /// Accepts clients and serves them until they hang up. With `heartbeat` set the client is
+    /// pinged on that interval and dropped (calling `cb_quit`) after `HEARTBEAT_MISSES` of silence.
+    pub fn start<F, A, I, J>(
+        &self,
+        cb_sock: F,
+        cb_quit: A,
+        heartbeat: Option<Duration>,
+    ) -> impl std::future::Future<Output = ()>
     where
         F: Fn(I) -> Option<String> + Clone + Send + Sync + 'static,
         A: Fn() -> J + Clone + Send + Sync + 'static,
@@ -47,7 +163,13 @@ impl SockHandle {
         let tx = self.tx.clone();
         async move {
             loop {
-                let (stream_raw, _addr) = self.listener.accept().await.unwrap();
+                let stream_raw = match self.listener.accept().await {
+                    Ok(stream_raw) => stream_raw,
+                    Err(err) => {
+                        error!("Failed to accept connection on unix socket: {:?}", err);
+                        continue;
+                    }
+                };
                 let cb_sock = cb_sock.clone();
                 let cb_quit = cb_quit.clone();
                 let mut rx = tx.subscribe();
@@ -58,17 +180,46 @@ impl SockHandle {
                 let stream = Mutex::new(stream_raw);
 
                 tokio::spawn(async move {
+                    // Clients that hang without closing the socket are caught by pinging them
+                    // every `heartbeat` and giving up after a few missed answers.
+                    let mut ticker = heartbeat.map(|heartbeat| {
+                        tokio::time::interval_at(tokio::time::Instant::now() + heartbeat, heartbeat)
+                    });
+                    let mut last_seen = Instant::now();
                     loop {
                         let mut size_buffer = [0; 4];
                         tokio::select! {
-                            Ok(data) = rx.recv() => {
-                                let bytes = data.as_bytes(); /* this is utf-8 */
-                                let size = bytes.len() as u32;
-                                let mut buf = Vec::with_capacity(4 + bytes.len());
-                                buf.extend_from_slice(&size.to_le_bytes());
-                                buf.extend_from_slice(bytes);
-                                let _ = stream.lock().await.write_all(&buf).await;
-
+                            data = rx.recv() => match data {
+                                Ok(data) => {
+                                    let _ = stream.lock().await.write_all(&frame(&data)).await;
+                                }
+                                /* the client reads slower than events come, the oldest are gone */
+                                Err(broadcast::error::RecvError::Lagged(n)) => {
+                                    warn!("The client fell behind, it missed {} events.", n);
+                                }
+                                Err(broadcast::error::RecvError::Closed) => return,
+                            },
+                            _ = async {
+                                match ticker.as_mut() {
+                                    Some(ticker) => { ticker.tick().await; },
+                                    None => std::future::pending::<()>().await, /* heartbeat disabled */
+                                }
+                            } => {
+                                if let Some(heartbeat) = heartbeat {
+                                    if last_seen.elapsed() > heartbeat * HEARTBEAT_MISSES {
+                                        warn!(
+                                            "No message from the client in {:?}, considering it dead.",
+                                            last_seen.elapsed()
+                                        );
+                                        cb_quit().await;
+                                        return;
+                                    }
+                                }
+                                let ping = frame(r#"{"kind":"ping"}"#);
+                                if let Err(err) = stream.lock().await.write_all(&ping).await {
+                                    warn!("Error when sending ping on socket, dropping connection. {:?}", err);
+                                    return;
+                                }
                             },
                             (mut stream_guard, maybe_error) = async {
                                 let mut stream_guard =  stream.lock().await;
@@ -93,12 +244,33 @@ impl SockHandle {
                             }
 
                             let message_size = u32::from_le_bytes(size_buffer);
+                            if message_size as usize > MAX_FRAME {
+                                /* the rest of the stream can't be framed anymore, so the connection is no use */
+                                warn!(
+                                    "Message of {} bytes is longer than the {} allowed, dropping connection.",
+                                    message_size, MAX_FRAME
+                                );
+                                let reply = unreadable(
+                                    format!("Message of {} bytes is longer than the {} allowed.", message_size, MAX_FRAME),
+                                    None,
+                                );
+                                let _ = stream_guard.write_all(&frame(&reply)).await;
+                                return;
+                            }
 
                             // Read the JSON payload based on the size
                             let mut buffer = vec![0; message_size as usize];
-                            stream_guard.read_exact(&mut buffer).await.unwrap();
-
-
+                            if let Err(err) = stream_guard.read_exact(&mut buffer).await {
+                                warn!(
+                                    "Error when trying to read_exact payload of {} bytes on unix socket -- the process probably hungup. {:?}",
+                                    message_size, err
+                                );
+                                if err.kind() == std::io::ErrorKind::UnexpectedEof {
+                                    /* hung up half way through a frame, same as hanging up between frames. */
+                                    cb_quit().await;
+                                }
+                                return;
+                            }
 
                             match String::from_utf8(buffer) {
                                 Ok(json_str) => {
@@ -106,23 +278,45 @@ impl SockHandle {
                                         "Received message size: {}, JSON: {}",
                                         message_size, json_str
                                     );
-                                    let maybe_response =
-                                        cb_sock(serde_json::from_str(&json_str).unwrap());
+                                    let value: serde_json::Value = match serde_json::from_str(&json_str) {
+                                        Ok(value) => value,
+                                        Err(err) => {
+                                            error!("Error parsing message as JSON: {}", err);
+                                            let reply = unreadable(format!("Message isn't valid JSON. {}", err), None);
+                                            if let Err(err) = stream_guard.write_all(&frame(&reply)).await {
+                                                warn!("Error when writing response on unix socket, dropping connection. {:?}", err);
+                                                return;
+                                            }
+                                            continue;
+                                        }
+                                    };
+                                    last_seen = Instant::now(); /* any message proves the client is alive */
+                                    if value.get("kind").and_then(|x| x.as_str()) == Some("pong") {
+                                        continue; /* answers our ping, nothing else to do */
+                                    }
+                                    let id = value.get("id").cloned();
+                                    let maybe_response = match serde_json::from_value(value) {
+                                        Ok(msg) => cb_sock(msg),
+                                        Err(err) => {
+                                            error!("Error parsing message: {}", err);
+                                            Some(unreadable(format!("Message isn't a map with stringy key 'kind'. {}", err), id.as_ref()))
+                                        }
+                                    };
                                     if let Some(response) = maybe_response {
-                                        // Prepare response
-                                        let response_bytes = response.as_bytes(); /* this is utf-8 */
-                                        let response_size = response_bytes.len() as u32;
-
-                                        // Construct out (size + message)
-                                        let mut buf =
-                                            Vec::with_capacity(4usize + response_size as usize);
-                                        buf.extend_from_slice(&response_size.to_le_bytes());
-                                        buf.extend_from_slice(response_bytes);
-                                        stream_guard.write_all(&buf).await.unwrap();
+                                        if let Err(err) = stream_guard.write_all(&frame(&response)).await {
+                                            /* the client can't see the answer to its ask, so the connection is no use anymore */
+                                            warn!("Error when writing response on unix socket, dropping connection. {:?}", err);
+                                            return;
+                                        }
                                     }
                                 }
                                 Err(err) => {
                                     error!("Error parsing message as UTF-8: {}", err);
+                                    let reply = unreadable(format!("Message isn't valid UTF-8. {}", err), None);
+                                    if let Err(err) = stream_guard.write_all(&frame(&reply)).await {
+                                        warn!("Error when writing response on unix socket, dropping connection. {:?}", err);
+                                        return;
+                                    }
                                 }
                             }
                         }
@@ -135,22 +329,9 @@ impl SockHandle {
 
     #[allow(dead_code)]
     pub fn broadcast(&mut self, data: &String) -> Result<()> {
-        self.tx.send(data.clone())?;
+        // Only fails if no client is connected, one of several programmes may still be starting
+        // and has nothing to be told yet.
+        let _ = self.tx.send(data.clone());
         Ok(())
     }
 }
-
-// impl Drop for SockGuard {
-//     fn drop(&mut self) {
-//         // Close the connection handles first
-//         drop(&mut self.listener);
-
-//         // Then remove the socket file
-//         if Path::new(&self.name).exists() {
-//             match fs::remove_file(&self.name) {
-//                 Ok(_) => println!("Socket file '{}' removed successfully", self.name),
-//                 Err(e) => eprintln!("Failed to remove socket file '{}': {}", self.name, e),
-//             }
-//         }
-//     }
-// }
@@ -0,0 +1,126 @@
+use std::{path::PathBuf, sync::mpsc};
+
+use anyhow::{Result, anyhow};
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tracing::warn;
+use winit::{
+    dpi::PhysicalSize,
+    window::{CursorGrabMode, CursorIcon, Fullscreen, Window},
+};
+
+// ::: ---- Commands sent from the socket side to the UI thread ---- :::
+// The socket handler runs on a tokio task and has no access to the window, so anything
+// the client wants to do to the window is queued here and applied by the event loop.
+// Adding a new window control means adding a variant below, handling it in `apply`,
+// and sending it from an `ask` in `handle_sock_msg`.
+
+#[derive(Debug, Clone, Copy)]
+pub enum FullscreenMode {
+    Off,
+    Borderless,
+    Exclusive,
+}
+
+#[derive(Debug, Clone)]
+pub enum UiCommand {
+    SetFullscreen(FullscreenMode),
+    SetWindowConstraints {
+        min_size: Option<(u32, u32)>,
+        max_size: Option<(u32, u32)>,
+    },
+    SetTitle(String),
+    SetCursor(Option<CursorIcon>), /* `None` hands the cursor back to the elements */
+    SetCursorVisible(bool),
+    SetCursorGrab(CursorGrabMode),
+    DumpState(PathBuf, mpsc::Sender<Result<()>>), /* needs the frame state, so the event loop writes it and says how it went */
+}
+impl UiCommand {
+    pub fn apply(self, window: &Window) {
+        match self {
+            UiCommand::SetFullscreen(mode) => {
+                let fullscreen = match mode {
+                    FullscreenMode::Off => None,
+                    FullscreenMode::Borderless => Some(Fullscreen::Borderless(None)),
+                    FullscreenMode::Exclusive => {
+                        /* pick the biggest, fastest mode of the monitor we are on */
+                        let video_mode = window.current_monitor().and_then(|monitor| {
+                            monitor.video_modes().max_by_key(|mode| {
+                                let size = mode.size();
+                                (size.width * size.height, mode.refresh_rate_millihertz())
+                            })
+                        });
+                        if let Some(video_mode) = video_mode {
+                            Some(Fullscreen::Exclusive(video_mode))
+                        } else {
+                            warn!(
+                                "No video mode available for exclusive fullscreen, using borderless."
+                            );
+                            Some(Fullscreen::Borderless(None))
+                        }
+                    }
+                };
+                window.set_fullscreen(fullscreen);
+            }
+            UiCommand::SetWindowConstraints { min_size, max_size } => {
+                window.set_min_inner_size(min_size.map(|(w, h)| PhysicalSize::new(w, h)));
+                window.set_max_inner_size(max_size.map(|(w, h)| PhysicalSize::new(w, h)));
+            }
+            UiCommand::SetTitle(title) => window.set_title(&title),
+            UiCommand::SetCursor(cursor) => {
+                window.set_cursor(cursor.unwrap_or(CursorIcon::Default))
+            }
+            UiCommand::SetCursorVisible(visible) => window.set_cursor_visible(visible),
+            UiCommand::SetCursorGrab(mode) => {
+                // Platforms only have one of the two: X11 and Windows can't lock the cursor in
+                // place and macOS can't confine it to the window. Either keeps it from leaving,
+                // so fall back to the other before giving up.
+                let fallback = match mode {
+                    CursorGrabMode::Locked => Some(CursorGrabMode::Confined),
+                    CursorGrabMode::Confined => Some(CursorGrabMode::Locked),
+                    CursorGrabMode::None => None,
+                };
+                let r = window.set_cursor_grab(mode).or_else(|err| match fallback {
+                    Some(fallback) => {
+                        warn!(
+                            "Cursor grab mode {:?} isn't supported here, using {:?}. {}",
+                            mode, fallback, err
+                        );
+                        window.set_cursor_grab(fallback)
+                    }
+                    None => Err(err),
+                });
+                if let Err(err) = r {
+                    warn!("Couldn't grab the cursor. {}", err);
+                }
+            }
+            UiCommand::DumpState(..) => (), /* nothing to do to the window */
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UiCommandSender {
+    tx: UnboundedSender<UiCommand>,
+    tx_wake: Sender<()>,
+}
+impl UiCommandSender {
+    pub fn new(tx: UnboundedSender<UiCommand>, tx_wake: Sender<()>) -> Self {
+        Self { tx, tx_wake }
+    }
+
+    pub fn send(&self, command: UiCommand) -> Result<()> {
+        self.tx
+            .send(command)
+            .map_err(|_| anyhow!("The UI thread is no longer receiving commands."))?;
+        // Commands are drained once the event loop wakes up, requesting a redraw does that.
+        self.request_redraw();
+        Ok(())
+    }
+
+    /// Draws the tree anew in the next frame, not the last settled frame again. Asks that change
+    /// what is drawn without writing to the shared file (roots, libraries, `compact`) need it.
+    pub fn request_redraw(&self) {
+        // If the channel is full a redraw is already pending which works just as well.
+        let _ = self.tx_wake.try_send(());
+    }
+}
@@ -2,6 +2,7 @@
 Example from: https://github.com/rust-skia/rust-skia/blob/master/skia-safe/examples/vulkan-window/context.rs
 */
 
+use anyhow::{Context, Result, anyhow};
 use std::sync::Arc;
 use tracing::debug;
 use vulkano::{
@@ -10,12 +11,13 @@ use vulkano::{
         Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
         physical::PhysicalDeviceType,
     },
-    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo},
+    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions},
     swapchain::Surface,
 };
 
 use winit::{event_loop::ActiveEventLoop, window::Window};
 
+use super::ColorSpaceOption;
 use super::renderer::VulkanRenderer;
 
 #[derive(Default)]
@@ -28,17 +30,33 @@ impl VulkanRenderContext {
         &mut self,
         event_loop: &ActiveEventLoop,
         window: Arc<Window>,
-    ) -> VulkanRenderer {
+        transparent: bool,
+        color_space: ColorSpaceOption,
+    ) -> Result<VulkanRenderer> {
         // lazily set up a shared instance, device, and queue to use for all subsequent renderers
-        let queue = self
-            .queue
-            .get_or_insert_with(|| Self::shared_queue(event_loop, window.clone()));
+        let queue = match &self.queue {
+            Some(queue) => queue.clone(),
+            None => {
+                let queue = Self::shared_queue(event_loop, window.clone(), color_space)?;
+                self.queue = Some(queue.clone());
+                queue
+            }
+        };
+
+        VulkanRenderer::new(window.clone(), queue, transparent, color_space)
+    }
 
-        VulkanRenderer::new(window.clone(), queue.clone())
+    /// Forgets the device, after it was lost the next renderer sets up a new one.
+    pub fn reset(&mut self) {
+        self.queue = None;
     }
 
-    fn shared_queue(event_loop: &ActiveEventLoop, window: Arc<Window>) -> Arc<Queue> {
-        let library = VulkanLibrary::new().expect("Vulkan libraries not found on system");
+    fn shared_queue(
+        event_loop: &ActiveEventLoop,
+        window: Arc<Window>,
+        color_space: ColorSpaceOption,
+    ) -> Result<Arc<Queue>> {
+        let library = VulkanLibrary::new().context("Vulkan libraries not found on system")?;
 
         // The first step of any Vulkan program is to create an instance.
         //
@@ -47,7 +65,17 @@ impl VulkanRenderContext {
         // All the window-drawing functionalities are part of non-core extensions that we need to
         // enable manually. To do so, we ask `Surface` for the list of extensions required to draw
         // to a window.
-        let required_extensions = Surface::required_extensions(event_loop).unwrap();
+        let mut required_extensions = Surface::required_extensions(event_loop)
+            .context("Failed to query the surface extensions")?;
+
+        // Swapchains in anything but sRGB need another extension. Only ask for it when it is
+        // wanted, and the renderer falls back to sRGB if it isn't there.
+        if color_space != ColorSpaceOption::Srgb {
+            required_extensions = required_extensions.union(&InstanceExtensions {
+                ext_swapchain_colorspace: library.supported_extensions().ext_swapchain_colorspace,
+                ..InstanceExtensions::empty()
+            });
+        }
 
         // Now creating the instance.
         let instance = Instance::new(
@@ -60,9 +88,9 @@ impl VulkanRenderContext {
                 ..Default::default()
             },
         )
-        .unwrap_or_else(|_| {
-            panic!("Could not create instance supporting: {required_extensions:?}")
-        });
+        .with_context(|| {
+            format!("Could not create instance supporting: {required_extensions:?}")
+        })?;
 
         // Choose device extensions that we're going to use. In order to present images to a
         // surface, we need a `Swapchain`, which is provided by the `khr_swapchain` extension.
@@ -75,14 +103,15 @@ impl VulkanRenderContext {
         // so we can check whether the queue supports it. Note that in a future vulkano release
         // this requirement will go away once it can check for `presentation_support` from the
         // event_loop's display (see commented usage below…)
-        let surface = Surface::from_window(instance.clone(), window.clone()).unwrap();
+        let surface = Surface::from_window(instance.clone(), window.clone())
+            .context("Failed to create a surface for the window")?;
 
         // We then choose which physical device to use. First, we enumerate all the available
         // physical devices, then apply filters to narrow them down to those that can support our
         // needs.
         let (physical_device, queue_family_index) = instance
             .enumerate_physical_devices()
-            .unwrap()
+            .context("Failed to list the GPUs")?
             .filter(|p| {
                 // Some devices may not support the extensions or features that your application,
                 // or report properties and limits that are not sufficient for your application.
@@ -139,7 +168,7 @@ impl VulkanRenderContext {
                     _ => 5,
                 }
             })
-            .expect("No suitable physical device found");
+            .ok_or(anyhow!("No suitable physical device found"))?;
 
         // Print out the device we selected
         debug!(
@@ -172,11 +201,11 @@ impl VulkanRenderContext {
                 ..Default::default()
             },
         )
-        .expect("Device initialization failed");
+        .context("Device initialization failed")?;
 
         // Since we can request multiple queues, the `queues` variable is in fact an iterator. We
         // only use one queue in this example, so we just retrieve the first and only element of
         // the iterator.
-        queues.next().unwrap()
+        queues.next().ok_or(anyhow!("The device has no queue"))
     }
 }
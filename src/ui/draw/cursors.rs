@@ -28,6 +28,17 @@ impl LinearCursor {
     }
 }
 impl LinearCursor {
+    pub fn region(&self) -> (*const u8, *const u8) {
+        (self.region_start, self.region_end)
+    }
+
+    /// Moves the cursor to the start of another memory region, ie. a library body.
+    pub fn set_region(&mut self, region_start: *const u8, region_end: *const u8) {
+        self.region_start = region_start;
+        self.region_end = region_end;
+        self.cursor = region_start;
+    }
+
     pub fn add_depth(&mut self) {
         self.element_depth += 1;
     }
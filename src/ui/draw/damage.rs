@@ -0,0 +1,94 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use skia_safe::Rect;
+
+// ::: ---- Damage tracking, which parts of the window changed since the last frame ---- :::
+// While drawing, every node hashes what it paints (resolved geometry, colours, text) and
+// remembers the bounds of it. Comparing those against the previous frame node by node gives
+// the region that needs repainting. Comparing what was painted rather than what the bytecode
+// says also catches changes coming from registers, the stack, hover states, or scrolling.
+
+/// What one node painted in a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodePaint {
+    signature: u64,
+    bounds: Option<Rect>, /* `None` if the node didn't paint anything */
+}
+
+/// Collects the paint of a single node while its bytecode runs.
+pub(super) struct PaintRecorder {
+    hasher: DefaultHasher,
+    bounds: Option<Rect>,
+}
+impl PaintRecorder {
+    pub fn new() -> Self {
+        Self {
+            hasher: DefaultHasher::new(),
+            bounds: None,
+        }
+    }
+
+    /// Notes one draw call, `data` should hold everything that decides how it looks.
+    pub fn note(&mut self, bounds: Rect, data: impl Hash) {
+        data.hash(&mut self.hasher);
+        self.bounds = Some(match self.bounds {
+            Some(b) => Rect::join2(b, bounds),
+            None => bounds,
+        });
+    }
+
+    pub fn finish(self) -> NodePaint {
+        NodePaint {
+            signature: self.hasher.finish(),
+            bounds: self.bounds,
+        }
+    }
+}
+
+/// Floats aren't `Hash`, so geometry is hashed by its bit pattern.
+pub(super) fn f32_bits<const N: usize>(values: [f32; N]) -> [u32; N] {
+    values.map(f32::to_bits)
+}
+
+/// How much of the last frame has to be repainted.
+#[derive(Debug, Clone, Copy)]
+pub enum Damage {
+    None,
+    Partial(Rect),
+    Full,
+}
+
+/// Every node's paint, in the order the draw pass visits them.
+#[derive(Debug, Default)]
+pub struct PaintLog {
+    pub(super) nodes: Vec<NodePaint>,
+}
+impl PaintLog {
+    /// The region that changed between `previous` and this frame.
+    pub fn damage_since(&self, previous: &PaintLog) -> Damage {
+        // Nodes are only matched up by position, so if elements came or went we can't tell
+        // what moved where and repaint everything.
+        if self.nodes.len() != previous.nodes.len() {
+            return Damage::Full;
+        }
+
+        let mut damage: Option<Rect> = None;
+        for (now, before) in self.nodes.iter().zip(previous.nodes.iter()) {
+            if now == before {
+                continue;
+            }
+            for bounds in [now.bounds, before.bounds].into_iter().flatten() {
+                damage = Some(match damage {
+                    Some(d) => Rect::join2(d, bounds),
+                    None => bounds,
+                });
+            }
+        }
+
+        match damage {
+            /* grow by a pixel so anti-aliased edges are repainted too */
+            Some(d) => Damage::Partial(Rect::from(d.round_out()).with_outset((1.0, 1.0))),
+            None => Damage::None,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use skia_safe::{Canvas, Color, Font, FontMgr, FontStyle, Paint, PaintStyle, Rect};
+use taffy::{NodeId, PrintTree, TaffyTree, TraversePartialTree};
+
+use super::layout_pass::LayoutContext;
+use super::{CarriedState, StateKey};
+
+// ::: ---- After the Draw Pass, Outline every Box for `--debug-layout` ---- :::
+// Like the layout view of a browser's devtools: every element's box is stroked on top of the
+// finished frame, in a colour that changes with its depth, and labelled with its size. It only
+// paints, the hover and click jumps already ran against the real tree.
+
+/* one per level of nesting, so neighbours and children tell apart */
+const DEPTH_COLORS: [Color; 4] = [
+    Color::from_rgb(230, 0, 180),
+    Color::from_rgb(0, 160, 230),
+    Color::from_rgb(240, 140, 0),
+    Color::from_rgb(40, 180, 60),
+];
+
+pub(super) fn debug_pass(
+    canvas: &Canvas,
+    tree: &TaffyTree<LayoutContext>,
+    root: NodeId,
+    next_frame_state: &HashMap<StateKey, CarriedState>,
+    display_scale: f32,
+) -> Result<()> {
+    let fmgr = FontMgr::default();
+    let font = fmgr
+        .match_family_style("Arial", FontStyle::normal())
+        .or_else(|| fmgr.legacy_make_typeface(None, FontStyle::normal()))
+        .map(|typeface| Font::new(typeface, 10.0 * display_scale));
+
+    let save_count = canvas.save_count();
+    let out = debug_node(
+        canvas,
+        tree,
+        root,
+        0.0,
+        0.0,
+        0,
+        next_frame_state,
+        font.as_ref(),
+        display_scale,
+    );
+    canvas.restore_to_count(save_count);
+    out
+}
+
+fn debug_node(
+    canvas: &Canvas,
+    tree: &TaffyTree<LayoutContext>,
+    node: NodeId,
+    px: f32,
+    py: f32,
+    depth: usize,
+    next_frame_state: &HashMap<StateKey, CarriedState>,
+    font: Option<&Font>,
+    display_scale: f32,
+) -> Result<()> {
+    if tree.style(node)?.display == taffy::Display::None {
+        return Ok(());
+    }
+    let layout = tree.get_final_layout(node);
+    let ctx = tree
+        .get_node_context(node)
+        .ok_or(anyhow!("Each node in the taffy tree must have a context"))?;
+    let x = px + layout.location.x;
+    let mut y = py + layout.location.y + ctx.baseline_shift;
+    let bounds = Rect::from_xywh(x, y, layout.size.width, layout.size.height);
+
+    let mut paint = Paint::default();
+    paint.set_anti_alias(true);
+    paint.set_style(PaintStyle::Stroke);
+    paint.set_stroke_width(display_scale);
+    paint.set_color(DEPTH_COLORS[depth % DEPTH_COLORS.len()]);
+    /* inset by half the stroke so boxes that touch don't paint over each other */
+    canvas.draw_rect(
+        bounds.with_inset((display_scale / 2.0, display_scale / 2.0)),
+        &paint,
+    );
+    if let Some(font) = font {
+        let label = format!(
+            "{}x{}",
+            (layout.size.width / display_scale).round(),
+            (layout.size.height / display_scale).round()
+        );
+        paint.set_style(PaintStyle::Fill);
+        canvas.draw_str(
+            label,
+            (x + 2.0 * display_scale, y + font.size()),
+            font,
+            &paint,
+        );
+    }
+
+    // Scrolled like the draw pass did it, this frame's offsets are in the next frame's state.
+    let content_height = layout.content_size.height.max(
+        ctx.maybe_font_layout
+            .as_ref()
+            .map(|x| x.height())
+            .unwrap_or(0.0),
+    );
+    let scrolls = content_height > layout.size.height;
+    if scrolls {
+        canvas.save();
+        canvas.clip_rect(bounds, None, Some(true));
+        y += ctx
+            .state_key
+            .and_then(|key| next_frame_state.get(&key))
+            .map(|state| state.scroll_y)
+            .unwrap_or(0.0);
+    }
+
+    for child in tree.child_ids(node) {
+        debug_node(
+            canvas,
+            tree,
+            child,
+            x,
+            y,
+            depth + 1,
+            next_frame_state,
+            font,
+            display_scale,
+        )?;
+    }
+    if scrolls {
+        canvas.restore();
+    }
+    Ok(())
+}
@@ -1,20 +1,42 @@
 use std::time::Duration;
-use std::{collections::HashMap, sync::Arc, usize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    usize,
+};
 
-use anyhow::{Result, anyhow};
-use skia_safe::{Canvas, Color, Paint, Path, Rect};
+use anyhow::{Context, Result, anyhow};
+use parley::FontContext;
+use parley::style::WordBreakStrength;
+use skia_safe::{Canvas, Color, Color4f, Paint, PaintStyle, Path, PathEffect, RRect, Rect, Vector};
 use taffy::{NodeId, PrintTree, TaffyTree, TraversePartialTree};
-use winit::window::{CursorIcon, Window};
+use winit::window::CursorIcon;
 
 use super::cursors::RaggedCursor;
+use super::damage::{PaintLog, PaintRecorder, f32_bits};
+use super::fade::ColorFade;
 use super::layout_pass::LayoutContext;
-use super::text::draw_text;
+use super::text::{draw_text, layout_text};
 
-use super::CarriedState;
 use super::InputState;
+use super::WindowLike;
 use super::traits::{Executor, HasStaticConfig, Intepreter};
 use super::utils::{StaticConfig, resolve_taffy_length};
-use super::vm_state::VMState;
+use super::vm_state::{Scopes, VMState};
+use super::{
+    CarriedState, ColorSpaceOption, FiredEvent, PassTrace, StateKey, StoredWhiteSpace, TaggedWord,
+};
+
+/// Sets `paint` to a colour from the layout, whose components are in `color_space`.
+fn set_layout_color(paint: &mut Paint, color: Color, color_space: ColorSpaceOption) {
+    paint.set_color4f(Color4f::from(color), color_space.to_skia().as_ref());
+}
+
+/* a colour as bytes, how draw calls are told apart in the paint log */
+type Argb = (u8, u8, u8, u8);
+
+// How far an arrow key scrolls, in logical pixels.
+const KEY_SCROLL_LINE: f32 = 40.0;
 
 #[allow(dead_code)]
 pub fn pos_exp_clamp(v: f32, f: f32, max: f32, k: f32, dt: Duration) -> f32 {
@@ -33,9 +55,10 @@ pub fn pos_exp_clamp(v: f32, f: f32, max: f32, k: f32, dt: Duration) -> f32 {
 }
 
 // :::::::-------- Third Pass, Draw ------ :::::
-struct DrawIntepreter<'a, F>
+struct DrawIntepreter<'a, F, W>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
+    W: WindowLike,
 {
     config: StaticConfig,
     state: &'a mut VMState,
@@ -43,85 +66,149 @@ where
 
     font_family: String,
     font_size: f32,
+    parent_font_size: f32, /* see `TextLayoutIntepreter` */
+    pixel_snap_text: bool,
+    tab_width: usize, /* for `TextAt`, the text pass has its own for `Text` */
+    white_space: StoredWhiteSpace, /* likewise */
+    outline_dash: Option<(f32, f32)>, /* `OutlineDash`, solid if `None` */
+    text_run_color: Option<Color>, /* `TextColorRun`, where it starts is in the node's context */
+    gradient: Option<([f32; 4], Color, Color)>, /* `LinearGradient` on the pencil, for `paint_key` */
+    corner_radii: [Option<f32>; 4], /* `BorderRadius`, `None` keeps the `RoundedRect`'s radius */
 
     paint: Paint,
     canvas: &'a Canvas,
-    window: Arc<Window>,
+    font_context: &'a mut FontContext, /* for `TextAt`, all other text was laid out before */
+    layout_context: &'a mut parley::LayoutContext<()>,
+    window: Arc<W>,
     is_hovered: bool,
+    is_focused: bool, /* the keyboard focus is on the element or one inside it */
+    is_key_scrolled: bool, /* the arrow keys scroll it, see `key_scroller` */
+    is_captured: bool, /* holds the pointer, set in `handle_enter` */
+    was_captured: bool, /* held it last frame, for the release */
+    entered: bool,    /* the node's own `Enter` ran, see `handle_enter` */
+    child_scopes: Vec<Option<Scopes>>, /* at each child's `Enter`, see `draw_pass` */
 
     x: f32,
     y: f32,
+    top: f32, /* of the box, `y` moves with the scrolling */
     width: f32,
-    #[allow(dead_code)]
     height: f32,
 
     cb_push_evt: F,
 
     input_state: InputState,
-    frame_state: &'a HashMap<*const u8, CarriedState>,
-    next_frame_state: &'a mut HashMap<*const u8, CarriedState>,
+    frame_state: &'a HashMap<StateKey, CarriedState>,
+    next_frame_state: &'a mut HashMap<StateKey, CarriedState>,
 
     tree: &'a TaffyTree<LayoutContext>,
     node: NodeId,
 
     maybe_active_path: Option<Path>,
+    show_if_i: usize,
+    paint_recorder: PaintRecorder,
 }
 
-impl<'a, F> DrawIntepreter<'a, F>
+impl<'a, F, W> DrawIntepreter<'a, F, W>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
+    W: WindowLike,
 {
     fn new(
-        window: Arc<Window>,
+        window: Arc<W>,
         canvas: &'a Canvas,
+        font_context: &'a mut FontContext,
+        layout_context: &'a mut parley::LayoutContext<()>,
         x: f32,
         y: f32,
+        parent_font_size: f32,
         tree: &'a TaffyTree<LayoutContext>,
         node: NodeId,
         state: &'a mut VMState,
         cb_push_evt: F,
         regions: Vec<(*const u8, *const u8)>,
-        frame_state: &'a HashMap<*const u8, CarriedState>,
-        next_frame_state: &'a mut HashMap<*const u8, CarriedState>,
+        frame_state: &'a HashMap<StateKey, CarriedState>,
+        next_frame_state: &'a mut HashMap<StateKey, CarriedState>,
         input_state: &InputState,
+        hovered: &HashSet<NodeId>,
+        focused: &HashSet<NodeId>,
+        key_scrolled: Option<NodeId>,
         config: StaticConfig,
     ) -> Result<Self> {
         let mut paint = Paint::default();
         paint.set_anti_alias(true);
 
         let layout = tree.get_final_layout(node);
-
-        let is_hovered = input_state.cursor_pos.x < (x + layout.size.width) as f64
-            && input_state.cursor_pos.x > x as f64
-            && input_state.cursor_pos.y < (y + layout.size.height) as f64
-            && input_state.cursor_pos.y > y as f64;
+        let is_hovered = hovered.contains(&node); /* see `hit_pass` */
+        let is_focused = focused.contains(&node); /* see `focus_pass` */
 
         Ok(Self {
             window,
             paint,
             x,
             y,
+            top: y,
             cb_push_evt,
             width: layout.size.width,
             height: layout.size.height,
             config,
             is_hovered,
+            is_focused,
+            is_key_scrolled: key_scrolled == Some(node),
+            is_captured: false,
+            was_captured: false,
+            entered: false,
+            child_scopes: Vec::new(),
             state,
             cursor: RaggedCursor::new(regions)?,
             canvas,
+            font_context,
+            layout_context,
             frame_state,
             next_frame_state,
             input_state: input_state.clone(),
 
             font_family: String::from("Arial"),
-            font_size: config.base_font_size(),
+            pixel_snap_text: false,
+            tab_width: 8,
+            white_space: StoredWhiteSpace::Preserve,
+            outline_dash: None,
+            text_run_color: None,
+            gradient: None,
+            corner_radii: [None; 4],
+            font_size: parent_font_size,
+            parent_font_size,
 
             tree,
             node,
             maybe_active_path: None,
+            show_if_i: 0,
+            paint_recorder: PaintRecorder::new(),
         })
     }
 
+    /// Everything that decides the look of a draw call besides its geometry.
+    fn paint_key(&self) -> (Argb, Option<([u32; 4], Argb, Argb)>, u32) {
+        let argb = |color: Color| (color.a(), color.r(), color.g(), color.b());
+        let gradient = self
+            .gradient
+            .map(|(points, from, to)| (f32_bits(points), argb(from), argb(to)));
+        (
+            argb(self.paint.color()),
+            gradient,
+            self.paint.stroke_width().to_bits(),
+        )
+    }
+
+    /// How far past its geometry a shape drawn with the pencil can reach, half of the stroke
+    /// and more where a sharp corner's miter sticks out.
+    fn stroke_outset(&self) -> (f32, f32) {
+        let outset = match self.paint.style() {
+            PaintStyle::Fill => 0.0,
+            _ => self.paint.stroke_width() / 2.0 * self.paint.stroke_miter().max(1.0),
+        };
+        (outset, outset)
+    }
+
     fn get_node_ctx(&self) -> Result<&LayoutContext> {
         let ctx = self
             .tree
@@ -133,11 +220,33 @@ where
     fn get_node_layout(&self) -> &taffy::Layout {
         self.tree.get_final_layout(self.node)
     }
+
+    /// The state jumps: whether the jump's condition held is latched for the next frame, and the
+    /// jump is taken unless it held in the last one.
+    fn state_jump(&mut self, rel_ptr: usize, active: bool) {
+        let ctx = self.tree.get_node_context(self.node);
+        let key = StateKey::of_jump(
+            ctx.and_then(|ctx| ctx.state_key),
+            ctx.and_then(|ctx| ctx.ragged_members.first())
+                .map_or(self.cursor.cursor, |(enter, _)| *enter),
+            self.cursor.cursor,
+        );
+        if active {
+            self.next_frame_state
+                .entry(key)
+                .or_insert(CarriedState::new())
+                .is_jmp = true;
+        }
+        if !self.frame_state.get(&key).is_some_and(|x| x.is_jmp) {
+            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
+        }
+    }
 }
 
-impl<'a, F> Executor<VMState, RaggedCursor, StaticConfig> for DrawIntepreter<'a, F>
+impl<'a, F, W> Executor<VMState, RaggedCursor, StaticConfig> for DrawIntepreter<'a, F, W>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
+    W: WindowLike,
 {
     fn get_config(&self) -> StaticConfig {
         self.config
@@ -152,28 +261,80 @@ where
     }
 }
 
-impl<'a, F> Intepreter for DrawIntepreter<'a, F>
+impl<'a, F, W> Intepreter for DrawIntepreter<'a, F, W>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
+    W: WindowLike,
 {
     fn handle_enter(&mut self) -> Result<()> {
+        // A node's regions run up to and including its children's `Enter`, only the first
+        // `Enter` is the node's own. Handling the others would scroll and clip it again.
+        if self.entered {
+            self.child_scopes.push(self.state.call_scopes());
+            return Ok(());
+        }
+        self.entered = true;
+
+        // `Opacity` fades the whole element, whatever it draws before the tag too, so the layer
+        // opens before anything is drawn. It is blended in as a whole, so overlapping children
+        // don't show through each other the way they would with every paint faded on its own.
+        // `draw_pass` restores it after the children.
+        if let Some(opacity) = self.get_node_ctx()?.opacity {
+            self.canvas.save_layer_alpha_f(None, opacity);
+            // The children's paint doesn't change with it, so all they could be drawn over repaints.
+            let bounds = Rect::from_xywh(self.x, self.top, self.width, self.height);
+            self.paint_recorder.note(
+                self.canvas.local_clip_bounds().unwrap_or(bounds),
+                ("opacity", f32_bits([opacity])),
+            );
+        }
+
         /* We are handling scrolling here. */
-        let desired_height = self.get_node_layout().size.height.max(
+        // An element scrolls when its content, its text or its children, is taller than its own
+        // box. The offset moves everything the element and its children draw, and all of it is
+        // clipped to the box (`draw_pass` undoes the clip once the children are drawn).
+        let layout = self.get_node_layout();
+        let box_height = layout.size.height;
+        let content_height = layout.content_size.height.max(
             self.get_node_ctx()?
                 .maybe_font_layout
                 .as_ref()
                 .map(|x| x.height())
                 .unwrap_or(0.0),
         );
-        let window_size = self.window.inner_size();
-        let window_height = window_size.height as f32;
+        let key = self
+            .get_node_ctx()?
+            .state_key
+            .unwrap_or(StateKey::Ptr(self.cursor.cursor));
         let mut state = self
             .frame_state
-            .get(&self.cursor.cursor)
+            .get(&key)
             .cloned()
             .unwrap_or(CarriedState::new());
+        // Only a `ColorTransition` the element still runs keeps its fade, one it dropped mustn't
+        // keep the frame animating.
+        state.color = None;
 
-        if desired_height > window_height {
+        // Pointer capture: the elements under the pointer when the button goes down keep the
+        // press until it comes up again, even once the pointer leaves them, so a drag handle
+        // doesn't slip when the mouse moves faster than it does. Other elements the pointer
+        // passes over in the meantime don't get pressed.
+        self.was_captured = state.captured;
+        self.is_captured = self.input_state.mouse_down
+            && if self.input_state.mouse_just_pressed {
+                self.is_hovered
+            } else {
+                state.captured
+            };
+        state.captured = self.is_captured;
+
+        if content_height > box_height {
+            self.canvas.save();
+            self.canvas.clip_rect(
+                Rect::from_xywh(self.x, self.y, self.width, box_height),
+                None,
+                Some(true),
+            );
             self.y += state.scroll_y;
             if self.is_hovered {
                 // if self.input_state.scroll_action.1 < 0.0 && state.scroll_y <= 0.0 {
@@ -198,12 +359,18 @@ where
                 // ^^^^ this implemnnts rubber banding around the edges and works but there is weird jumoy ness that comes from winit animations I think...
 
                 state.scroll_y += self.input_state.scroll_action.1;
-                state.scroll_y = state.scroll_y.clamp(-(desired_height - window_height), 0.0);
             }
+            if self.is_key_scrolled {
+                state.scroll_y += self.input_state.key_scroll(
+                    KEY_SCROLL_LINE * self.config.display_scale(),
+                    box_height * 0.9, /* keep a bit of the last page in view */
+                );
+            }
+            state.scroll_y = state.scroll_y.clamp(-(content_height - box_height), 0.0);
         } else {
             state.scroll_y = 0.0;
         }
-        self.next_frame_state.insert(self.cursor.cursor, state);
+        self.next_frame_state.insert(key, state);
 
         Ok(())
     }
@@ -215,13 +382,22 @@ where
         w: taffy::LengthPercentageAuto,
         h: taffy::LengthPercentageAuto,
     ) -> Result<()> {
+        /* a "Frac" of the element's width across and of its height down, like the other shapes */
         let x = resolve_taffy_length(x, self.width);
-        let y = resolve_taffy_length(y, self.width);
+        let y = resolve_taffy_length(y, self.height);
         let w = resolve_taffy_length(w, self.width);
-        let h = resolve_taffy_length(h, self.width);
+        let h = resolve_taffy_length(h, self.height);
 
         let rect = Rect::from_xywh(x + self.x, y + self.y, w, h);
         self.canvas.draw_rect(rect, &self.paint);
+        self.paint_recorder.note(
+            rect.with_outset(self.stroke_outset()),
+            (
+                "rect",
+                f32_bits([rect.left, rect.top, rect.right, rect.bottom]),
+                self.paint_key(),
+            ),
+        );
         Ok(())
     }
 
@@ -247,6 +423,24 @@ where
             },
         );
 
+        // Per corner radii from `BorderRadius`, Skia shrinks them when they don't fit the sides.
+        if self.corner_radii.iter().any(Option::is_some) {
+            let radii = self.corner_radii.map(|corner| corner.unwrap_or(r));
+            let rect = Rect::from_xywh(x, y, width, height);
+            let rrect = RRect::new_rect_radii(rect, &radii.map(|r| Vector::new(r, r)));
+            self.canvas.draw_rrect(rrect, &self.paint);
+            self.paint_recorder.note(
+                rect.with_outset(self.stroke_outset()),
+                (
+                    "border_radius",
+                    f32_bits([x, y, width, height]),
+                    f32_bits(radii),
+                    self.paint_key(),
+                ),
+            );
+            return Ok(());
+        }
+
         let mut path = Path::new();
         path.move_to((x + r, y));
         path.line_to((x + width - r, y));
@@ -259,80 +453,273 @@ where
         path.arc_to_tangent((x, y), (x + r, y), r);
         path.close();
         self.canvas.draw_path(&path, &self.paint);
+        self.paint_recorder.note(
+            Rect::from_xywh(x, y, width, height).with_outset(self.stroke_outset()),
+            (
+                "rounded_rect",
+                f32_bits([x, y, width, height, r]),
+                self.paint_key(),
+            ),
+        );
+        Ok(())
+    }
+
+    fn handle_clip_rounded_rect(&mut self, radius: taffy::LengthPercentage) -> Result<()> {
+        // The clip is the box, which stays put while the content of a scrolling element moves
+        // inside it. A "Frac" radius is of the shorter side, and no corner gets rounder than
+        // half of it. `draw_pass` takes the clip off again after the children.
+        let bounds = Rect::from_xywh(self.x, self.top, self.width, self.height);
+        let short_side = self.width.min(self.height);
+        let r = resolve_taffy_length(radius, short_side).min(short_side / 2.0);
+        self.canvas.save();
+        self.canvas
+            .clip_rrect(RRect::new_rect_xy(bounds, r, r), None, Some(true));
+        self.paint_recorder.note(
+            bounds,
+            (
+                "clip_rounded_rect",
+                f32_bits([bounds.left, bounds.top, bounds.right, bounds.bottom, r]),
+            ),
+        );
+        Ok(())
+    }
+
+    fn handle_outline(
+        &mut self,
+        width: taffy::LengthPercentage,
+        color: Color,
+        offset: taffy::LengthPercentage,
+    ) -> Result<()> {
+        let size = self.get_node_layout().size;
+        let width = resolve_taffy_length(width, size.width);
+        let offset = resolve_taffy_length(offset, size.width);
+
+        // Skia centres strokes on the path, so push it out by half the width to keep the ring outside the box.
+        let grow = offset + width / 2.0;
+        let rect = Rect::from_xywh(
+            self.x - grow,
+            self.y - grow,
+            size.width + 2.0 * grow,
+            size.height + 2.0 * grow,
+        );
+
+        /* a paint of its own so the pencil colour of the element is left alone */
+        let mut paint = Paint::default();
+        paint.set_anti_alias(true);
+        paint.set_style(PaintStyle::Stroke);
+        paint.set_stroke_width(width);
+        set_layout_color(&mut paint, color, self.config.color_space());
+        if let Some((dash, gap)) = self.outline_dash {
+            paint.set_path_effect(PathEffect::dash(&[dash, gap], 0.0));
+        }
+        self.canvas.draw_rect(rect, &paint);
+        self.paint_recorder.note(
+            rect.with_outset((width / 2.0, width / 2.0)),
+            (
+                "outline",
+                f32_bits([rect.left, rect.top, rect.right, rect.bottom, width]),
+                self.outline_dash.map(|(dash, gap)| f32_bits([dash, gap])),
+                (color.a(), color.r(), color.g(), color.b()),
+            ),
+        );
+        Ok(())
+    }
+
+    fn handle_outline_dash(
+        &mut self,
+        dash: taffy::LengthPercentage,
+        gap: taffy::LengthPercentage,
+    ) -> Result<()> {
+        let width = self.get_node_layout().size.width;
+        let dash = resolve_taffy_length(dash, width);
+        let gap = resolve_taffy_length(gap, width);
+        self.outline_dash = (dash > 0.0 && gap > 0.0).then_some((dash, gap));
         Ok(())
     }
 
     fn handle_pencil_color(&mut self, color: Color) -> Result<()> {
-        self.paint.set_color(color);
+        set_layout_color(&mut self.paint, color, self.config.color_space());
+        self.paint.set_shader(None); /* a plain colour replaces a gradient */
+        self.gradient = None;
+        Ok(())
+    }
+
+    fn handle_stroke_width(&mut self, width: taffy::LengthPercentage) -> Result<()> {
+        let width = resolve_taffy_length(width, self.get_node_layout().size.width);
+        // Zero goes back to filling, a zero width stroke would be a hairline instead.
+        if width > 0.0 {
+            self.paint.set_style(PaintStyle::Stroke);
+            self.paint.set_stroke_width(width);
+        } else {
+            self.paint.set_style(PaintStyle::Fill);
+            self.paint.set_stroke_width(0.0);
+        }
         Ok(())
     }
 
+    fn handle_border_radius(&mut self, corners: [taffy::LengthPercentageAuto; 4]) -> Result<()> {
+        /* a "Frac" radius is of the element's width, like the other lengths across */
+        let width = self.get_node_layout().size.width;
+        self.corner_radii = corners.map(|corner| {
+            (corner != taffy::LengthPercentageAuto::auto())
+                .then(|| resolve_taffy_length(corner, width).max(0.0))
+        });
+        Ok(())
+    }
+
+    fn handle_linear_gradient(
+        &mut self,
+        x0: taffy::LengthPercentage,
+        y0: taffy::LengthPercentage,
+        x1: taffy::LengthPercentage,
+        y1: taffy::LengthPercentage,
+        from: Color,
+        to: Color,
+    ) -> Result<()> {
+        let size = self.get_node_layout().size;
+        let points = [
+            self.x + resolve_taffy_length(x0, size.width),
+            self.y + resolve_taffy_length(y0, size.height),
+            self.x + resolve_taffy_length(x1, size.width),
+            self.y + resolve_taffy_length(y1, size.height),
+        ];
+        let colors = [Color4f::from(from), Color4f::from(to)];
+        let color_space = self.config.color_space().to_skia();
+        // The shader stays on the pencil whatever its style, so fills and strokes both get it.
+        let Some(shader) = skia_safe::Shader::linear_gradient(
+            ((points[0], points[1]), (points[2], points[3])),
+            skia_safe::gradient_shader::GradientShaderColors::ColorsInSpace(&colors, color_space),
+            None,
+            skia_safe::TileMode::Clamp,
+            None,
+            None,
+        ) else {
+            return Ok(()); /* both points are the same, the pencil stays as it is */
+        };
+        self.paint.set_color(Color::BLACK); /* its alpha is multiplied into the gradient's */
+        self.paint.set_shader(shader);
+        self.gradient = Some((points, from, to));
+        Ok(())
+    }
+
+    fn handle_color_transition(&mut self, duration: Duration, color: Color) -> Result<()> {
+        // Kept with the element's other state, so with a "Key" the fade survives the element
+        // moving in the file. `handle_enter` already put the entry there.
+        let Some(key) = self.get_node_ctx()?.state_key else {
+            return self.handle_pencil_color(color);
+        };
+        let fade = if self.config.reduce_motion() {
+            ColorFade::settled(color)
+        } else {
+            let last = self.frame_state.get(&key).and_then(|x| x.color);
+            ColorFade::next(last, color, duration, self.config.get_dt())
+        };
+        self.next_frame_state
+            .entry(key)
+            .or_insert(CarriedState::new())
+            .color = Some(fade);
+        self.handle_pencil_color(fade.color(duration))
+    }
+
     fn handle_hover(&mut self, rel_ptr: usize) -> Result<()> {
         // if we are NOT hovered we want to execute the jump to ptr, otherwise continue (do nothing)
         // this way the hover state is the one right after the tag
-        if self.is_hovered {
-            self.next_frame_state
-                .entry(self.cursor.cursor)
-                .or_insert(CarriedState::new())
-                .is_jmp = true;
-        }
-
-        if !self
-            .frame_state
-            .get(&self.cursor.cursor)
-            .map(|x| &x.is_jmp)
-            .unwrap_or(&false)
-        {
-            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
-        }
+        self.state_jump(rel_ptr, self.is_hovered);
         Ok(())
     }
 
     fn handle_cursor(&mut self, cursor: CursorIcon) -> Result<()> {
-        self.window.set_cursor(cursor);
+        // Only the elements under the pointer get a say, inner ones are drawn later and win.
+        if self.is_hovered {
+            self.window.set_cursor(cursor);
+        }
         Ok(())
     }
 
     fn handle_event(&mut self, id: usize) -> Result<()> {
-        self.cb_push_evt.clone()(id);
+        self.cb_push_evt.clone()(FiredEvent::Id(id));
         Ok(())
     }
 
-    fn handle_mouse_pressed(&mut self, rel_ptr: usize) -> Result<()> {
-        if self.is_hovered && self.input_state.mouse_down {
-            self.next_frame_state
-                .entry(self.cursor.cursor)
-                .or_insert(CarriedState::new())
-                .is_jmp = true;
-        }
+    fn handle_named_event(&mut self, name: &str) -> Result<()> {
+        self.cb_push_evt.clone()(FiredEvent::Named(String::from(name)));
+        Ok(())
+    }
 
-        if !self
+    fn handle_on_scroll_report(&mut self, id: usize) -> Result<()> {
+        // `handle_enter` already scrolled for this frame, so compare against the last one.
+        let Some(key) = self.get_node_ctx()?.state_key else {
+            return Ok(());
+        };
+        let before = self
             .frame_state
-            .get(&self.cursor.cursor)
-            .map(|x| &x.is_jmp)
-            .unwrap_or(&false)
-        {
-            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
+            .get(&key)
+            .map(|x| x.scroll_y)
+            .unwrap_or(0.0);
+        let now = self
+            .next_frame_state
+            .get(&key)
+            .map(|x| x.scroll_y)
+            .unwrap_or(0.0);
+        if now != before {
+            self.cb_push_evt.clone()(FiredEvent::Scroll {
+                id,
+                scroll_y: -now / self.config.display_scale(), /* scrolling down is negative */
+            });
         }
         Ok(())
     }
 
+    fn handle_mouse_pressed(&mut self, rel_ptr: usize) -> Result<()> {
+        self.state_jump(rel_ptr, self.is_captured);
+        Ok(())
+    }
+
     fn handle_clicked(&mut self, rel_ptr: usize) -> Result<()> {
-        if self.is_hovered && self.input_state.mouse_just_released {
-            self.next_frame_state
-                .entry(self.cursor.cursor)
-                .or_insert(CarriedState::new())
-                .is_jmp = true;
-        }
+        // Only if the press started here too, which may have been in this very frame.
+        let pressed_here = self.was_captured || self.input_state.mouse_just_pressed;
+        self.state_jump(
+            rel_ptr,
+            self.is_hovered && pressed_here && self.input_state.mouse_just_released,
+        );
+        Ok(())
+    }
 
-        if !self
-            .frame_state
-            .get(&self.cursor.cursor)
-            .map(|x| &x.is_jmp)
-            .unwrap_or(&false)
-        {
-            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
-        }
+    fn handle_focus_visible(&mut self, rel_ptr: usize) -> Result<()> {
+        // Like `:focus-visible`, which element has focus is up to the client but a focus ring
+        // should only show while the keyboard is being used, not right after a click.
+        self.state_jump(rel_ptr, self.input_state.keyboard_modality);
+        Ok(())
+    }
+
+    fn handle_button_pressed(&mut self, rel_ptr: usize, button: usize) -> Result<()> {
+        // Held on any gamepad, unlike the pointer states this doesn't depend on the element.
+        self.state_jump(rel_ptr, self.input_state.gamepad_button_down(button));
+        Ok(())
+    }
+
+    fn handle_key_down(&mut self, rel_ptr: usize, key: usize) -> Result<()> {
+        // Keys go to the focused element and bubble out through the elements it is in.
+        self.state_jump(
+            rel_ptr,
+            self.is_focused && self.input_state.key_went_down(key),
+        );
+        Ok(())
+    }
+
+    fn handle_key_up(&mut self, rel_ptr: usize, key: usize) -> Result<()> {
+        self.state_jump(
+            rel_ptr,
+            self.is_focused && self.input_state.key_went_up(key),
+        );
+        Ok(())
+    }
+
+    fn handle_key_press(&mut self, rel_ptr: usize, ch: char) -> Result<()> {
+        self.state_jump(
+            rel_ptr,
+            self.is_focused && self.input_state.text_typed.contains(&ch),
+        );
         Ok(())
     }
 
@@ -346,30 +733,143 @@ where
         Ok(())
     }
 
+    fn handle_show_if(&mut self, rel_ptr: usize, _condition: TaggedWord) -> Result<()> {
+        let show = *self
+            .get_node_ctx()?
+            .show_if
+            .get(self.show_if_i)
+            .ok_or(anyhow!("`ShowIf` wasn't seen by the layout pass"))?;
+        self.show_if_i += 1;
+        if !show {
+            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
+        }
+        Ok(())
+    }
+
     fn handle_text(
         &mut self,
         x: taffy::LengthPercentage,
         y: taffy::LengthPercentage,
-        _txt: &str,
+        txt: &str,
     ) -> Result<()> {
         let ctx = self
             .tree
             .get_node_context(self.node)
             .ok_or(anyhow!("all nodes need to have context"))?;
         let layout = self.tree.get_final_layout(self.node);
+        let font_layout = ctx.maybe_font_layout.as_ref().ok_or(anyhow!(
+            "Somehow trying to draw font node without corresponding layout"
+        ))?;
+        let x = resolve_taffy_length(x, layout.size.width) + self.x;
+        let y = resolve_taffy_length(y, layout.size.height) + self.y;
+        // The box has room above the text for a shift up, see `TextLayoutIntepreter`, so
+        // unshifted text starts below it.
+        let text_y = y + ctx.text_shift.max(0.0);
+        let color_run = ctx
+            .text_color_split
+            .zip(self.text_run_color)
+            .map(|(at, color)| {
+                let mut run_paint = self.paint.clone();
+                set_layout_color(&mut run_paint, color, self.config.color_space());
+                run_paint.set_shader(None);
+                run_paint.set_style(PaintStyle::Fill);
+                (at, run_paint)
+            });
 
         draw_text(
-            ctx.maybe_font_layout.as_ref().ok_or(anyhow!(
-                "Somehow trying to draw font node without corresponding layout"
-            ))?,
-            resolve_taffy_length(x, layout.size.width) + self.x,
-            resolve_taffy_length(y, layout.size.height) + self.y,
+            font_layout,
+            x,
+            text_y,
             &self.canvas,
             &self.paint,
             &self.font_family,
             self.font_size,
+            ctx.text_shift,
+            self.pixel_snap_text,
+            color_run.as_ref().map(|(at, run_paint)| (*at, run_paint)),
             self.config.display_scale(),
         )?;
+        self.paint_recorder.note(
+            Rect::from_xywh(
+                x,
+                y,
+                font_layout.width(),
+                font_layout.height() + ctx.text_shift.abs(),
+            ),
+            (
+                "text",
+                txt,
+                &self.font_family,
+                f32_bits([
+                    x,
+                    y,
+                    font_layout.width(),
+                    font_layout.height(),
+                    self.font_size,
+                    ctx.text_shift,
+                ]),
+                self.pixel_snap_text,
+                color_run
+                    .as_ref()
+                    .map(|(at, run_paint)| (*at, run_paint.color().to_u32())),
+                self.paint_key(),
+            ),
+        );
+        Ok(())
+    }
+
+    fn handle_text_at(
+        &mut self,
+        x: taffy::LengthPercentage,
+        y: taffy::LengthPercentage,
+        max_width: taffy::LengthPercentage,
+        txt: &str,
+    ) -> Result<()> {
+        // Laid out right here since the text pass never saw it, with the element's font but
+        // none of the settings only the text pass keeps, like the alignment.
+        let layout = self.tree.get_final_layout(self.node);
+        let x = resolve_taffy_length(x, layout.size.width) + self.x;
+        let y = resolve_taffy_length(y, layout.size.height) + self.y;
+        let max_width = resolve_taffy_length(max_width, layout.size.width);
+        let font_layout = layout_text(
+            txt,
+            max_width,
+            parley::Alignment::Start,
+            self.font_context,
+            self.layout_context,
+            &self.font_family,
+            self.font_size,
+            self.tab_width,
+            self.white_space,
+            WordBreakStrength::Normal,
+            &[],
+            self.config.display_scale(),
+        );
+
+        draw_text(
+            &font_layout,
+            x,
+            y,
+            &self.canvas,
+            &self.paint,
+            &self.font_family,
+            self.font_size,
+            0.0,
+            self.pixel_snap_text,
+            None,
+            self.config.display_scale(),
+        )?;
+        self.paint_recorder.note(
+            Rect::from_xywh(x, y, font_layout.width(), font_layout.height()),
+            (
+                "text_at",
+                txt,
+                &self.font_family,
+                f32_bits([x, y, max_width, self.font_size]),
+                self.pixel_snap_text,
+                self.paint_key(),
+            ),
+        );
         Ok(())
     }
 
@@ -493,12 +993,44 @@ where
         Ok(())
     }
 
+    fn handle_line(
+        &mut self,
+        x0: taffy::LengthPercentage,
+        y0: taffy::LengthPercentage,
+        x1: taffy::LengthPercentage,
+        y1: taffy::LengthPercentage,
+    ) -> Result<()> {
+        let layout = self.tree.get_final_layout(self.node);
+        let x0 = self.x + resolve_taffy_length(x0, layout.size.width);
+        let y0 = self.y + resolve_taffy_length(y0, layout.size.height);
+        let x1 = self.x + resolve_taffy_length(x1, layout.size.width);
+        let y1 = self.y + resolve_taffy_length(y1, layout.size.height);
+
+        /* skia strokes lines whatever the style, without a "StrokeWidth" that's a hairline */
+        self.canvas.draw_line((x0, y0), (x1, y1), &self.paint);
+        let bounds = Rect::new(x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1));
+        let outset = 1f32.max(self.paint.stroke_width() / 2.0);
+        self.paint_recorder.note(
+            bounds.with_outset((outset, outset)),
+            ("line", f32_bits([x0, y0, x1, y1]), self.paint_key()),
+        );
+        Ok(())
+    }
+
     fn handle_end_path(&mut self) -> Result<()> {
         let path = self
             .maybe_active_path
             .take()
             .ok_or(anyhow!("No active path"))?;
         self.canvas.draw_path(&path, &self.paint);
+        let points: Vec<[u32; 2]> = (0..path.count_points())
+            .filter_map(|i| path.get_point(i))
+            .map(|p| f32_bits([p.x, p.y]))
+            .collect();
+        self.paint_recorder.note(
+            path.bounds().with_outset(self.stroke_outset()),
+            ("path", points, path.count_verbs(), self.paint_key()),
+        );
         Ok(())
     }
 
@@ -507,42 +1039,83 @@ where
         Ok(())
     }
 
+    fn handle_font_size_em(&mut self, factor: f32) -> Result<()> {
+        self.font_size = self.parent_font_size * factor;
+        Ok(())
+    }
+
+    fn handle_text_color_run(&mut self, _offset: usize, color: Color) -> Result<()> {
+        self.text_run_color = Some(color);
+        Ok(())
+    }
+
+    fn handle_pixel_snap_text(&mut self, snap: bool) -> Result<()> {
+        self.pixel_snap_text = snap;
+        Ok(())
+    }
+
+    fn handle_tab_width(&mut self, columns: usize) -> Result<()> {
+        self.tab_width = columns;
+        Ok(())
+    }
+
+    fn handle_white_space(&mut self, white_space: StoredWhiteSpace) -> Result<()> {
+        self.white_space = white_space;
+        Ok(())
+    }
+
     fn handle_font_family(&mut self, font_desc: &str) -> Result<()> {
         self.font_family = String::from(font_desc);
         Ok(())
     }
 }
 
-pub(super) fn draw_pass<F>(
-    window: Arc<Window>,
+pub(super) fn draw_pass<F, W>(
+    window: Arc<W>,
     canvas: &Canvas,
+    font_context: &mut FontContext,
+    layout_context: &mut parley::LayoutContext<()>,
     px: f32,
     py: f32,
+    parent_font_size: f32,
     vm_state: &mut VMState,
     tree: &TaffyTree<LayoutContext>,
     node: NodeId,
     cb_push_evt: F,
-    frame_state: &HashMap<*const u8, CarriedState>,
-    next_frame_state: &mut HashMap<*const u8, CarriedState>,
+    frame_state: &HashMap<StateKey, CarriedState>,
+    next_frame_state: &mut HashMap<StateKey, CarriedState>,
     input_state: &InputState,
+    hovered: &HashSet<NodeId>,
+    focused: &HashSet<NodeId>,
+    key_scrolled: Option<NodeId>,
     config: StaticConfig,
+    paint_log: &mut PaintLog,
 ) -> Result<()>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
+    W: WindowLike,
 {
+    // Taffy leaves a hidden element out of the layout but keeps it in the tree, its bytecode
+    // mustn't run either or it would still paint and fire events.
+    if tree.style(node)?.display == taffy::Display::None {
+        return Ok(());
+    }
     let layout = tree.get_final_layout(node);
-    let x = px + layout.location.x;
-    let y = py + layout.location.y;
-
     let ctx = tree
         .get_node_context(node)
         .ok_or(anyhow!("Each node in the taffy tree must have a context"))?;
+    let x = px + layout.location.x;
+    let y = py + layout.location.y + ctx.baseline_shift;
+
     let regions = ctx.ragged_members.clone();
     let mut intepreter = DrawIntepreter::new(
         window.clone(),
         canvas,
+        font_context,
+        layout_context,
         x,
         y,
+        parent_font_size,
         tree,
         node,
         vm_state,
@@ -551,18 +1124,37 @@ where
         frame_state,
         next_frame_state,
         input_state,
+        hovered,
+        focused,
+        key_scrolled,
         config,
     )?;
 
+    let save_count = canvas.save_count();
     let mut trace = Vec::new();
-    while let Some(_) = intepreter.advance(&mut trace)? {}
+    while let Some(_) = intepreter
+        .advance(&mut trace)
+        .with_context(|| PassTrace::new("Draw Pass", &trace, 10))?
+    {}
+    let y = intepreter.y; /* scrolled */
+    let font_size = intepreter.font_size;
+    paint_log.nodes.push(intepreter.paint_recorder.finish());
+    let mut child_scopes = std::mem::take(&mut intepreter.child_scopes).into_iter();
 
     for child in tree.child_ids(node) {
-        draw_pass(
+        // Like in `text_pass`, children entered in a library body see its registers.
+        let outer = child_scopes
+            .next()
+            .flatten()
+            .map(|scopes| vm_state.swap_scopes(scopes));
+        let out = draw_pass(
             window.clone(),
             canvas,
+            font_context,
+            layout_context,
             x,
             y,
+            font_size,
             vm_state,
             tree,
             child,
@@ -570,8 +1162,456 @@ where
             frame_state,
             next_frame_state,
             input_state,
+            hovered,
+            focused,
+            key_scrolled,
             config,
-        )?;
+            paint_log,
+        );
+        if let Some(outer) = outer {
+            vm_state.swap_scopes(outer);
+        }
+        out?;
     }
+    canvas.restore_to_count(save_count); /* the scroll clip, `ClipRoundedRect` and `Opacity` */
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use skia_safe::Color;
+    use winit::keyboard::KeyCode;
+    use winit::window::CursorIcon;
+
+    use crate::ui::draw::{DisplayOption, PassTrace, Tag};
+    use crate::ui::gamepad::{GamepadEvent, GamepadEventKind};
+    use crate::ui::testing::{Headless, Tree, Word, frac, px, real, rgb};
+
+    #[test]
+    fn a_dropped_color_transition_stops_animating() {
+        let tree = |tag: Tag, color: Word| {
+            let mut tree = Tree::new();
+            tree.enter()
+                .op(Tag::Width, &[px(40.0)])
+                .op(Tag::Height, &[px(40.0)])
+                .push((tag, 100))
+                .push(color)
+                .op(Tag::Rect, &[px(0.0), px(0.0), px(40.0), px(40.0)])
+                .leave();
+            tree
+        };
+        let mut headless = Headless::new(40, 40);
+
+        let frame = headless
+            .frame(&tree(Tag::ColorTransition, rgb(255, 0, 0)))
+            .unwrap();
+        assert!(
+            !frame.drawn.animating,
+            "the first frame starts at the colour"
+        );
+        let frame = headless
+            .frame(&tree(Tag::ColorTransition, rgb(0, 0, 255)))
+            .unwrap();
+        assert!(frame.drawn.animating);
+        let frame = headless.frame(&tree(Tag::Color, rgb(0, 0, 255))).unwrap();
+        assert!(!frame.drawn.animating);
+        assert_eq!(headless.pixel(20, 20), Color::from_rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn scrolls_a_box_smaller_than_the_window() {
+        // A 200px box with 600px of content: red, then green, then blue.
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[frac(1.0)])
+            .op(Tag::Height, &[frac(1.0)])
+            .enter()
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(200.0)])
+            .enter()
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(600.0)])
+            .op(Tag::Color, &[rgb(255, 0, 0)])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(200.0), px(100.0)])
+            .op(Tag::Color, &[rgb(0, 255, 0)])
+            .op(Tag::Rect, &[px(0.0), px(100.0), px(200.0), px(100.0)])
+            .op(Tag::Color, &[rgb(0, 0, 255)])
+            .op(Tag::Rect, &[px(0.0), px(200.0), px(200.0), px(400.0)])
+            .leave()
+            .leave()
+            .leave();
+        let mut headless = Headless::new(800, 800);
+        let red = Color::from_rgb(255, 0, 0);
+        let green = Color::from_rgb(0, 255, 0);
+        let blue = Color::from_rgb(0, 0, 255);
+
+        headless.move_to(100.0, 100.0);
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 20), red);
+        assert_eq!(headless.pixel(100, 250), Color::WHITE, "clipped to the box");
+
+        // The offset moves the content from the next frame on.
+        headless.scroll(-150.0).frame(&tree).unwrap();
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 20), green);
+
+        headless.scroll(-1000.0).frame(&tree).unwrap();
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 190), blue, "scrolled to the end");
+        assert_eq!(headless.pixel(100, 210), Color::WHITE);
+
+        // Outside the box the wheel does nothing to it.
+        headless
+            .move_to(500.0, 500.0)
+            .scroll(1000.0)
+            .frame(&tree)
+            .unwrap();
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 190), blue);
+    }
+
+    #[test]
+    fn text_at_uses_the_tab_width() {
+        // Where the ink of a tab and a bar starts.
+        let ink_from = |columns: usize| {
+            let mut tree = Tree::new();
+            let text = tree.string("\t|");
+            tree.enter()
+                .op(Tag::Width, &[px(300.0)])
+                .op(Tag::Height, &[px(40.0)])
+                .push((Tag::TabWidth, columns))
+                .op(
+                    Tag::TextAt,
+                    &[px(0.0), px(0.0), px(300.0), (Tag::TextPtr, text)],
+                )
+                .leave();
+            let mut headless = Headless::new(300, 40);
+            headless.frame(&tree).unwrap();
+            (0..300)
+                .find(|&x| (0..40).any(|y| headless.pixel(x, y) != Color::WHITE))
+                .unwrap()
+        };
+
+        assert!(ink_from(16) > ink_from(2) + 20);
+    }
+
+    #[test]
+    fn errors_say_where_they_happened() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::LineTo, &[px(10.0), px(10.0)]) /* without a path to add to */
+            .leave();
+        let mut headless = Headless::new(40, 40);
+
+        let err = headless.frame(&tree).unwrap_err();
+        let trace = err.downcast_ref::<PassTrace>().unwrap();
+        assert_eq!(trace.pass, "Draw Pass");
+        assert_eq!(trace.words.first().map(|w| w.0), Some(Tag::Enter));
+        assert_eq!(trace.words.last().map(|w| w.0), Some(Tag::LineTo));
+    }
+
+    #[test]
+    fn a_keyed_jump_survives_a_rewrite() {
+        // The element moves further into the tree when `shifted`, its box stays.
+        let tree = |key: Option<usize>, shifted: bool| {
+            let mut tree = Tree::new();
+            tree.enter();
+            if shifted {
+                tree.op(Tag::Width, &[frac(1.0)]);
+            }
+            tree.enter();
+            if let Some(key) = key {
+                tree.push((Tag::Key, key));
+            }
+            tree.op(Tag::Width, &[px(50.0)])
+                .op(Tag::Height, &[px(50.0)])
+                .jump(Tag::Hover, &[], |t| {
+                    t.push((Tag::Event, 7));
+                })
+                .leave()
+                .leave();
+            tree
+        };
+
+        let mut headless = Headless::new(100, 100);
+        headless.move_to(25.0, 25.0);
+        headless.frame(&tree(Some(5), false)).unwrap();
+        let frame = headless.frame(&tree(Some(5), true)).unwrap();
+        assert_eq!(frame.event_ids(), vec![7]);
+
+        let mut headless = Headless::new(100, 100);
+        headless.move_to(25.0, 25.0);
+        headless.frame(&tree(None, false)).unwrap();
+        let frame = headless.frame(&tree(None, true)).unwrap();
+        assert!(frame.event_ids().is_empty(), "found by where it was");
+    }
+
+    #[test]
+    fn arrow_keys_scroll_the_innermost_box() {
+        // A box scrolling a 100px list and a 300px block below it.
+        let mut tree = Tree::new();
+        tree.enter()
+            .push((Tag::Display, DisplayOption::Block as usize))
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(200.0)])
+            .enter()
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(100.0)])
+            .enter()
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(300.0)])
+            .op(Tag::Color, &[rgb(255, 0, 0)])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(200.0), px(40.0)])
+            .op(Tag::Color, &[rgb(0, 255, 0)])
+            .op(Tag::Rect, &[px(0.0), px(40.0), px(200.0), px(260.0)])
+            .leave()
+            .leave()
+            .enter()
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(300.0)])
+            .op(Tag::Color, &[rgb(0, 0, 0)])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(200.0), px(20.0)])
+            .op(Tag::Color, &[rgb(0, 0, 255)])
+            .op(Tag::Rect, &[px(0.0), px(20.0), px(200.0), px(280.0)])
+            .leave()
+            .leave();
+        let mut headless = Headless::new(400, 400);
+        let black = Color::from_rgb(0, 0, 0);
+
+        headless.move_to(100.0, 50.0).frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 20), Color::from_rgb(255, 0, 0));
+        headless.key(KeyCode::ArrowDown).frame(&tree).unwrap();
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 20), Color::from_rgb(0, 255, 0));
+        assert_eq!(headless.pixel(100, 110), black, "the outer box stays");
+
+        headless.move_to(100.0, 150.0);
+        headless.key(KeyCode::ArrowDown).frame(&tree).unwrap();
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(100, 110), Color::from_rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn keys_go_to_the_focused_element() {
+        const ENTER: usize = 0x28;
+        const ESCAPE: usize = 0x29;
+        let mut tree = Tree::new();
+        tree.enter()
+            .jump(Tag::KeyDown, &[(Tag::Array, ESCAPE)], |t| {
+                t.push((Tag::Event, 9));
+            });
+        for id in [1, 2] {
+            tree.enter()
+                .op(Tag::Width, &[px(50.0)])
+                .op(Tag::Height, &[px(50.0)])
+                .jump(Tag::KeyDown, &[(Tag::Array, ENTER)], |t| {
+                    t.push((Tag::Event, id));
+                })
+                .leave();
+        }
+        tree.leave();
+        let mut headless = Headless::new(200, 100);
+
+        headless.key(KeyCode::Enter).frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert!(frame.event_ids().is_empty(), "the root has the focus");
+        headless.key(KeyCode::Escape).frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.event_ids(), vec![9]);
+
+        headless.move_to(75.0, 25.0).press().frame(&tree).unwrap();
+        headless.release().frame(&tree).unwrap();
+        headless.key(KeyCode::Enter).frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.event_ids(), vec![2]);
+        headless.key(KeyCode::Escape).frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.event_ids(), vec![9], "keys bubble out to the root");
+    }
+
+    #[test]
+    fn opacity_fades_what_was_drawn_before_it() {
+        // Red on top, drawn before the tag, and a child's blue below. Both blend with the white
+        // behind the element.
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .op(Tag::Color, &[rgb(255, 0, 0)])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(40.0), px(20.0)])
+            .push(real(Tag::Opacity, 0.5))
+            .enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .op(Tag::Color, &[rgb(0, 0, 255)])
+            .op(Tag::Rect, &[px(0.0), px(20.0), px(40.0), px(20.0)])
+            .leave()
+            .leave();
+        let mut headless = Headless::new(40, 40);
+        headless.frame(&tree).unwrap();
+
+        let half = |c: u8| c.abs_diff(128) <= 1;
+        let red = headless.pixel(20, 10);
+        assert!(
+            red.r() == 255 && half(red.g()) && half(red.b()),
+            "{:?}",
+            red
+        );
+        let blue = headless.pixel(20, 30);
+        assert!(
+            half(blue.r()) && half(blue.g()) && blue.b() == 255,
+            "{:?}",
+            blue
+        );
+    }
+
+    #[test]
+    fn only_the_hovered_element_sets_the_cursor() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(200.0)])
+            .op(Tag::Height, &[px(100.0)])
+            .enter()
+            .op(Tag::Width, &[px(100.0)])
+            .op(Tag::Height, &[px(100.0)])
+            .op(Tag::CursorPointer, &[])
+            .leave()
+            .enter()
+            .op(Tag::Width, &[px(100.0)])
+            .op(Tag::Height, &[px(100.0)])
+            .leave()
+            .leave();
+        let mut headless = Headless::new(200, 100);
+
+        let frame = headless.move_to(150.0, 50.0).frame(&tree).unwrap();
+        assert!(frame.cursors.is_empty(), "{:?}", frame.cursors);
+        let frame = headless.move_to(50.0, 50.0).frame(&tree).unwrap();
+        assert_eq!(frame.cursors, vec![CursorIcon::Pointer]);
+    }
+
+    #[test]
+    fn a_hidden_element_draws_nothing() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .enter()
+            .push((Tag::Display, DisplayOption::None as usize))
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .push((Tag::Event, 1))
+            .enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .op(Tag::Color, &[rgb(255, 0, 0)])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(40.0), px(40.0)])
+            .push((Tag::Event, 2))
+            .leave()
+            .leave()
+            .leave();
+        let mut headless = Headless::new(40, 40);
+
+        let frame = headless.frame(&tree).unwrap();
+        assert!(frame.event_ids().is_empty(), "{:?}", frame.event_ids());
+        assert_eq!(headless.pixel(20, 20), Color::WHITE);
+    }
+
+    #[test]
+    fn strokes_a_rounded_rect_with_a_gradient() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(100.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .op(
+                Tag::LinearGradient,
+                &[
+                    px(0.0),
+                    px(0.0),
+                    px(100.0),
+                    px(0.0),
+                    rgb(255, 0, 0),
+                    rgb(0, 0, 255),
+                ],
+            )
+            .op(Tag::StrokeWidth, &[px(4.0)])
+            .op(
+                Tag::RoundedRect,
+                &[px(10.0), px(10.0), px(80.0), px(20.0), px(5.0)],
+            )
+            .leave();
+        let mut headless = Headless::new(100, 40);
+        headless.frame(&tree).unwrap();
+
+        // Red on the left side, blue on the right and the inside left unfilled.
+        let left = headless.pixel(10, 20);
+        assert!(left.r() > 200 && left.b() < 60, "{:?}", left);
+        let right = headless.pixel(90, 20);
+        assert!(right.b() > 200 && right.r() < 60, "{:?}", right);
+        assert_eq!(headless.pixel(50, 20), Color::WHITE);
+    }
+
+    #[test]
+    fn gamepad_buttons_jump_and_press_keys() {
+        const ENTER: usize = 0x28;
+        const HOME: usize = 16;
+        let mut tree = Tree::new();
+        tree.enter()
+            .jump(Tag::ButtonPressed, &[(Tag::Array, HOME)], |t| {
+                t.push((Tag::Event, 3));
+            })
+            .jump(Tag::KeyDown, &[(Tag::Array, ENTER)], |t| {
+                t.push((Tag::Event, 4));
+            })
+            .leave();
+        let mut headless = Headless::new(100, 100);
+        let pad = |kind| GamepadEvent { gamepad: 0, kind };
+
+        headless.input.gamepad(pad(GamepadEventKind::Pressed(HOME)));
+        headless.frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.event_ids(), vec![3], "held, and no key stands for it");
+
+        // A is Enter, for the focused element like the keyboard's.
+        headless
+            .input
+            .gamepad(pad(GamepadEventKind::Released(HOME)));
+        headless.input.gamepad(pad(GamepadEventKind::Pressed(0)));
+        headless.frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.event_ids(), vec![4]);
+    }
+
+    #[test]
+    fn border_radius_per_corner() {
+        let tree = |top_left: Word| {
+            let mut tree = Tree::new();
+            tree.enter()
+                .op(Tag::Width, &[px(100.0)])
+                .op(Tag::Height, &[px(40.0)])
+                .op(Tag::Color, &[rgb(255, 0, 0)])
+                .op(Tag::BorderRadius, &[top_left, px(0.0), px(0.0), px(0.0)])
+                .op(
+                    Tag::RoundedRect,
+                    &[px(0.0), px(0.0), px(100.0), px(40.0), px(0.0)],
+                )
+                .leave();
+            tree
+        };
+        let red = Color::from_rgb(255, 0, 0);
+        let mut headless = Headless::new(100, 40);
+
+        // No radius is a plain rect, right into the corners.
+        headless.frame(&tree(px(0.0))).unwrap();
+        for (x, y) in [(0, 0), (99, 0), (99, 39), (0, 39)] {
+            assert_eq!(headless.pixel(x, y), red, "({}, {})", x, y);
+        }
+
+        // 20% of the 100px width is 20px. Of the 40px height it would be 8px, which would
+        // cover (4, 4).
+        headless.frame(&tree(frac(0.2))).unwrap();
+        assert_eq!(headless.pixel(4, 4), Color::WHITE);
+        assert_eq!(headless.pixel(12, 12), red);
+        assert_eq!(headless.pixel(99, 0), red, "the other corners stay square");
+    }
+}
@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use skia_safe::Color;
+
+// ::: ---- Colours that fade from one frame's value to the next ---- :::
+// `ColorTransition` doesn't snap the pencil colour to a new value but moves it there over a
+// while. The fade is done in OKLab, where the halfway point of two colours looks halfway
+// between them, instead of going muddy or dark like a mix of the sRGB bytes does.
+
+/* How close to its target a fade has to be to count as there, in OKLab (about 0.1 of a byte) */
+const SETTLE_EPSILON: f32 = 5e-4;
+
+/// A colour on its way from `from` to `to`, both in OKLab with alpha last.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorFade {
+    from: [f32; 4],
+    to: [f32; 4],
+    elapsed: Duration,
+}
+impl ColorFade {
+    /// Already at `color`, nothing left to fade.
+    pub fn settled(color: Color) -> Self {
+        let lab = to_oklab(color);
+        Self {
+            from: lab,
+            to: lab,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// The fade for this frame. The last frame's fade goes on if it was heading to the same
+    /// `target`, otherwise a new one starts from wherever the colour was.
+    pub fn next(last: Option<ColorFade>, target: Color, duration: Duration, dt: Duration) -> Self {
+        let to = to_oklab(target);
+        let Some(last) = last else {
+            /* the element is new, nothing to fade from */
+            return Self::settled(target);
+        };
+        let fade = if last.to == to {
+            Self {
+                elapsed: last.elapsed + dt,
+                ..last
+            }
+        } else {
+            // Not `dt` in, after a quiet spell it would be a whole fade long.
+            Self {
+                from: last.value(duration),
+                to,
+                elapsed: Duration::ZERO,
+            }
+        };
+
+        let now = fade.value(duration);
+        let close = now
+            .iter()
+            .zip(to)
+            .all(|(a, b)| (a - b).abs() < SETTLE_EPSILON);
+        if close {
+            Self { from: to, ..fade }
+        } else {
+            fade
+        }
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.from == self.to
+    }
+
+    /// Where the colour is `elapsed` into a fade of `duration`.
+    fn value(&self, duration: Duration) -> [f32; 4] {
+        let t = if duration.is_zero() {
+            1.0
+        } else {
+            (self.elapsed.as_secs_f32() / duration.as_secs_f32()).clamp(0.0, 1.0)
+        };
+        let mut out = self.from;
+        for (x, to) in out.iter_mut().zip(self.to) {
+            *x += (to - *x) * t;
+        }
+        out
+    }
+
+    pub fn color(&self, duration: Duration) -> Color {
+        from_oklab(self.value(duration))
+    }
+}
+
+// The conversions are Björn Ottosson's, https://bottosson.github.io/posts/oklab/, in f64 since
+// the constants have more digits than an f32 holds.
+fn to_oklab(color: Color) -> [f32; 4] {
+    let r = srgb_to_linear(color.r());
+    let g = srgb_to_linear(color.g());
+    let b = srgb_to_linear(color.b());
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        (0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s) as f32,
+        (1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s) as f32,
+        (0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s) as f32,
+        color.a() as f32 / 255.0,
+    ]
+}
+
+fn from_oklab([lightness, a, b, alpha]: [f32; 4]) -> Color {
+    let (lightness, a, b) = (lightness as f64, a as f64, b as f64);
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    Color::from_argb(
+        (alpha.clamp(0.0, 1.0) * 255.0).round() as u8,
+        linear_to_srgb(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s),
+        linear_to_srgb(-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s),
+        linear_to_srgb(-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s),
+    )
+}
+
+fn srgb_to_linear(byte: u8) -> f64 {
+    let x = byte as f64 / 255.0;
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f64) -> u8 {
+    let x = x.clamp(0.0, 1.0);
+    let x = if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    };
+    (x * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FADE: Duration = Duration::from_millis(100);
+
+    fn near(a: Color, b: Color) -> bool {
+        [
+            (a.r(), b.r()),
+            (a.g(), b.g()),
+            (a.b(), b.b()),
+            (a.a(), b.a()),
+        ]
+        .iter()
+        .all(|(a, b)| a.abs_diff(*b) <= 1)
+    }
+
+    #[test]
+    fn oklab_round_trips() {
+        for color in [
+            Color::BLACK,
+            Color::WHITE,
+            Color::from_argb(128, 200, 30, 30),
+            Color::from_rgb(12, 150, 250),
+        ] {
+            assert!(near(from_oklab(to_oklab(color)), color), "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn halfway_is_perceptual() {
+        // Halfway from black to white is an OKLab lightness of 0.5, darker than the 128 a mix of
+        // the sRGB bytes gives.
+        let fade = ColorFade::next(
+            Some(ColorFade::settled(Color::BLACK)),
+            Color::WHITE,
+            FADE,
+            Duration::ZERO,
+        );
+        let fade = ColorFade::next(Some(fade), Color::WHITE, FADE, FADE / 2);
+        assert!(near(fade.color(FADE), Color::from_rgb(99, 99, 99)));
+    }
+
+    #[test]
+    fn settles_at_the_target() {
+        let new = ColorFade::next(None, Color::WHITE, FADE, FADE);
+        assert!(new.is_settled(), "a new element starts at its colour");
+
+        let fade = ColorFade::next(Some(new), Color::BLACK, FADE, Duration::from_millis(16));
+        assert!(!fade.is_settled());
+        assert!(
+            near(fade.color(FADE), Color::WHITE),
+            "a fade starts where it was"
+        );
+        let fade = ColorFade::next(Some(fade), Color::BLACK, FADE, Duration::from_millis(60));
+        assert!(!fade.is_settled());
+        let fade = ColorFade::next(Some(fade), Color::BLACK, FADE, Duration::from_millis(60));
+        assert!(fade.is_settled());
+        assert!(near(fade.color(FADE), Color::BLACK));
+    }
+
+    #[test]
+    fn a_new_target_starts_from_the_current_colour() {
+        let fade = ColorFade::next(
+            Some(ColorFade::settled(Color::BLACK)),
+            Color::WHITE,
+            FADE,
+            Duration::ZERO,
+        );
+        let halfway = ColorFade::next(Some(fade), Color::WHITE, FADE, FADE / 2);
+        let back = ColorFade::next(Some(halfway), Color::BLACK, FADE, Duration::from_millis(16));
+        assert!(near(back.color(FADE), halfway.color(FADE)));
+        assert!(!back.is_settled());
+    }
+
+    #[test]
+    fn a_zero_duration_snaps() {
+        let fade = ColorFade::next(
+            Some(ColorFade::settled(Color::BLACK)),
+            Color::WHITE,
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        assert!(fade.is_settled());
+        assert_eq!(fade.color(Duration::ZERO), Color::WHITE);
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, anyhow};
+use skia_safe::{Point, Rect};
+use taffy::{NodeId, PrintTree, TaffyTree, TraversePartialTree};
+
+use super::layout_pass::LayoutContext;
+use super::{CarriedState, StateKey};
+
+// ::: ---- Between Text and Draw Pass, Find what the Pointer is Over ---- :::
+// Elements paint in tree order, so where they overlap the later one is on top. Only the top
+// most element under the pointer and the elements it sits in count as hovered, so a click on
+// a popup doesn't also land on whatever is behind it. This has to be known before the draw
+// pass runs the hover and click jumps, so the boxes are walked once up front, offset and
+// clipped by scrolling exactly like the draw pass will do it. The same boxes are kept after
+// the frame for the `hit_test` ask.
+
+/// Where an element is on screen, in tree order. `element` says which one it is, a `NodeId`
+/// while drawing and an `ElementId` once the frame is done.
+#[derive(Debug, Clone, Copy)]
+pub struct ElementBox<T> {
+    pub element: T,
+    pub parent: Option<usize>, /* index of the element this one is in */
+    pub bounds: Rect,
+    pub clip: Option<Rect>, /* of the scrolling elements around this one */
+    pub scrolls: bool,      /* its content is taller than it */
+}
+impl<T> ElementBox<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> ElementBox<U> {
+        ElementBox {
+            element: f(self.element),
+            parent: self.parent,
+            bounds: self.bounds,
+            clip: self.clip,
+            scrolls: self.scrolls,
+        }
+    }
+
+    pub fn scaled(self, factor: f32) -> Self {
+        let scale = |r: Rect| {
+            Rect::from_ltrb(
+                r.left * factor,
+                r.top * factor,
+                r.right * factor,
+                r.bottom * factor,
+            )
+        };
+        Self {
+            bounds: scale(self.bounds),
+            clip: self.clip.map(scale),
+            ..self
+        }
+    }
+}
+
+/// The topmost element under `point` and the elements it sits in, as indices into `boxes`,
+/// innermost first.
+pub fn hit_test<T>(boxes: &[ElementBox<T>], point: Point) -> Vec<usize> {
+    /* later elements are drawn on top, so the last one wins */
+    let top = boxes.iter().rposition(|b| {
+        b.clip.map_or(true, |clip| clip.contains(point)) && b.bounds.contains(point)
+    });
+    std::iter::successors(top, |&i| boxes[i].parent).collect()
+}
+
+pub(super) fn hit_pass(boxes: &[ElementBox<NodeId>], pointer: Point) -> HashSet<NodeId> {
+    hit_test(boxes, pointer)
+        .into_iter()
+        .map(|i| boxes[i].element)
+        .collect()
+}
+
+/// The innermost scrolling element under the pointer, the only one the arrow keys scroll.
+/// The wheel scrolls them all, but a key press should only move what the pointer is on.
+pub(super) fn key_scroller(boxes: &[ElementBox<NodeId>], pointer: Point) -> Option<NodeId> {
+    hit_test(boxes, pointer)
+        .into_iter()
+        .find(|&i| boxes[i].scrolls)
+        .map(|i| boxes[i].element)
+}
+
+/// The `Enter` of an element, what the keyboard focus is remembered by.
+fn enter_ptr(tree: &TaffyTree<LayoutContext>, node: NodeId) -> Option<*const u8> {
+    let ctx = tree.get_node_context(node)?;
+    ctx.ragged_members.first().map(|(start, _)| *start)
+}
+
+/// Where the keyboard focus is: the focused element's `Enter` and the elements from it out to
+/// the root, which all get its keys. A press moves the focus to the topmost element under
+/// the pointer, otherwise it stays on the element `last` is the `Enter` of. Without a focused
+/// element the root has it.
+pub(super) fn focus_pass(
+    tree: &TaffyTree<LayoutContext>,
+    boxes: &[ElementBox<NodeId>],
+    pointer: Point,
+    just_pressed: bool,
+    last: Option<*const u8>,
+) -> (Option<*const u8>, HashSet<NodeId>) {
+    let focused = if just_pressed {
+        hit_test(boxes, pointer).first().copied()
+    } else {
+        last.and_then(|ptr| {
+            boxes
+                .iter()
+                .position(|b| enter_ptr(tree, b.element) == Some(ptr))
+        })
+    };
+    let root = (!boxes.is_empty()).then_some(0); /* the root's box comes first */
+    let within = std::iter::successors(focused.or(root), |&i| boxes[i].parent)
+        .map(|i| boxes[i].element)
+        .collect();
+    (
+        focused.and_then(|i| enter_ptr(tree, boxes[i].element)),
+        within,
+    )
+}
+
+/// Every drawn element's box, positioned with the scroll offsets in `frame_state`.
+pub(super) fn element_boxes(
+    tree: &TaffyTree<LayoutContext>,
+    root: NodeId,
+    frame_state: &HashMap<StateKey, CarriedState>,
+) -> Result<Vec<ElementBox<NodeId>>> {
+    let mut boxes = Vec::new();
+    box_node(tree, root, 0.0, 0.0, None, None, frame_state, &mut boxes)?;
+    Ok(boxes)
+}
+
+fn box_node(
+    tree: &TaffyTree<LayoutContext>,
+    node: NodeId,
+    px: f32,
+    py: f32,
+    parent: Option<usize>,
+    clip: Option<Rect>,
+    frame_state: &HashMap<StateKey, CarriedState>,
+    boxes: &mut Vec<ElementBox<NodeId>>,
+) -> Result<()> {
+    if tree.style(node)?.display == taffy::Display::None {
+        return Ok(()); /* not drawn, see `draw_pass` */
+    }
+    let layout = tree.get_final_layout(node);
+    let ctx = tree
+        .get_node_context(node)
+        .ok_or(anyhow!("Each node in the taffy tree must have a context"))?;
+    let x = px + layout.location.x;
+    let mut y = py + layout.location.y + ctx.baseline_shift;
+    let bounds = Rect::from_xywh(x, y, layout.size.width, layout.size.height);
+
+    // Same as the scrolling in `DrawIntepreter::handle_enter`.
+    let content_height = layout.content_size.height.max(
+        ctx.maybe_font_layout
+            .as_ref()
+            .map(|x| x.height())
+            .unwrap_or(0.0),
+    );
+    let scrolls = content_height > layout.size.height;
+
+    let index = boxes.len();
+    boxes.push(ElementBox {
+        element: node,
+        parent,
+        bounds,
+        clip,
+        scrolls,
+    });
+
+    // The rounded corners of `ClipRoundedRect` are left out, they're too small to aim for.
+    let mut clip = clip;
+    if scrolls || ctx.clips {
+        let mut inside = clip.unwrap_or(bounds);
+        if !inside.intersect(bounds) {
+            inside = Rect::default(); /* scrolled out of view, nothing in it can be hit */
+        }
+        clip = Some(inside);
+    }
+    if scrolls {
+        y += ctx
+            .state_key
+            .and_then(|key| frame_state.get(&key))
+            .map(|state| state.scroll_y)
+            .unwrap_or(0.0);
+    }
+
+    for child in tree.child_ids(node) {
+        box_node(tree, child, x, y, Some(index), clip, frame_state, boxes)?;
+    }
+    Ok(())
+}
@@ -2,16 +2,63 @@ use std::{collections::HashMap, usize};
 
 use anyhow::{Context, Result, anyhow};
 use skia_safe::Color;
-use taffy::{NodeId, TaffyTree};
+use taffy::{NodeId, TaffyTree, style_helpers::fr};
+use tracing::warn;
 use winit::window::CursorIcon;
 
 use super::cursors::LinearCursor;
-use super::{CarriedState, Tag, TaggedWord};
+use super::{CarriedState, PassTrace, StateKey, Tag, TaggedWord};
 
-use super::DisplayOption;
-use super::traits::{Executor, Intepreter, ReadIn};
+use super::traits::{Executor, HasStaticConfig, Intepreter, ReadIn};
 use super::utils::StaticConfig;
 use super::vm_state::VMState;
+use super::{DisplayOption, Side, StoredAlignContent, StoredAlignItems};
+
+// More tracks than this is a corrupted count rather than a layout.
+const MAX_GRID_TRACKS: usize = 1024;
+
+fn taffy_align_items(align_items: StoredAlignItems) -> taffy::AlignItems {
+    match align_items {
+        StoredAlignItems::Start => taffy::AlignItems::Start,
+        StoredAlignItems::End => taffy::AlignItems::End,
+        StoredAlignItems::Center => taffy::AlignItems::Center,
+        StoredAlignItems::Baseline => taffy::AlignItems::Baseline,
+        StoredAlignItems::Stretch => taffy::AlignItems::Stretch,
+    }
+}
+
+fn taffy_align_content(align_content: StoredAlignContent) -> taffy::AlignContent {
+    match align_content {
+        StoredAlignContent::Start => taffy::AlignContent::Start,
+        StoredAlignContent::End => taffy::AlignContent::End,
+        StoredAlignContent::Center => taffy::AlignContent::Center,
+        StoredAlignContent::Stretch => taffy::AlignContent::Stretch,
+        StoredAlignContent::SpaceBetween => taffy::AlignContent::SpaceBetween,
+        StoredAlignContent::SpaceEvenly => taffy::AlignContent::SpaceEvenly,
+        StoredAlignContent::SpaceAround => taffy::AlignContent::SpaceAround,
+    }
+}
+
+/// Whether the main axis of `style` runs down, where `Gap`'s first length is the row gap.
+fn is_column(style: &taffy::Style) -> bool {
+    style.display == taffy::Display::Flex
+        && matches!(
+            style.flex_direction,
+            taffy::FlexDirection::Column | taffy::FlexDirection::ColumnReverse
+        )
+}
+
+fn check_grid_tracks(tag: Tag, count: usize) -> Result<()> {
+    if count > MAX_GRID_TRACKS {
+        return Err(anyhow!(
+            "`{}` {} is more than the {} tracks a grid can have",
+            tag,
+            count,
+            MAX_GRID_TRACKS
+        ));
+    }
+    Ok(())
+}
 
 // ::: ---- Rendering Code --- :::
 // Rendering is done in three passes
@@ -23,28 +70,46 @@ use super::vm_state::VMState;
 #[derive(Clone, Default)]
 pub(crate) struct LayoutContext {
     pub ragged_members: Vec<(*const u8, *const u8)>,
+    pub state_key: Option<StateKey>, /* what the element's `CarriedState` is kept under */
+    pub show_if: Vec<bool>, /* in execution order, so later passes skip exactly what layout skipped */
+    pub baseline: Option<f32>, /* first line of text, relative to the top of the node */
+    pub baseline_shift: f32, /* taffy can't align on text baselines, so we nudge the node after */
+    pub text_shift: f32,    /* `BaselineShift` of the text, positive is up */
+    pub text_color_split: Option<usize>, /* `TextColorRun` offset into the laid out text */
+    pub clips: bool,        /* `ClipRoundedRect`, the children can only be hit inside the box */
+    pub opacity: Option<f32>, /* `Opacity`, the draw pass opens the layer at the element's `Enter` */
     pub maybe_font_layout: Option<parley::Layout<()>>,
 }
 
+// Where to pick up again once a library body hits `LibraryReturn`.
+struct CallFrame {
+    return_ptr: *const u8,
+    region: (*const u8, *const u8),
+    node_depth: usize,
+}
+
 struct LayoutIntepreter<'a> {
     config: StaticConfig,
     state: VMState,
     cursor: LinearCursor,
 
-    last_frame_state: &'a HashMap<*const u8, CarriedState>,
+    last_frame_state: &'a HashMap<StateKey, CarriedState>,
+    library: &'a HashMap<usize, Vec<usize>>,
 
     tree: TaffyTree<LayoutContext>,
     node_stack: Vec<NodeId>,
     cur_start_ptr: *const u8,
-    // call_stack: Vec<*const u8>,
+    call_stack: Vec<CallFrame>,
     root: NodeId,
+    clear_color: Option<Color>,
 }
 impl<'a> LayoutIntepreter<'a> {
     fn new(
         region_start: *const u8,
         region_end: *const u8,
         config: StaticConfig,
-        last_frame_state: &'a HashMap<*const u8, CarriedState>,
+        last_frame_state: &'a HashMap<StateKey, CarriedState>,
+        library: &'a HashMap<usize, Vec<usize>>,
     ) -> Result<Self> {
         assert!(
             region_start as usize % size_of::<usize>() == 0,
@@ -64,76 +129,101 @@ impl<'a> LayoutIntepreter<'a> {
 
         // create a node for the root, which we just read
         let mut tree = TaffyTree::new();
-        let root = tree.new_leaf_with_context(taffy::Style::default(), LayoutContext::default())?;
+        let root = tree.new_leaf_with_context(
+            taffy::Style::default(),
+            LayoutContext {
+                state_key: Some(StateKey::Ptr(cursor.cursor)), /* right after the `Enter` */
+                ..Default::default()
+            },
+        )?;
         let node_stack = vec![root];
 
         Ok(Self {
             config,
-            state: VMState::new(),
+            state: VMState::new(config.vm_limits()),
             cursor,
             tree,
             node_stack,
             cur_start_ptr: region_start,
+            call_stack: Vec::new(),
             last_frame_state,
+            library,
             root,
+            clear_color: None,
         })
     }
 
+    fn push_ragged_member(&mut self, node: NodeId) -> Result<()> {
+        let mut ctx: LayoutContext = self
+            .tree
+            .get_node_context(node)
+            .cloned()
+            .unwrap_or_default(); /* TODO: eliminate copy here */
+        ctx.ragged_members
+            .push((self.cur_start_ptr, self.cursor.cursor));
+        self.tree.set_node_context(node, Some(ctx))?;
+        Ok(())
+    }
+
     fn enter_child(&mut self) -> Result<()> {
-        // This is used for all ways of entering children: `Enter`, `LibraryCall`, or `Call`
-        // the reason to make this separate is that the `self.cur_start_ptr` needs to be updated differently
-        // depending on if we are jumping into different memory regions.
-        let cur_node = self
+        // This is kept separate from `handle_enter` since `self.cur_start_ptr` is updated by the caller.
+        // Note `LibraryCall` does not enter a child, library bodies run as part of the calling element.
+        let cur_node = *self
             .node_stack
             .last()
             .ok_or(anyhow!("At least one `Leave` too many."))?;
         self.cursor.add_depth();
 
         // otherwise this is the root
-        let mut ctx: LayoutContext = self
-            .tree
-            .get_node_context(*cur_node)
-            .cloned()
-            .unwrap_or_default(); /* TODO: eliminate copy here */
-        ctx.ragged_members
-            .push((self.cur_start_ptr, self.cursor.cursor));
-        self.tree.set_node_context(*cur_node, Some(ctx))?;
+        self.push_ragged_member(cur_node)?;
 
-        self.node_stack.push(
-            self.tree
-                .new_leaf_with_context(taffy::Style::default(), LayoutContext::default())?,
-        );
+        self.node_stack.push(self.tree.new_leaf_with_context(
+            taffy::Style::default(),
+            LayoutContext {
+                state_key: Some(StateKey::Ptr(self.cursor.cursor)),
+                ..Default::default()
+            },
+        )?);
 
         Ok(())
     }
 
-    fn leave_child(&mut self) -> Result<()> {
-        // This is used for all ways of leaving children: `Leave`, `LibraryReturn`, or `Return`
-        // the reason to make this separate is that the `self.cur_start_ptr` needs to be updated differently
-        // depending on if we are jumping into different memory regions.
+    /// Takes a state jump the way the draw pass did in the last frame, which is what this
+    /// frame draws (see `DrawIntepreter::state_jump`).
+    fn replay_jump(&mut self, rel_ptr: usize) {
+        /* the element's first region, as the draw pass sees it, starts at its `Enter` */
+        let ctx = self
+            .node_stack
+            .last()
+            .and_then(|node| self.tree.get_node_context(*node));
+        let key = StateKey::of_jump(
+            ctx.and_then(|ctx| ctx.state_key),
+            ctx.and_then(|ctx| ctx.ragged_members.first())
+                .map_or(self.cur_start_ptr, |(enter, _)| *enter),
+            self.cursor.cursor,
+        );
+        if !self.last_frame_state.get(&key).is_some_and(|x| x.is_jmp) {
+            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
+        }
+    }
 
+    fn leave_child(&mut self) -> Result<()> {
+        // This is kept separate from `handle_leave` since `self.cur_start_ptr` is updated by the caller.
         let cur_node = self
             .node_stack
             .pop()
             .ok_or(anyhow!("At-least one `Leave` too many"))?;
-        let parent = self.node_stack.last();
+        let parent = self.node_stack.last().copied();
 
         // Push a new node range
-        let mut ctx: LayoutContext = self
-            .tree
-            .get_node_context(cur_node)
-            .cloned()
-            .unwrap_or_default(); /* TODO: eliminate copy here */
-        ctx.ragged_members
-            .push((self.cur_start_ptr, self.cursor.cursor));
-        self.tree.set_node_context(cur_node, Some(ctx))?;
+        self.push_ragged_member(cur_node)?;
 
         self.cursor.sub_depth();
 
         // Update connectivness
         if let Some(parent) = parent {
             /* root node doesn't have a parent. */
-            self.tree.add_child(*parent, cur_node)?;
+            self.tree.add_child(parent, cur_node)?;
         }
 
         Ok(())
@@ -221,70 +311,224 @@ impl<'a> Intepreter for LayoutIntepreter<'a> {
         Ok(())
     }
 
+    fn handle_padding_side(&mut self, side: Side, length: taffy::LengthPercentage) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        *side.of(&mut cur_style.padding) = length; /* the other sides stay as they are */
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_margin_side(
+        &mut self,
+        side: Side,
+        length: taffy::LengthPercentageAuto,
+    ) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        *side.of(&mut cur_style.margin) = length;
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_clip_rounded_rect(&mut self, _radius: taffy::LengthPercentage) -> Result<()> {
+        let cur_node = *self
+            .node_stack
+            .last()
+            .ok_or(anyhow!("`ClipRoundedRect` outside of any element"))?;
+        self.tree
+            .get_node_context_mut(cur_node)
+            .ok_or(anyhow!("All nodes must have context"))?
+            .clips = true;
+        Ok(())
+    }
+
+    fn handle_opacity(&mut self, opacity: f32) -> Result<()> {
+        let cur_node = *self
+            .node_stack
+            .last()
+            .ok_or(anyhow!("`Opacity` outside of any element"))?;
+        let opacity = if (0.0..=1.0).contains(&opacity) {
+            opacity
+        } else {
+            warn!(
+                "`Opacity` of {} is outside of 0 to 1, clamping it.",
+                opacity
+            );
+            opacity.clamp(0.0, 1.0)
+        };
+        let ctx = self
+            .tree
+            .get_node_context_mut(cur_node)
+            .ok_or(anyhow!("All nodes must have context"))?;
+        // A second `Opacity` fades on top of the first, like a layer inside a layer would.
+        let opacity = ctx.opacity.unwrap_or(1.0) * opacity;
+        ctx.opacity = (opacity != 1.0).then_some(opacity);
+        Ok(())
+    }
+
+    fn handle_key(&mut self, key: usize) -> Result<()> {
+        let cur_node = *self
+            .node_stack
+            .last()
+            .ok_or(anyhow!("`Key` outside of any element"))?;
+        self.tree
+            .get_node_context_mut(cur_node)
+            .ok_or(anyhow!("All nodes must have context"))?
+            .state_key = Some(StateKey::Key(key));
+        Ok(())
+    }
+
     fn handle_display(&mut self, display: DisplayOption) -> Result<()> {
         let cur_node = self.node_stack.last().unwrap();
         let mut cur_style = self.tree.style(*cur_node)?.clone();
+        let was_column = is_column(&cur_style);
         match display {
             DisplayOption::Block => cur_style.display = taffy::Display::Block,
             DisplayOption::FlexRow => cur_style.display = taffy::Display::Flex,
             DisplayOption::FlexColumn => cur_style.display = taffy::Display::Flex,
+            DisplayOption::FlexRowReverse => cur_style.display = taffy::Display::Flex,
+            DisplayOption::FlexColumnReverse => cur_style.display = taffy::Display::Flex,
             DisplayOption::Grid => cur_style.display = taffy::Display::Grid,
             DisplayOption::None => cur_style.display = taffy::Display::None,
         }
         match display {
             DisplayOption::FlexRow => cur_style.flex_direction = taffy::FlexDirection::Row,
             DisplayOption::FlexColumn => cur_style.flex_direction = taffy::FlexDirection::Column,
+            DisplayOption::FlexRowReverse => {
+                cur_style.flex_direction = taffy::FlexDirection::RowReverse
+            }
+            DisplayOption::FlexColumnReverse => {
+                cur_style.flex_direction = taffy::FlexDirection::ColumnReverse
+            }
             _ => (),
         }
+        if is_column(&cur_style) != was_column {
+            /* a `Gap` before the `Display` was for the other main axis */
+            cur_style.gap = taffy::Size {
+                width: cur_style.gap.height,
+                height: cur_style.gap.width,
+            };
+        }
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_align_items(&mut self, align_items: StoredAlignItems) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        cur_style.align_items = Some(taffy_align_items(align_items));
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_justify_items(&mut self, justify_items: StoredAlignItems) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        cur_style.justify_items = Some(taffy_align_items(justify_items));
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_justify_content(&mut self, justify_content: StoredAlignContent) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        cur_style.justify_content = Some(taffy_align_content(justify_content));
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_align_content(&mut self, align_content: StoredAlignContent) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        cur_style.align_content = Some(taffy_align_content(align_content));
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_grid_columns(&mut self, count: usize) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        check_grid_tracks(Tag::GridColumns, count)?;
+        // Like `repeat(count, 1fr)`, the room left after the gaps is split equally.
+        cur_style.grid_template_columns =
+            vec![taffy::GridTemplateComponent::Single(fr(1.0)); count];
+        self.tree.set_style(*cur_node, cur_style)?;
+        Ok(())
+    }
+
+    fn handle_grid_rows(&mut self, count: usize) -> Result<()> {
+        let cur_node = self.node_stack.last().unwrap();
+        let mut cur_style = self.tree.style(*cur_node)?.clone();
+        check_grid_tracks(Tag::GridRows, count)?;
+        // Like `repeat(count, 1fr)`, the room left after the gaps is split equally.
+        cur_style.grid_template_rows = vec![taffy::GridTemplateComponent::Single(fr(1.0)); count];
         self.tree.set_style(*cur_node, cur_style)?;
         Ok(())
     }
 
     fn handle_gap(
         &mut self,
-        width: taffy::LengthPercentage,
-        height: taffy::LengthPercentage,
+        main: taffy::LengthPercentage,
+        cross: taffy::LengthPercentage,
     ) -> Result<()> {
         let cur_node = self.node_stack.last().unwrap();
         let mut cur_style = self.tree.style(*cur_node)?.clone();
-        cur_style.gap = taffy::Size { width, height };
+        // The first length is the gap along the main axis. Taffy's `gap.width` is the column gap
+        // and resolves percentages against the container's width (`height` likewise), so in a
+        // column the two trade places. Grids and blocks go by the horizontal axis like a row.
+        cur_style.gap = if is_column(&cur_style) {
+            taffy::Size {
+                width: cross,
+                height: main,
+            }
+        } else {
+            taffy::Size {
+                width: main,
+                height: cross,
+            }
+        };
         self.tree.set_style(*cur_node, cur_style)?;
         Ok(())
     }
 
     fn handle_hover(&mut self, rel_ptr: usize) -> Result<()> {
-        if !self
-            .last_frame_state
-            .get(&self.cursor.cursor)
-            .map(|x| &x.is_jmp)
-            .unwrap_or(&false)
-        {
-            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
-        }
+        self.replay_jump(rel_ptr);
         Ok(())
     }
 
     fn handle_mouse_pressed(&mut self, rel_ptr: usize) -> Result<()> {
-        if !self
-            .last_frame_state
-            .get(&self.cursor.cursor)
-            .map(|x| &x.is_jmp)
-            .unwrap_or(&false)
-        {
-            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
-        }
+        self.replay_jump(rel_ptr);
         Ok(())
     }
 
     fn handle_clicked(&mut self, rel_ptr: usize) -> Result<()> {
-        if !self
-            .last_frame_state
-            .get(&self.cursor.cursor)
-            .map(|x| &x.is_jmp)
-            .unwrap_or(&false)
-        {
-            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
-        }
+        self.replay_jump(rel_ptr);
+        Ok(())
+    }
+
+    fn handle_focus_visible(&mut self, rel_ptr: usize) -> Result<()> {
+        self.replay_jump(rel_ptr);
+        Ok(())
+    }
+
+    fn handle_button_pressed(&mut self, rel_ptr: usize, _button: usize) -> Result<()> {
+        self.replay_jump(rel_ptr);
+        Ok(())
+    }
+
+    fn handle_key_down(&mut self, rel_ptr: usize, _key: usize) -> Result<()> {
+        self.replay_jump(rel_ptr);
+        Ok(())
+    }
+
+    fn handle_key_up(&mut self, rel_ptr: usize, _key: usize) -> Result<()> {
+        self.replay_jump(rel_ptr);
+        Ok(())
+    }
+
+    fn handle_key_press(&mut self, rel_ptr: usize, _ch: char) -> Result<()> {
+        self.replay_jump(rel_ptr);
         Ok(())
     }
 
@@ -297,6 +541,52 @@ impl<'a> Intepreter for LayoutIntepreter<'a> {
         Ok(())
     }
 
+    fn handle_show_if(&mut self, rel_ptr: usize, condition: TaggedWord) -> Result<()> {
+        let show = unsafe { condition.word.word } != 0;
+        let cur_node = *self
+            .node_stack
+            .last()
+            .ok_or(anyhow!("`ShowIf` outside of any element"))?;
+        self.tree
+            .get_node_context_mut(cur_node)
+            .ok_or(anyhow!("All nodes must have context"))?
+            .show_if
+            .push(show);
+
+        if !show {
+            // The skipped region may hold whole elements, but it can't leave one half open.
+            let (_, region_end) = self.cursor.region();
+            let target = unsafe { self.cursor.cursor.add(rel_ptr) };
+            if rel_ptr % (2 * size_of::<usize>()) != 0 || target > region_end {
+                return Err(anyhow!(
+                    "`ShowIf` jumps to {} bytes ahead, which is unaligned or out of bounds",
+                    rel_ptr
+                ));
+            }
+            let mut depth = 0;
+            let mut scan = self.cursor.cursor;
+            while scan < target {
+                match unsafe { TaggedWord::read_in(&mut scan) }.tag {
+                    Tag::Enter => depth += 1,
+                    Tag::Leave if depth == 0 => {
+                        return Err(anyhow!(
+                            "`ShowIf` can't skip past the `Leave` of the element it is in"
+                        ));
+                    }
+                    Tag::Leave => depth -= 1,
+                    _ => (),
+                }
+            }
+            if depth != 0 {
+                return Err(anyhow!(
+                    "`ShowIf` skips an `Enter` without its matching `Leave`"
+                ));
+            }
+            self.cursor.cursor = target;
+        }
+        Ok(())
+    }
+
     fn handle_text(
         &mut self,
         _x: taffy::LengthPercentage,
@@ -332,6 +622,18 @@ impl<'a> Intepreter for LayoutIntepreter<'a> {
         Ok(())
     }
 
+    fn handle_clear_color(&mut self, color: Color) -> Result<()> {
+        // The window is cleared before anything is drawn, so it can't change halfway through.
+        let before_children = self.tree.child_count(self.root) == 0;
+        if self.node_stack.len() != 1 || !self.call_stack.is_empty() || !before_children {
+            return Err(anyhow!(
+                "`ClearColor` must be in the root element, before its first child"
+            ));
+        }
+        self.clear_color = Some(color);
+        Ok(())
+    }
+
     fn handle_cursor(&mut self, _cursor: CursorIcon) -> Result<()> {
         Ok(())
     }
@@ -340,6 +642,51 @@ impl<'a> Intepreter for LayoutIntepreter<'a> {
         Ok(())
     }
 
+    fn handle_library_call(&mut self, id: usize) -> Result<()> {
+        let library = self.library;
+        let code = library
+            .get(&id)
+            .ok_or(anyhow!("Library with id {} not found", id))?;
+        let cur_node = *self
+            .node_stack
+            .last()
+            .ok_or(anyhow!("`LibraryCall` outside of any element"))?;
+
+        // The caller's code up to (and including) the call belongs to the current element,
+        // so does the library body which is spliced in after it.
+        self.push_ragged_member(cur_node)?;
+        self.call_stack.push(CallFrame {
+            return_ptr: self.cursor.cursor,
+            region: self.cursor.region(),
+            node_depth: self.node_stack.len(),
+        });
+
+        let start = code.as_ptr() as *const u8;
+        let end = unsafe { start.add(code.len() * size_of::<usize>()) };
+        self.cursor.set_region(start, end);
+        self.cur_start_ptr = start;
+        Ok(())
+    }
+
+    fn handle_library_return(&mut self) -> Result<()> {
+        let frame = self
+            .call_stack
+            .pop()
+            .ok_or(anyhow!("`LibraryReturn` without a matching `LibraryCall`"))?;
+        if frame.node_depth != self.node_stack.len() {
+            return Err(anyhow!(
+                "A library must `Leave` every element it `Enter`s before `LibraryReturn`"
+            ));
+        }
+        let cur_node = *self.node_stack.last().unwrap();
+        self.push_ragged_member(cur_node)?;
+
+        self.cursor.set_region(frame.region.0, frame.region.1);
+        self.cursor.cursor = frame.return_ptr;
+        self.cur_start_ptr = frame.return_ptr;
+        Ok(())
+    }
+
     fn handle_begin_path(&mut self) -> Result<()> {
         Ok(())
     }
@@ -406,32 +753,88 @@ pub(super) fn layout_pass(
     region_start: *const u8,
     region_end: *const u8,
     config: StaticConfig,
-    last_frame_state: &HashMap<*const u8, CarriedState>,
-) -> Result<(NodeId, TaffyTree<LayoutContext>)> {
+    last_frame_state: &HashMap<StateKey, CarriedState>,
+    library: &HashMap<usize, Vec<usize>>,
+) -> Result<(NodeId, TaffyTree<LayoutContext>, Option<Color>, usize)> {
     assert!(
         region_start as usize % size_of::<usize>() == 0,
         "region_start not aligned"
     );
 
-    let mut intepreter = LayoutIntepreter::new(region_start, region_end, config, last_frame_state)?;
+    let mut intepreter =
+        LayoutIntepreter::new(region_start, region_end, config, last_frame_state, library)?;
 
     let mut trace = Vec::new();
-    while let Some(_) = intepreter.advance(&mut trace).with_context(|| {
-        let n = 10;
-        let slice = trace.get(trace.len().saturating_sub(n)..).unwrap_or(&[]);
-
-        let mut out = String::from("\n***Context [Layout Pass]***\n");
-        for (i, tagged_word) in slice.iter().enumerate() {
-            let color = if i == n - 1 { "\x1B[31m" } else { "\x1B[0m" };
-
-            out.push_str(&format!(
-                "{}{:?} {:?}\x1B[0m\n",
-                color,
-                tagged_word.tag,
-                unsafe { tagged_word.word._debug_bytes }
-            ));
-        }
-        out
-    })? {}
-    Ok((intepreter.root, intepreter.tree))
+    while let Some(_) = intepreter
+        .advance(&mut trace)
+        .with_context(|| PassTrace::new("Layout Pass", &trace, 10))?
+    {}
+    if !intepreter.call_stack.is_empty() {
+        return Err(anyhow!(
+            "Library body ended without `LibraryReturn` ({} call(s) still open)",
+            intepreter.call_stack.len()
+        ));
+    }
+    /* the instructions the pass ran, library bodies included, for `--profile` */
+    let instruction_count = trace.len();
+    Ok((
+        intepreter.root,
+        intepreter.tree,
+        intepreter.clear_color,
+        instruction_count,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use skia_safe::Color;
+
+    use crate::ui::draw::{DisplayOption, Tag};
+    use crate::ui::testing::{Headless, Tree, Word, frac, px, rgb};
+
+    /// Two 100px squares, red then blue, in a `width` by `height` container laid out by
+    /// `display` with a gap of a tenth of the main axis.
+    fn two_squares(display: DisplayOption, width: f32, height: f32) -> Tree {
+        let square = |t: &mut Tree, color: Word| {
+            t.enter()
+                .op(Tag::Width, &[px(100.0)])
+                .op(Tag::Height, &[px(100.0)])
+                .op(Tag::Color, &[color])
+                .op(Tag::Rect, &[px(0.0), px(0.0), px(100.0), px(100.0)])
+                .leave();
+        };
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(width)])
+            .op(Tag::Height, &[px(height)])
+            .op(Tag::Gap, &[frac(0.1), px(0.0)])
+            .push((Tag::Display, display as usize));
+        square(&mut tree, rgb(255, 0, 0));
+        square(&mut tree, rgb(0, 0, 255));
+        tree.leave();
+        tree
+    }
+
+    #[test]
+    fn frac_gap_in_a_row() {
+        let mut headless = Headless::new(400, 400);
+        headless
+            .frame(&two_squares(DisplayOption::FlexRow, 400.0, 200.0))
+            .unwrap();
+        assert_eq!(headless.pixel(50, 50), Color::from_rgb(255, 0, 0));
+        assert_eq!(headless.pixel(120, 50), Color::WHITE, "a tenth of 400px");
+        assert_eq!(headless.pixel(150, 50), Color::from_rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn frac_gap_in_a_column() {
+        // The first length is still the main-axis gap, a tenth of the height.
+        let mut headless = Headless::new(400, 400);
+        headless
+            .frame(&two_squares(DisplayOption::FlexColumn, 200.0, 400.0))
+            .unwrap();
+        assert_eq!(headless.pixel(50, 50), Color::from_rgb(255, 0, 0));
+        assert_eq!(headless.pixel(50, 120), Color::WHITE, "a tenth of 400px");
+        assert_eq!(headless.pixel(50, 150), Color::from_rgb(0, 0, 255));
+    }
 }
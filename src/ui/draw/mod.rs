@@ -1,46 +1,218 @@
 mod cursors;
+mod damage;
+mod debug_pass;
 mod draw_pass;
+mod fade;
+mod hit_pass;
 mod layout_pass;
+mod profile;
 mod text;
 mod text_pass;
 mod traits;
 mod utils;
+mod validate_pass;
 mod vm_state;
 
-use std::{collections::HashMap, sync::Arc, time::Duration, usize};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration, usize};
 
 use anyhow::{Result, anyhow};
 use parley::FontContext;
-use skia_safe::{Canvas, Color, HSV, RGB};
+use skia_safe::{Canvas, Color, HSV};
 use strum::{EnumCount, EnumString};
 use utils::StaticConfig;
 use vm_state::VMState;
+use winit::dpi::PhysicalSize;
 use winit::window::{CursorIcon, Window};
 
+use debug_pass::debug_pass;
 use draw_pass::draw_pass;
+use fade::ColorFade;
+use hit_pass::{element_boxes, focus_pass, hit_pass, key_scroller};
 use layout_pass::layout_pass;
-use text_pass::text_pass;
+use profile::FrameProfile;
+use text::MAX_TAB_WIDTH;
+use text_pass::{align_baselines, text_pass};
+use validate_pass::validate_pass;
 
-use super::InputState;
+use super::gamepad::GamepadEvent;
+use super::{ColorSpaceOption, InputState};
+pub use damage::{Damage, PaintLog};
+pub use hit_pass::{ElementBox, hit_test};
 
-#[derive(Debug, Clone, Copy)]
+/// What `CarriedState` is kept under from one frame to the next. Usually where in the layout
+/// the element or jump is, which moves when the client writes the tree anew; an element with a
+/// `Key` is found again by that instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateKey {
+    Ptr(*const u8),
+    Key(usize),
+    Jump(usize, usize), /* a jump in an element with a `Key`, by the key and its offset from the `Enter` */
+}
+impl StateKey {
+    /// What a jump at `cursor` is kept under, in the element kept under `element` with its
+    /// `Enter` at `enter`. In a keyed element the jump moves along with the element.
+    pub(super) fn of_jump(
+        element: Option<StateKey>,
+        enter: *const u8,
+        cursor: *const u8,
+    ) -> StateKey {
+        match element {
+            Some(StateKey::Key(key)) => {
+                StateKey::Jump(key, (cursor as usize).wrapping_sub(enter as usize))
+            }
+            _ => StateKey::Ptr(cursor),
+        }
+    }
+}
+
+/// An element as the client knows it: by its `Key` if it has one, else by the offset of its
+/// `Enter` in the tree (`None` for elements from a library body, which isn't in the tree).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementId {
+    At(Option<usize>),
+    Key(usize),
+}
+impl ElementId {
+    fn new(key: Option<StateKey>, file_start: *const u8, file_end: *const u8) -> Self {
+        match key {
+            Some(StateKey::Key(key)) => ElementId::Key(key),
+            Some(StateKey::Ptr(ptr)) if (file_start..file_end).contains(&ptr) => {
+                /* the key points right after the `Enter` */
+                ElementId::At(Some(
+                    ptr as usize - file_start as usize - size_of::<TaggedWord>(),
+                ))
+            }
+            _ => ElementId::At(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct CarriedState {
     pub is_jmp: bool,
     #[allow(dead_code)]
     pub scroll_y: f32,
+    pub captured: bool, /* the press started on the element and the button is still down */
+    pub color: Option<ColorFade>, /* `ColorTransition`, where the pencil colour is heading */
+    pub focused_node: Option<*const u8>, /* on the root, the `Enter` of the element with the keyboard focus */
 }
 impl CarriedState {
     pub fn new() -> Self {
         CarriedState {
             is_jmp: false,
             scroll_y: 0.0,
+            captured: false,
+            color: None,
+            focused_node: None,
+        }
+    }
+}
+
+/// An event for the client, mostly fired by the layout through the callback given to `draw`.
+#[derive(Debug, Clone)]
+pub enum FiredEvent {
+    Id(usize),     /* `Event` */
+    Named(String), /* `NamedEvent` */
+    Scroll {
+        id: usize,
+        scroll_y: f32,
+    }, /* `OnScrollReport`, how far the element is scrolled down in layout pixels */
+    Viewport(Viewport), /* the root scrolled or the window was resized */
+    Focus(bool),   /* the window gained or lost the keyboard focus */
+    FileHover(Option<PathBuf>), /* a file is dragged over the window, `None` once it left */
+    FileDrop(PathBuf),
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    Gamepad(GamepadEvent), /* with `--gamepad`, a button or stick moved or a pad came or went */
+    Ready {
+        width: u32,
+        height: u32,
+    }, /* the first frame made it to the screen, in physical pixels */
+    RenderError {
+        message: String,
+        trace: Option<PassTrace>,
+    }, /* the layout couldn't be drawn, sent once until a frame works again */
+}
+
+/// What the window shows of the root element, in layout pixels (the same as "Pxs").
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub scroll_y: f32, /* how far the root is scrolled down */
+    pub height: f32,
+}
+
+/// What the lengths in the layout are relative to.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthUnits {
+    pub base_font_size: f32,  /* "Rems" */
+    pub display_scale: f32,   /* "Pxs" and "Rems" are logical pixels */
+    pub viewport: (f32, f32), /* "Vw" and "Vh", already in the layout's pixels */
+}
+
+/// What a successful `draw` hands back to the window.
+pub struct DrawnFrame {
+    pub state: HashMap<StateKey, CarriedState>, /* carried into the next frame */
+    pub clear_color: Option<Color>,             /* `ClearColor`, if the layout set one */
+    pub viewport: Viewport,
+    pub animating: bool, /* a colour is still fading, draw again */
+    pub boxes: Vec<ElementBox<ElementId>>, /* where the elements are in layout pixels, for `hit_test` */
+}
+
+/// The last tagged words a pass read before it failed, attached to its error as context so it
+/// can be logged and sent to the client.
+#[derive(Debug, Clone)]
+pub struct PassTrace {
+    pub pass: &'static str,
+    pub words: Vec<(Tag, usize)>, /* oldest first, the last one failed */
+}
+impl PassTrace {
+    fn new(pass: &'static str, trace: &[TaggedWord], n: usize) -> Self {
+        let slice = trace.get(trace.len().saturating_sub(n)..).unwrap_or(&[]);
+        Self {
+            pass,
+            words: slice
+                .iter()
+                .map(|tagged_word| (tagged_word.tag, unsafe { tagged_word.word.word }))
+                .collect(),
+        }
+    }
+}
+impl std::fmt::Display for PassTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n***Context [{}]***\n", self.pass)?;
+        for (i, (tag, word)) in self.words.iter().enumerate() {
+            let color = if i == self.words.len() - 1 {
+                "\x1B[31m"
+            } else {
+                "\x1B[0m"
+            };
+            write!(f, "{}{:?} {:?}\x1B[0m\n", color, tag, word.to_ne_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+// Caps on what the (untrusted) bytecode may make the VM allocate.
+#[derive(Debug, Clone, Copy)]
+pub struct VmLimits {
+    pub max_stack: usize,
+    pub max_registers: usize,
+    pub max_call_depth: usize,
+}
+impl Default for VmLimits {
+    fn default() -> Self {
+        Self {
+            max_stack: 1024,
+            max_registers: 1024,
+            max_call_depth: 64,
         }
     }
 }
 
 /* :----- Defines the representation of data in memory -----: */
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, EnumString, EnumCount, strum::Display, PartialEq, Eq)]
+#[derive(
+    Debug, Clone, Copy, EnumString, EnumCount, strum::FromRepr, strum::Display, PartialEq, Eq,
+)]
 #[repr(usize)]
 pub enum Tag {
     // Fundamental
@@ -109,8 +281,123 @@ pub enum Tag {
     // Cursors
     CursorDefault, /* 45 */
     CursorPointer, /* 46 */
+
+    // Library
+    LibraryCall,   /* 47 word(library id) */
+    LibraryReturn, /* 48 */
+    FromOuterReg,  /* 49 word(id) */
+
+    // Formatting
+    FormatNumber, /* 50 word(precision) */
+    ScratchStr,   /* 51 word(index) -- internal, a string produced by the VM this frame */
+
+    // Conditions
+    ShowIf, /* 52 rel_pointer, any (condition) */
+
+    // Decoration
+    Outline, /* 53 width, color, offset */
+
+    // Layout (cont.)
+    AlignItems, /* 54 align items option */
+
+    // Text (cont.)
+    TabWidth,      /* 55 word(columns) */
+    BaselineShift, /* 56 signed length (positive is up) */
+
+    // Events (cont.)
+    NamedEvent, /* 57 _, TextPtr (name) */
+
+    // Text (cont.)
+    WordBreak,     /* 58 word break option */
+    PixelSnapText, /* 59 word(0 = off, else on) */
+    TextColorRun,  /* 60 word(byte offset), color */
+    InlineBox,     /* 61 word(byte offset), width, height */
+
+    // States (cont.)
+    FocusVisible, /* 62 rel_pointer, [... no jmp], [jmp ...] */
+
+    // Shape (cont.)
+    OutlineDash, /* 63 dash, gap */
+
+    // Text (cont.)
+    FontSizeEm, /* 64 real (times the parent's font size) */
+
+    // Layout (cont.), one side of the padding or margin
+    PaddingLeft,   /* 65 */
+    PaddingTop,    /* 66 */
+    PaddingRight,  /* 67 */
+    PaddingBottom, /* 68 */
+    MarginLeft,    /* 69 */
+    MarginTop,     /* 70 */
+    MarginRight,   /* 71 */
+    MarginBottom,  /* 72 */
+
+    // Events (cont.)
+    OnScrollReport, /* 73 word(evt_id) */
+
+    // State
+    Key, /* 74 word(key) */
+
+    // Window
+    ClearColor, /* 75 color, only in the root before its first child */
+
+    // Shape (cont.)
+    Line, /* 76 x0, y0, x1, y1 */
+
+    // Font (cont.)
+    TextAt, /* 77 x, y, max_width, ptr -- drawn where it is, takes no room in the layout */
+
+    // Draw (cont.)
+    ColorTransition, /* 78 word(ms), color -- fades the pencil colour to it */
+    StrokeWidth,     /* 79 width -- shapes after it are stroked, zero fills them again */
+    LinearGradient,  /* 80 x0, y0, x1, y1, color, color */
+
+    // Units (cont.)
+    Vw, /* 81 real, fraction of the window's width */
+    Vh, /* 82 real, fraction of the window's height */
+
+    // States (cont.)
+    ButtonPressed, /* 83 rel_pointer, any (button), [... no jmp], [jmp ...] */
+
+    // Cursors (cont.)
+    HoverCursor, /* 84 word(cursor, see `HOVER_CURSORS`) */
+
+    // Events (cont.)
+    EventArg, /* 85 _, any (id) -- `Event` with an id that can be pulled */
+
+    // Layout (cont.), grids and alignment
+    GridColumns,    /* 86 word(count) -- that many columns sharing the width */
+    GridRows,       /* 87 word(count) */
+    JustifyItems,   /* 88 align items option, along the row */
+    JustifyContent, /* 89 align content option */
+    AlignContent,   /* 90 align content option */
+
+    // Draw (cont.)
+    ClipRoundedRect, /* 91 radius -- the rest of the element and its children only show inside its box */
+    BorderRadius, /* 92 tl, tr, br, bl -- per corner radii for the next `RoundedRect`s, auto keeps its radius */
+    Opacity,      /* 93 word (f32) -- the element and its children are blended in at 0 to 1 */
+    KeyDown,      /* 94 rel_pointer, any (key), [... no jmp], [jmp ...] */
+    KeyUp,        /* 95 rel_pointer, any (key), [... no jmp], [jmp ...] */
+    KeyPress,     /* 96 rel_pointer, any (char), [... no jmp], [jmp ...] */
+    WhiteSpace,   /* 97 white space option */
+    FontSizeArg,  /* 98 _, any (real) -- `FontSize` with a size that can be pulled */
 }
 
+/// The cursors "HoverCursor" picks from by its word, in the order the `set_cursor` ask lists them.
+const HOVER_CURSORS: [CursorIcon; 11] = [
+    CursorIcon::Default,
+    CursorIcon::Pointer,
+    CursorIcon::Text,
+    CursorIcon::Wait,
+    CursorIcon::Progress,
+    CursorIcon::Crosshair,
+    CursorIcon::Move,
+    CursorIcon::Grab,
+    CursorIcon::Grabbing,
+    CursorIcon::NotAllowed,
+    CursorIcon::Help,
+];
+
 #[derive(Clone, Copy)]
 #[repr(C)] /* should align to machine word */
 pub struct TaggedWord {
@@ -130,23 +417,23 @@ pub union ParamUnion {
     pub real: f32,
     pub short_color: (u8, u8, u8),
     pub long_color: (u8, u8, u8, u8),
-    pub display_option: DisplayOption,
-    pub font_alignment: StoredAlignment,
     pub _debug_bytes: [u8; size_of::<usize>()],
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, strum::FromRepr)]
 #[repr(usize)]
 #[allow(dead_code)]
 pub enum DisplayOption {
     Block = 0,
-    FlexRow,    /* 1 */
-    FlexColumn, /* 2 */
-    Grid,       /* 3 */
-    None,       /* 4 hidden */
+    FlexRow,           /* 1 */
+    FlexColumn,        /* 2 */
+    Grid,              /* 3 */
+    None,              /* 4 hidden */
+    FlexRowReverse,    /* 5 */
+    FlexColumnReverse, /* 6 bottom to top, e.g. a chat log */
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, strum::FromRepr)]
 #[repr(usize)]
 #[allow(dead_code)]
 pub enum StoredAlignment {
@@ -158,46 +445,158 @@ pub enum StoredAlignment {
     Justified,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::FromRepr)]
+#[repr(usize)]
+#[allow(dead_code)]
+pub enum StoredAlignItems {
+    Start = 0,
+    End,      /* 1 */
+    Center,   /* 2 */
+    Baseline, /* 3 */
+    Stretch,  /* 4 */
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::FromRepr)]
+#[repr(usize)]
+#[allow(dead_code)]
+pub enum StoredAlignContent {
+    Start = 0,
+    End,          /* 1 */
+    Center,       /* 2 */
+    Stretch,      /* 3 */
+    SpaceBetween, /* 4 */
+    SpaceEvenly,  /* 5 */
+    SpaceAround,  /* 6 */
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::FromRepr)]
+#[repr(usize)]
+#[allow(dead_code)]
+pub enum StoredWordBreak {
+    Normal = 0,
+    BreakAll, /* 1 */
+    KeepAll,  /* 2 */
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::FromRepr)]
+#[repr(usize)]
+#[allow(dead_code)]
+pub enum StoredWhiteSpace {
+    Preserve = 0,
+    Collapse, /* 1 */
+}
+
+/// One edge of a box, for the tags that set a single side of the padding or margin.
+#[derive(Debug, Clone, Copy)]
+pub enum Side {
+    Left,
+    Top,
+    Right,
+    Bottom,
+}
+impl Side {
+    pub(super) fn of<T>(self, rect: &mut taffy::Rect<T>) -> &mut T {
+        match self {
+            Side::Left => &mut rect.left,
+            Side::Top => &mut rect.top,
+            Side::Right => &mut rect.right,
+            Side::Bottom => &mut rect.bottom,
+        }
+    }
+}
+
 /* :----- Defines the structure within a tagged word. ie how to inteprete the `word` bytes given a tag -----: */
-trait ExtractFromWord {
-    fn extract(param: &ParamUnion) -> Self;
+trait ExtractFromWord: Sized {
+    /// `None` if the word isn't one of the values `Self` can have.
+    fn extract(param: &ParamUnion) -> Option<Self>;
+
+    /// Rejects values the rest of the runtime can't work with, they come straight from the client.
+    fn check(self, _tag: Tag) -> Result<Self> {
+        Ok(self)
+    }
 }
 impl ExtractFromWord for usize {
-    fn extract(param: &ParamUnion) -> Self {
-        unsafe { param.word }
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        Some(unsafe { param.word })
     }
 }
 impl ExtractFromWord for f32 {
-    fn extract(param: &ParamUnion) -> Self {
-        unsafe { param.real }
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        Some(unsafe { param.real })
+    }
+
+    fn check(self, tag: Tag) -> Result<Self> {
+        finite(tag, self)
     }
 }
 impl ExtractFromWord for () {
-    fn extract(_param: &ParamUnion) -> Self {
-        ()
+    fn extract(_param: &ParamUnion) -> Option<Self> {
+        Some(())
     }
 }
+// The options are read from the whole word and matched against the enum's values, reading a
+// `repr(usize)` enum straight out of the union would be undefined for a value it doesn't have.
 impl ExtractFromWord for DisplayOption {
-    fn extract(param: &ParamUnion) -> Self {
-        unsafe { param.display_option }
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        DisplayOption::from_repr(unsafe { param.word })
     }
 }
 impl ExtractFromWord for StoredAlignment {
-    fn extract(param: &ParamUnion) -> Self {
-        unsafe { param.font_alignment }
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        StoredAlignment::from_repr(unsafe { param.word })
+    }
+}
+impl ExtractFromWord for StoredAlignItems {
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        StoredAlignItems::from_repr(unsafe { param.word })
+    }
+}
+impl ExtractFromWord for StoredAlignContent {
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        StoredAlignContent::from_repr(unsafe { param.word })
+    }
+}
+impl ExtractFromWord for StoredWordBreak {
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        StoredWordBreak::from_repr(unsafe { param.word })
+    }
+}
+impl ExtractFromWord for StoredWhiteSpace {
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        StoredWhiteSpace::from_repr(unsafe { param.word })
     }
 }
 
 impl ExtractFromWord for ParamUnion {
-    fn extract(param: &ParamUnion) -> Self {
-        param.clone()
+    fn extract(param: &ParamUnion) -> Option<Self> {
+        Some(param.clone())
+    }
+}
+/// A NaN or infinity would make it through taffy into skia and break the frame in ways that
+/// are hard to trace back to the word that caused it, so every real is checked when it's read.
+fn finite(tag: Tag, value: f32) -> Result<f32> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(anyhow!(
+            "`{}` holds {}, but only finite numbers are allowed.",
+            tag,
+            value
+        ))
     }
 }
+
 macro_rules! define_reader {
     ($name:ident, $tag:path, $return_type:ty) => {
         pub fn $name(&self) -> Result<$return_type> {
             match &self.tag {
-                $tag => Ok(<$return_type as ExtractFromWord>::extract(&self.word)),
+                $tag => <$return_type as ExtractFromWord>::extract(&self.word)
+                    .ok_or(anyhow!(
+                        "`{}` holds {}, which isn't one of its options.",
+                        self.tag,
+                        unsafe { self.word.word }
+                    ))?
+                    .check(self.tag),
                 _ => Err(anyhow!(
                     concat!(
                         "Expected `",
@@ -218,6 +617,8 @@ macro_rules! define_reader {
 impl TaggedWord {
     define_reader!(read_as_array, Tag::Array, usize);
     define_reader!(read_as_event, Tag::Event, usize);
+    define_reader!(read_as_on_scroll_report, Tag::OnScrollReport, usize);
+    define_reader!(read_as_key, Tag::Key, usize);
     define_reader!(read_as_hover, Tag::Hover, usize);
     define_reader!(read_as_mouse_pressed, Tag::MousePressed, usize);
     define_reader!(read_as_clicked, Tag::Clicked, usize);
@@ -225,15 +626,84 @@ impl TaggedWord {
     define_reader!(read_as_jmp, Tag::Jmp, usize);
     define_reader!(read_as_text_ptr, Tag::TextPtr, usize);
     define_reader!(read_as_display, Tag::Display, DisplayOption);
-    define_reader!(read_as_font_size, Tag::FontSize, f32);
+    define_reader!(read_as_font_size_any, Tag::FontSize, f32);
+    define_reader!(read_as_font_size_em_any, Tag::FontSizeEm, f32);
+    define_reader!(read_as_opacity, Tag::Opacity, f32);
     define_reader!(read_as_font_alignment, Tag::FontAlignment, StoredAlignment);
+    define_reader!(read_as_align_items, Tag::AlignItems, StoredAlignItems);
+    define_reader!(read_as_justify_items, Tag::JustifyItems, StoredAlignItems);
+    define_reader!(
+        read_as_justify_content,
+        Tag::JustifyContent,
+        StoredAlignContent
+    );
+    define_reader!(read_as_align_content, Tag::AlignContent, StoredAlignContent);
+    define_reader!(read_as_grid_columns, Tag::GridColumns, usize);
+    define_reader!(read_as_grid_rows, Tag::GridRows, usize);
+    define_reader!(read_as_tab_width_any, Tag::TabWidth, usize);
+    define_reader!(read_as_word_break, Tag::WordBreak, StoredWordBreak);
+    define_reader!(read_as_white_space, Tag::WhiteSpace, StoredWhiteSpace);
+    define_reader!(read_as_pixel_snap_text, Tag::PixelSnapText, usize);
+    define_reader!(read_as_text_color_run, Tag::TextColorRun, usize);
+    define_reader!(read_as_inline_box, Tag::InlineBox, usize);
+    define_reader!(read_as_focus_visible, Tag::FocusVisible, usize);
     define_reader!(read_as_load_register, Tag::LoadReg, usize);
+    define_reader!(read_as_library_call, Tag::LibraryCall, usize);
+    define_reader!(read_as_format_number, Tag::FormatNumber, usize);
+    define_reader!(read_as_show_if, Tag::ShowIf, usize);
+    define_reader!(read_as_button_pressed, Tag::ButtonPressed, usize);
+    define_reader!(read_as_key_down, Tag::KeyDown, usize);
+    define_reader!(read_as_key_up, Tag::KeyUp, usize);
+    define_reader!(read_as_key_press, Tag::KeyPress, usize);
+    define_reader!(read_as_color_transition, Tag::ColorTransition, usize);
+
+    pub fn read_as_font_size(&self) -> Result<f32> {
+        self.non_negative(self.read_as_font_size_any()?)
+    }
+
+    pub fn read_as_font_size_em(&self) -> Result<f32> {
+        self.non_negative(self.read_as_font_size_em_any()?)
+    }
+
+    pub fn read_as_tab_width(&self) -> Result<usize> {
+        let columns = self.read_as_tab_width_any()?;
+        if !(1..=MAX_TAB_WIDTH).contains(&columns) {
+            return Err(anyhow!(
+                "`TabWidth` holds {}, but it must be between 1 and {} columns.",
+                columns,
+                MAX_TAB_WIDTH
+            ));
+        }
+        Ok(columns)
+    }
+
+    /// The real of a pulled font size, whatever tag it was stored with.
+    pub fn read_as_any_font_size(&self) -> Result<f32> {
+        self.non_negative(self.real()?)
+    }
+
+    /// For reals that are a size, where a negative one can only be a mistake.
+    fn non_negative(&self, value: f32) -> Result<f32> {
+        if value < 0.0 {
+            return Err(anyhow!(
+                "`{}` holds {}, but it's a size and can't be negative.",
+                self.tag,
+                value
+            ));
+        }
+        Ok(value)
+    }
+
+    /// The bare real of a `Pxs`, `Rems`, `Vw`, `Vh`, or `Frac`, checked to be finite.
+    fn real(&self) -> Result<f32> {
+        finite(self.tag, unsafe { self.word.real })
+    }
 
     pub fn read_as_any_color(&self) -> Result<Color> {
         match &self.tag {
             Tag::Rgb => {
                 let (r, g, b) = unsafe { self.word.short_color };
-                Ok(RGB { r, g, b }.to_hsv().to_color(255))
+                Ok(Color::from_rgb(r, g, b))
             }
             Tag::Hsv => {
                 let (h, s, v) = unsafe { self.word.short_color };
@@ -245,8 +715,10 @@ impl TaggedWord {
                 .to_color(255))
             }
             Tag::Rgba => {
+                // The bytes as they are, a detour through HSV would round them and can shift
+                // the hue of dark colours.
                 let (r, g, b, a) = unsafe { self.word.long_color };
-                Ok(RGB { r, g, b }.to_hsv().to_color(a))
+                Ok(Color::from_argb(a, r, g, b))
             }
             Tag::Hsva => {
                 let (h, s, v, a) = unsafe { self.word.long_color };
@@ -269,48 +741,68 @@ impl TaggedWord {
         }
     }
 
-    pub fn read_as_taffy_length_pct(
-        &self,
-        base_font_size: f32,
-        display_scale: f32,
-    ) -> Result<taffy::LengthPercentage> {
+    pub fn read_as_taffy_length_pct(&self, units: LengthUnits) -> Result<taffy::LengthPercentage> {
         match &self.tag {
-            Tag::Pxs => Ok(taffy::LengthPercentage::length(
-                unsafe { self.word.real } * display_scale,
-            )),
-            Tag::Rems => Ok(taffy::LengthPercentage::length(
-                base_font_size * unsafe { self.word.real } * display_scale,
-            )),
-            Tag::Frac => Ok(taffy::LengthPercentage::percent(unsafe { self.word.real })),
-            _ => Err(anyhow!(
-                "Expected `Pxs`, `Rems`,  or `Frac` tagged word, got `{}` instead",
-                if self.tag as usize <= Tag::COUNT {
-                    format!("{}", self.tag)
-                } else {
-                    format!("corupted tag ({})", self.tag as usize)
-                },
-            )),
+            Tag::Frac => Ok(taffy::LengthPercentage::percent(self.real()?)),
+            _ => {
+                let length = self.read_as_absolute_length(units)?.ok_or(anyhow!(
+                    "Expected `Pxs`, `Rems`, `Vw`, `Vh`, or `Frac` tagged word, got `{}` instead",
+                    self.tag_name()
+                ))?;
+                Ok(taffy::LengthPercentage::length(length))
+            }
         }
     }
 
+    /// Like `read_as_taffy_length_pct`, for widths, gaps, and the like that can't be negative.
+    pub fn read_as_taffy_size_pct(&self, units: LengthUnits) -> Result<taffy::LengthPercentage> {
+        let length = self.read_as_taffy_length_pct(units)?;
+        self.non_negative(self.real()?)?;
+        Ok(length)
+    }
+
     pub fn read_as_taffy_length_pctauto(
         &self,
-        base_font_size: f32,
-        display_scale: f32,
+        units: LengthUnits,
     ) -> Result<taffy::LengthPercentageAuto> {
         match &self.tag {
             Tag::Auto => Ok(taffy::LengthPercentageAuto::auto()),
-            Tag::Pxs => Ok(taffy::LengthPercentageAuto::length(
-                unsafe { self.word.real } * display_scale,
-            )),
-            Tag::Rems => Ok(taffy::LengthPercentageAuto::length(
-                base_font_size * unsafe { self.word.real } * display_scale,
-            )),
-            Tag::Frac => Ok(taffy::LengthPercentageAuto::percent(unsafe {
-                self.word.real
-            })),
+            Tag::Frac => Ok(taffy::LengthPercentageAuto::percent(self.real()?)),
+            _ => {
+                let length = self.read_as_absolute_length(units)?.ok_or(anyhow!(
+                    "Expected `Pxs`, `Rems`, `Vw`, `Vh`, `Auto`, or `Frac` tagged word, got `{}` instead",
+                    self.tag_name()
+                ))?;
+                Ok(taffy::LengthPercentageAuto::length(length))
+            }
+        }
+    }
+
+    /// The units that are the same length wherever they are used, in the layout's pixels.
+    /// `None` for any other tag.
+    fn read_as_absolute_length(&self, units: LengthUnits) -> Result<Option<f32>> {
+        Ok(Some(match &self.tag {
+            Tag::Pxs => self.real()? * units.display_scale,
+            Tag::Rems => units.base_font_size * self.real()? * units.display_scale,
+            Tag::Vw => self.real()? * units.viewport.0,
+            Tag::Vh => self.real()? * units.viewport.1,
+            _ => return Ok(None),
+        }))
+    }
+
+    fn tag_name(&self) -> String {
+        if self.tag as usize <= Tag::COUNT {
+            format!("{}", self.tag)
+        } else {
+            format!("corupted tag ({})", self.tag as usize)
+        }
+    }
+
+    pub fn read_as_number(&self) -> Result<f32> {
+        match &self.tag {
+            Tag::Pxs | Tag::Rems | Tag::Frac => self.real(),
             _ => Err(anyhow!(
-                "Expected `Pxs`, `Rems`, `Auto`, or `Frac` tagged word, got `{}` instead",
+                "Expected `Pxs`, `Rems`, or `Frac` tagged word, got `{}` instead",
                 if self.tag as usize <= Tag::COUNT {
                     format!("{}", self.tag)
                 } else {
@@ -324,6 +816,14 @@ impl TaggedWord {
         match &self.tag {
             Tag::CursorDefault => Ok(CursorIcon::Default),
             Tag::CursorPointer => Ok(CursorIcon::Pointer),
+            Tag::HoverCursor => {
+                let i = unsafe { self.word.word };
+                HOVER_CURSORS.get(i).copied().ok_or(anyhow!(
+                    "`HoverCursor` {} is not a cursor, expected 0 to {}",
+                    i,
+                    HOVER_CURSORS.len() - 1
+                ))
+            }
             _ => Err(anyhow!(
                 "Expected a tagged word of the `Cursor` family, got `{}` instead",
                 if self.tag as usize <= Tag::COUNT {
@@ -336,34 +836,142 @@ impl TaggedWord {
     }
 }
 
+/// What the draw pass needs from the window. Implemented by winit's window, and by anything
+/// else that wants to run `draw` without one.
+pub trait WindowLike {
+    fn set_cursor(&self, cursor: CursorIcon);
+    fn inner_size(&self) -> PhysicalSize<u32>;
+}
+impl WindowLike for Window {
+    fn set_cursor(&self, cursor: CursorIcon) {
+        Window::set_cursor(self, cursor);
+    }
+
+    fn inner_size(&self) -> PhysicalSize<u32> {
+        Window::inner_size(self)
+    }
+}
+
+/// Checks the tree at `loc` without drawing it. On error, also returns the offset of the
+/// tagged word that is wrong.
+///
+/// # Safety
+/// `file_start` to `file_end` has to be readable and not written to while this runs.
+pub unsafe fn validate(
+    loc: usize,
+    file_start: *const u8,
+    file_end: *const u8,
+    library: &HashMap<usize, Vec<usize>>,
+    vm_limits: VmLimits,
+) -> Result<(), (anyhow::Error, usize)> {
+    assert!(file_start as usize % size_of::<usize>() == 0);
+    check_root(loc, file_start, file_end).map_err(|err| (err, loc))?;
+
+    /* sizes don't matter, nothing is laid out */
+    let config = StaticConfig::new(
+        file_start,
+        file_end,
+        1.0,
+        1.0,
+        Duration::ZERO,
+        vm_limits,
+        ColorSpaceOption::Srgb,
+        true,
+        (0.0, 0.0), /* there's no window to be relative to, `Vw` and `Vh` are zero */
+    );
+    validate_pass(unsafe { file_start.add(loc) }, file_end, config, library)
+}
+
+/// Makes sure the root pointer is on an `Enter`. Getting it wrong by a few bytes is a common client
+/// mistake which the passes would only report as some corrupted or misplaced tag.
+fn check_root(loc: usize, file_start: *const u8, file_end: *const u8) -> Result<()> {
+    let len = file_end as usize - file_start as usize;
+    if loc % size_of::<usize>() != 0 {
+        return Err(anyhow!(
+            "Root pointer 0x{:x} is misaligned, it must be a multiple of {} to point at a tagged word.",
+            loc,
+            size_of::<usize>()
+        ));
+    }
+    if loc
+        .checked_add(size_of::<TaggedWord>())
+        .is_none_or(|end| end > len)
+    {
+        return Err(anyhow!(
+            "Root pointer 0x{:x} is past the end of the {} byte tree.",
+            loc,
+            len
+        ));
+    }
+
+    /* read the bare word, it may not be a valid `Tag` */
+    let raw_tag = unsafe { (file_start.add(loc) as *const usize).read() };
+    match Tag::from_repr(raw_tag) {
+        Some(Tag::Enter) => Ok(()),
+        Some(tag) => Err(anyhow!(
+            "Root pointer 0x{:x} points at `{}` instead of `Enter`, it may point into the middle of an element.",
+            loc,
+            tag
+        )),
+        None => Err(anyhow!(
+            "Root pointer 0x{:x} points at {} instead of `Enter`, which isn't a tag at all. Is it off by a few bytes?",
+            loc,
+            raw_tag
+        )),
+    }
+}
+
 //::::: ----- Finally the main draw call ------
-pub unsafe fn draw<F>(
+/// # Safety
+/// Like `validate`, the tree is read straight out of `file_start` to `file_end`.
+pub unsafe fn draw<F, W>(
     loc: usize,
     file_start: *const u8,
     file_end: *const u8,
     width: f32,
     height: f32,
     canvas: &Canvas,
-    window: Arc<Window>,
+    window: Arc<W>,
     cb_push_evt: F,
     input_state: &InputState,
     font_ctx: &mut FontContext,
     layout_ctx: &mut parley::LayoutContext<()>,
     display_scale: f32,
     base_font_size: f32,
-    frame_state: &HashMap<*const u8, CarriedState>,
+    frame_state: &HashMap<StateKey, CarriedState>,
+    library: &HashMap<usize, Vec<usize>>,
+    vm_limits: VmLimits,
+    color_space: ColorSpaceOption,
+    reduce_motion: bool,
     dt: Duration,
-) -> Result<HashMap<*const u8, CarriedState>>
+    paint_log: &mut PaintLog,
+    profile: bool,
+    debug_layout: bool,
+) -> Result<DrawnFrame>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
+    W: WindowLike,
 {
-    let config = StaticConfig::new(file_start, base_font_size, display_scale, dt);
+    let config = StaticConfig::new(
+        file_start,
+        file_end,
+        base_font_size,
+        display_scale,
+        dt,
+        vm_limits,
+        color_space,
+        reduce_motion,
+        (width, height),
+    );
 
     assert!(file_start as usize % size_of::<usize>() == 0);
-    assert!(unsafe { file_start.add(loc) } as usize % size_of::<usize>() == 0);
+    check_root(loc, file_start, file_end)?;
 
+    let mut profile = FrameProfile::new(profile);
     let region_start = unsafe { file_start.add(loc) };
-    let (root, mut tree) = layout_pass(region_start, file_end, config, frame_state)?;
+    let (root, mut tree, clear_color, instruction_count) =
+        layout_pass(region_start, file_end, config, frame_state, library)?;
+    profile.lap("layout pass");
     tree.compute_layout(
         root,
         taffy::Size {
@@ -371,17 +979,50 @@ where
             height: taffy::prelude::length(height),
         },
     )?;
+    profile.lap("compute_layout");
 
     // tree.print_tree(root);
 
-    text_pass(&mut tree, root, font_ctx, layout_ctx, config)?;
-    let mut next_frame_state: HashMap<*const u8, CarriedState> = HashMap::new();
-    let mut vm_state = VMState::new();
-    draw_pass(
+    text_pass(
+        &mut tree,
+        root,
+        font_ctx,
+        layout_ctx,
+        config,
+        base_font_size,
+        None,
+    )?;
+    align_baselines(&mut tree, root)?;
+    profile.lap("text pass");
+    let pointer = (
+        input_state.cursor_pos.x as f32,
+        input_state.cursor_pos.y as f32,
+    );
+    let boxes = element_boxes(&tree, root, frame_state)?;
+    let hovered = hit_pass(&boxes, pointer.into());
+    let key_scrolled = key_scroller(&boxes, pointer.into());
+    let root_key = tree.get_node_context(root).and_then(|ctx| ctx.state_key);
+    let (focused_node, focused) = focus_pass(
+        &tree,
+        &boxes,
+        pointer.into(),
+        input_state.mouse_just_pressed,
+        root_key
+            .and_then(|key| frame_state.get(&key))
+            .and_then(|x| x.focused_node),
+    );
+    profile.lap("hit pass");
+    let mut next_frame_state: HashMap<StateKey, CarriedState> = HashMap::new();
+    let mut vm_state = VMState::new(vm_limits);
+    let save_count = canvas.save_count();
+    let out = draw_pass(
         window,
         canvas,
+        font_ctx,
+        layout_ctx,
         0.0,
         0.0,
+        base_font_size,
         &mut vm_state,
         &mut tree,
         root,
@@ -389,8 +1030,325 @@ where
         frame_state,
         &mut next_frame_state,
         input_state,
+        &hovered,
+        &focused,
+        key_scrolled,
         config,
-    )?;
+        paint_log,
+    );
+    canvas.restore_to_count(save_count); /* an error may leave a scroll clip behind */
+    out?;
+    if let Some(key) = root_key {
+        next_frame_state
+            .entry(key)
+            .or_insert(CarriedState::new())
+            .focused_node = focused_node;
+    }
+    if debug_layout {
+        debug_pass(canvas, &tree, root, &next_frame_state, display_scale)?;
+    }
+    profile.lap("draw pass");
+    profile.report(tree.total_node_count(), instruction_count);
+
+    let scroll_y = root_key
+        .and_then(|key| next_frame_state.get(&key))
+        .map(|x| -x.scroll_y / display_scale)
+        .unwrap_or(0.0);
+    let animating = next_frame_state
+        .values()
+        .any(|x| x.color.is_some_and(|fade| !fade.is_settled()));
+    Ok(DrawnFrame {
+        state: next_frame_state,
+        animating,
+        boxes: boxes
+            .into_iter()
+            .map(|b| {
+                /* in "Pxs", like the viewport */
+                b.scaled(1.0 / display_scale).map(|node| {
+                    let key = tree.get_node_context(node).and_then(|ctx| ctx.state_key);
+                    ElementId::new(key, file_start, file_end)
+                })
+            })
+            .collect(),
+        clear_color,
+        viewport: Viewport {
+            scroll_y,
+            height: height / display_scale,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::window::CursorIcon;
+
+    use skia_safe::Color;
+
+    use super::{
+        DisplayOption, LengthUnits, ParamUnion, StoredAlignContent, StoredAlignItems,
+        StoredWhiteSpace, StoredWordBreak, Tag, TaggedWord,
+    };
+    use crate::ui::testing::{Headless, Tree, frac, pull, px, real, reg, rgb, rgba};
+
+    fn tagged(tag: Tag, word: usize) -> TaggedWord {
+        TaggedWord {
+            tag,
+            word: ParamUnion { word },
+        }
+    }
+
+    #[test]
+    fn draws_with_a_mock_window() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(100.0)])
+            .op(Tag::Height, &[px(50.0)])
+            .op(Tag::CursorPointer, &[])
+            .jump(Tag::Hover, &[], |t| {
+                t.push((Tag::Event, 7));
+            })
+            .leave();
+        let mut headless = Headless::new(200, 100);
+
+        headless.move_to(20.0, 20.0);
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.cursors, vec![CursorIcon::Pointer]);
+        assert!(
+            frame.event_ids().is_empty(),
+            "a hover shows from the next frame"
+        );
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.event_ids(), vec![7]);
+
+        headless.move_to(150.0, 80.0);
+        let frame = headless.frame(&tree).unwrap();
+        assert!(frame.cursors.is_empty(), "the pointer left the element");
+        headless.frame(&tree).unwrap();
+        let frame = headless.frame(&tree).unwrap();
+        assert!(frame.event_ids().is_empty());
+    }
+
+    #[test]
+    fn library_children_read_its_registers() {
+        let mut tree = Tree::new();
+        let label = tree.string("registers");
+        tree.library(1, |t| {
+            t.push((Tag::LoadReg, 0))
+                .push(rgb(0, 0, 255))
+                .push((Tag::LoadReg, 1))
+                .push((Tag::TextPtr, label))
+                .enter()
+                .op(Tag::Width, &[px(40.0)])
+                .op(Tag::Height, &[px(40.0)])
+                .op(Tag::Color, &[reg(0)])
+                .op(Tag::Rect, &[px(0.0), px(0.0), px(40.0), px(20.0)])
+                .op(Tag::Text, &[px(0.0), px(20.0), reg(1)])
+                .leave()
+                .push((Tag::LibraryReturn, 0));
+        });
+        tree.enter()
+            .op(Tag::Width, &[frac(1.0)])
+            .op(Tag::Height, &[frac(1.0)])
+            .push((Tag::LibraryCall, 1))
+            .leave();
+        let mut headless = Headless::new(80, 60);
 
-    Ok(next_frame_state)
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(20, 10), Color::from_rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn a_library_returns_a_value_on_the_stack() {
+        // The element the body enters on the way mustn't lose what it pushes after.
+        let mut tree = Tree::new();
+        tree.library(2, |t| {
+            t.enter()
+                .op(Tag::Width, &[px(10.0)])
+                .op(Tag::Height, &[px(10.0)])
+                .leave()
+                .op(Tag::PushArg, &[rgb(0, 0, 255)])
+                .push((Tag::LibraryReturn, 0));
+        });
+        tree.enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .push((Tag::LibraryCall, 2))
+            .op(Tag::Color, &[pull()])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(40.0), px(40.0)])
+            .leave();
+        let mut headless = Headless::new(40, 40);
+
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(30, 30), Color::from_rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn list_items_fire_their_own_ids() {
+        // One body for every row, the id comes from the register the caller loads.
+        let mut tree = Tree::new();
+        tree.library(1, |t| {
+            t.enter()
+                .op(Tag::Width, &[px(50.0)])
+                .op(Tag::Height, &[px(50.0)])
+                .jump(Tag::Clicked, &[], |t| {
+                    t.push((Tag::EventArg, 0)).push(reg(0));
+                })
+                .leave()
+                .push((Tag::LibraryReturn, 0));
+        });
+        tree.enter()
+            .op(Tag::Width, &[px(150.0)])
+            .op(Tag::Height, &[px(50.0)]);
+        for index in [10, 11, 12] {
+            tree.push((Tag::LoadReg, 0))
+                .push((Tag::Array, index))
+                .push((Tag::LibraryCall, 1));
+        }
+        tree.leave();
+        let mut headless = Headless::new(150, 50);
+
+        let mut clicked = Vec::new();
+        for x in [25.0, 75.0, 125.0] {
+            headless.move_to(x, 25.0).press().frame(&tree).unwrap();
+            headless.release().frame(&tree).unwrap();
+            clicked.extend(headless.frame(&tree).unwrap().event_ids());
+        }
+        assert_eq!(clicked, vec![10, 11, 12]);
+    }
+
+    #[test]
+    fn a_pushed_color_is_pulled() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(40.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .op(Tag::PushArg, &[rgb(0, 128, 0)])
+            .op(Tag::Color, &[pull()])
+            .op(Tag::Rect, &[px(0.0), px(0.0), px(40.0), px(40.0)])
+            .leave();
+        let mut headless = Headless::new(40, 40);
+
+        headless.frame(&tree).unwrap();
+        assert_eq!(headless.pixel(20, 20), Color::from_rgb(0, 128, 0));
+    }
+
+    #[test]
+    fn font_size_from_a_register() {
+        let tree = |size: f32| {
+            let mut tree = Tree::new();
+            tree.enter()
+                .enter()
+                .op(Tag::LoadReg, &[px(size)])
+                .op(Tag::FontSizeArg, &[reg(0)])
+                .text(px(0.0), px(0.0), "Ag")
+                .leave()
+                .leave();
+            tree
+        };
+        let mut headless = Headless::new(200, 200);
+        let mut height = |size: f32| {
+            let frame = headless.frame(&tree(size)).unwrap();
+            frame.drawn.boxes[1].bounds.height()
+        };
+
+        assert!(height(40.0) > 2.0 * height(12.0));
+        assert!(headless.frame(&tree(-1.0)).is_err());
+    }
+
+    #[test]
+    fn boxes_are_in_the_viewports_pixels() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .enter()
+            .op(Tag::Width, &[px(50.0)])
+            .op(Tag::Height, &[px(30.0)])
+            .leave()
+            .leave();
+        let mut headless = Headless::new(200, 200);
+        headless.scale = 2.0;
+
+        let frame = headless.frame(&tree).unwrap();
+        assert_eq!(frame.drawn.viewport.height, 100.0);
+        let bounds = frame.drawn.boxes[1].bounds;
+        assert_eq!((bounds.width(), bounds.height()), (50.0, 30.0));
+    }
+
+    #[test]
+    fn rgba_keeps_its_bytes() {
+        let color = |word: (Tag, usize)| tagged(word.0, word.1).read_as_any_color().unwrap();
+        assert_eq!(
+            color(rgba(255, 128, 64, 200)),
+            Color::from_argb(200, 255, 128, 64)
+        );
+        // Dark and see-through, where a detour through HSV would lose the hue.
+        assert_eq!(color(rgba(3, 1, 2, 0)), Color::from_argb(0, 3, 1, 2));
+        assert_eq!(color(rgb(255, 128, 64)), Color::from_rgb(255, 128, 64));
+    }
+
+    #[test]
+    fn reals_must_be_finite() {
+        let units = LengthUnits {
+            base_font_size: 16.0,
+            display_scale: 1.0,
+            viewport: (100.0, 100.0),
+        };
+        let read = |(tag, word)| tagged(tag, word);
+        for bad in [f32::NAN, -f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(read(real(Tag::FontSize, bad)).read_as_font_size().is_err());
+            assert!(read(real(Tag::Opacity, bad)).read_as_opacity().is_err());
+            assert!(read(px(bad)).read_as_taffy_length_pct(units).is_err());
+            assert!(read(frac(bad)).read_as_any_font_size().is_err());
+        }
+        assert!(read(real(Tag::FontSize, -1.0)).read_as_font_size().is_err());
+
+        // In a tree it fails the frame with an error rather than reaching taffy and skia.
+        let mut tree = Tree::new();
+        tree.enter().op(Tag::Width, &[px(f32::NAN)]).leave();
+        let err = Headless::new(40, 40).frame(&tree).unwrap_err();
+        assert!(format!("{:#}", err).contains("finite"), "{:#}", err);
+    }
+
+    #[test]
+    fn options_must_be_in_range() {
+        assert!(matches!(
+            tagged(Tag::Display, 6).read_as_display(),
+            Ok(DisplayOption::FlexColumnReverse)
+        ));
+        assert!(tagged(Tag::Display, 7).read_as_display().is_err());
+        assert!(
+            tagged(Tag::FontAlignment, 6)
+                .read_as_font_alignment()
+                .is_err()
+        );
+        assert_eq!(
+            tagged(Tag::AlignItems, 4).read_as_align_items().unwrap(),
+            StoredAlignItems::Stretch
+        );
+        assert!(
+            tagged(Tag::AlignItems, usize::MAX)
+                .read_as_align_items()
+                .is_err()
+        );
+        assert_eq!(
+            tagged(Tag::AlignContent, 6)
+                .read_as_align_content()
+                .unwrap(),
+            StoredAlignContent::SpaceAround
+        );
+        assert!(
+            tagged(Tag::AlignContent, 7)
+                .read_as_align_content()
+                .is_err()
+        );
+        assert_eq!(
+            tagged(Tag::WordBreak, 2).read_as_word_break().unwrap(),
+            StoredWordBreak::KeepAll
+        );
+        assert!(tagged(Tag::WordBreak, 3).read_as_word_break().is_err());
+        assert_eq!(
+            tagged(Tag::WhiteSpace, 1).read_as_white_space().unwrap(),
+            StoredWhiteSpace::Collapse
+        );
+        assert!(tagged(Tag::WhiteSpace, 2).read_as_white_space().is_err());
+    }
 }
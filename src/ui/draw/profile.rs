@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+// ::: ---- `--profile`, where the time of a frame goes ---- :::
+// Times each pass of `draw` and logs them with the size of the tree, so it's clear whether the
+// layout or the text is worth caching first. Switched off it never reads the clock.
+
+pub(super) struct FrameProfile {
+    last: Option<Instant>, /* `None` while profiling is off */
+    laps: Vec<(&'static str, Duration)>,
+}
+impl FrameProfile {
+    pub fn new(on: bool) -> Self {
+        Self {
+            last: on.then(Instant::now),
+            laps: Vec::new(),
+        }
+    }
+
+    /// Ends the lap of `pass`, the time since the last lap or since the frame started.
+    pub fn lap(&mut self, pass: &'static str) {
+        if let Some(last) = self.last.as_mut() {
+            let now = Instant::now();
+            self.laps.push((pass, now - *last));
+            *last = now;
+        }
+    }
+
+    pub fn report(&self, nodes: usize, instructions: usize) {
+        if self.last.is_none() {
+            return;
+        }
+        let total: Duration = self.laps.iter().map(|(_, time)| *time).sum();
+        let laps = self
+            .laps
+            .iter()
+            .map(|(pass, time)| format!("{} {:.3}ms", pass, time.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(
+            "Frame took {:.3}ms: {} ({} nodes, {} instructions)",
+            total.as_secs_f64() * 1000.0,
+            laps,
+            nodes,
+            instructions
+        );
+    }
+}
@@ -1,12 +1,18 @@
 use anyhow::Result;
 use anyhow::anyhow;
 use parley::{
-    Alignment, AlignmentOptions, FontContext, FontWeight, Layout, LayoutContext, StyleProperty,
+    Alignment, AlignmentOptions, FontContext, FontWeight, InlineBox, Layout, LayoutContext,
+    StyleProperty, style::WordBreakStrength,
 };
-use skia_safe::{Canvas, Font, FontMgr, FontStyle, Paint, TextBlob};
+use skia_safe::{Canvas, Font, FontMgr, FontStyle, Paint, PaintStyle, Rect, TextBlob};
 use smallvec::SmallVec;
 use std::borrow::Cow;
 
+use super::StoredWhiteSpace;
+
+/* more columns than this is a mistake, not a layout */
+pub const MAX_TAB_WIDTH: usize = 64;
+
 pub fn layout_text(
     text: &str,
     max_width: f32,
@@ -15,8 +21,13 @@ pub fn layout_text(
     layout_ctx: &mut LayoutContext<()>,
     font_family: &str,
     font_size: f32,
+    tab_width: usize,
+    white_space: StoredWhiteSpace,
+    word_break: WordBreakStrength,
+    inline_boxes: &[(usize, f32, f32)], /* offset into the text after `expand_tabs`, width, height */
     display_scale: f32,
 ) -> Layout<()> {
+    let text = &expand_tabs(text, tab_width, white_space);
     let mut builder = layout_ctx.ranged_builder(font_ctx, text, display_scale, true);
     builder.push_default(StyleProperty::FontSize(font_size));
     builder.push_default(StyleProperty::FontStack(parley::FontStack::Source(
@@ -24,6 +35,15 @@ pub fn layout_text(
     )));
     builder.push_default(StyleProperty::FontWeight(FontWeight::NORMAL));
     builder.push_default(StyleProperty::LetterSpacing(0.1));
+    builder.push_default(StyleProperty::WordBreak(word_break));
+    for (i, (index, width, height)) in inline_boxes.iter().enumerate() {
+        builder.push_inline_box(InlineBox {
+            id: i as u64,
+            index: *index,
+            width: *width,
+            height: *height,
+        });
+    }
 
     let mut layout: Layout<()> = builder.build(&text);
     layout.break_all_lines(Some(max_width));
@@ -31,6 +51,65 @@ pub fn layout_text(
     layout
 }
 
+// Parley shapes a tab like any other character, so replace each with spaces up to the next stop.
+// Stops are counted in characters, which lines columns up exactly in monospaced fonts. With
+// `Collapse` each run of spaces, tabs and line breaks becomes a single space instead, like
+// text in HTML, so text with the indentation of the source it came from can flow.
+fn expand_tabs(text: &str, tab_width: usize, white_space: StoredWhiteSpace) -> Cow<'_, str> {
+    if white_space == StoredWhiteSpace::Preserve && !text.contains('\t') {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(expand_tabs_up_to(text, text.len(), tab_width, white_space))
+}
+
+/// Where byte `offset` of `text` ends up after `expand_tabs`.
+pub fn expanded_offset(
+    text: &str,
+    offset: usize,
+    tab_width: usize,
+    white_space: StoredWhiteSpace,
+) -> usize {
+    expand_tabs_up_to(text, offset, tab_width, white_space).len()
+}
+
+fn expand_tabs_up_to(
+    text: &str,
+    end: usize,
+    tab_width: usize,
+    white_space: StoredWhiteSpace,
+) -> String {
+    let mut out = String::with_capacity(end);
+    let mut column = 0;
+    let mut in_space = false;
+    for c in text[..end].chars() {
+        if white_space == StoredWhiteSpace::Collapse && c.is_whitespace() {
+            if !in_space {
+                out.push(' ');
+                column += 1;
+            }
+            in_space = true;
+            continue;
+        }
+        in_space = false;
+        match c {
+            '\t' => {
+                let n = tab_width - column % tab_width;
+                out.extend(std::iter::repeat_n(' ', n));
+                column += n;
+            }
+            '\n' => {
+                out.push(c);
+                column = 0;
+            }
+            _ => {
+                out.push(c);
+                column += 1;
+            }
+        }
+    }
+    out
+}
+
 pub fn draw_text(
     layout: &Layout<()>,
     x: f32,
@@ -39,6 +118,9 @@ pub fn draw_text(
     paint: &Paint,
     font_family: &str,
     font_size: f32,
+    baseline_shift: f32,
+    pixel_snap: bool,
+    color_run: Option<(usize, &Paint)>, /* from this byte of the laid out text on, use this paint */
     display_scale: f32,
 ) -> Result<()> {
     let fntmgr = FontMgr::new();
@@ -48,10 +130,14 @@ pub fn draw_text(
             "Could not find font with for family {:?}",
             font_family
         ))?;
-    let skia_font = Font::new(typeface, font_size * display_scale);
+    let mut skia_font = Font::new(typeface, font_size * display_scale);
+    skia_font.set_subpixel(!pixel_snap);
+    /* whole pixels are crisper for small text, fractional ones move smoothly for large or animated text */
+    let place = |v: f32| if pixel_snap { v.round() } else { v };
 
     let mut paint = paint.clone();
     paint.set_anti_alias(true);
+    paint.set_style(PaintStyle::Fill); /* "StrokeWidth" is for shapes, glyphs stay solid */
 
     // let start = std::time::Instant::now();
     for line in layout.lines() {
@@ -59,31 +145,58 @@ pub fn draw_text(
             match item {
                 parley::PositionedLayoutItem::GlyphRun(glyph_run) => {
                     let mut run_x = glyph_run.offset() + x;
-                    let run_y = glyph_run.baseline() + y;
+                    /* positive is up, like `BaselineShift` */
+                    let run_y = place(glyph_run.baseline() + y - baseline_shift);
 
-                    // Collect all the glyphs
-                    let mut glyph_ids: SmallVec<[skia_safe::GlyphId; 128]> = SmallVec::new();
-                    let mut positions: SmallVec<[f32; 128]> = SmallVec::new();
+                    // Collect all the glyphs, split by colour. Clusters know which part of the
+                    // text their glyphs came from.
+                    let mut parts: [(SmallVec<[skia_safe::GlyphId; 128]>, SmallVec<[f32; 128]>);
+                        2] = Default::default();
 
-                    for glyph in glyph_run.glyphs() {
-                        glyph_ids.push(glyph.id as skia_safe::GlyphId);
-                        positions.push(run_x + glyph.x);
-                        run_x += glyph.advance;
+                    for cluster in glyph_run.run().visual_clusters() {
+                        let part = match color_run {
+                            Some((at, _)) if cluster.text_range().start >= at => 1,
+                            _ => 0,
+                        };
+                        for glyph in cluster.glyphs() {
+                            parts[part].0.push(glyph.id as skia_safe::GlyphId);
+                            parts[part].1.push(place(run_x + glyph.x));
+                            run_x += glyph.advance;
+                        }
                     }
 
-                    // Render this run together
-                    let blob = TextBlob::from_pos_text_h(
-                        &glyph_ids.as_slice(),
-                        &positions,
-                        run_y,
-                        &skia_font,
-                    )
-                    .ok_or(anyhow!("Coudln't create TextBlob for run."))?;
+                    // Render each colour of this run together
+                    for (part, (glyph_ids, positions)) in parts.iter().enumerate() {
+                        if glyph_ids.is_empty() {
+                            continue;
+                        }
+                        let blob = TextBlob::from_pos_text_h(
+                            &glyph_ids.as_slice(),
+                            positions,
+                            run_y,
+                            &skia_font,
+                        )
+                        .ok_or(anyhow!("Coudln't create TextBlob for run."))?;
 
-                    canvas.draw_text_blob(blob, (0.0, 0.0), &paint);
+                        let paint = match (part, color_run) {
+                            (1, Some((_, run_paint))) => run_paint,
+                            _ => &paint,
+                        };
+                        canvas.draw_text_blob(blob, (0.0, 0.0), paint);
+                    }
                 }
 
-                parley::PositionedLayoutItem::InlineBox(_) => todo!(),
+                // Nothing to put in there yet, so the reserved space is filled with the
+                // pencil colour as a placeholder.
+                parley::PositionedLayoutItem::InlineBox(inline_box) => {
+                    let rect = Rect::from_xywh(
+                        x + inline_box.x,
+                        y + inline_box.y - baseline_shift,
+                        inline_box.width,
+                        inline_box.height,
+                    );
+                    canvas.draw_rect(rect, &paint);
+                }
             }
         }
     }
@@ -91,3 +204,146 @@ pub fn draw_text(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use parley::{Alignment, FontContext, LayoutContext, style::WordBreakStrength};
+    use skia_safe::{Color, Paint};
+
+    use super::{
+        MAX_TAB_WIDTH, StoredWhiteSpace, draw_text, expand_tabs, expanded_offset, layout_text,
+    };
+
+    #[test]
+    fn tabs_move_to_the_next_stop() {
+        let text = "a\tb\nccc\td\teeeee\tf";
+        assert_eq!(
+            expand_tabs(text, 4, StoredWhiteSpace::Preserve),
+            "a   b\nccc d   eeeee   f"
+        );
+        assert_eq!(
+            expanded_offset(text, text.find('d').unwrap(), 4, StoredWhiteSpace::Preserve),
+            10
+        );
+        assert_eq!(
+            expand_tabs("\t|", MAX_TAB_WIDTH, StoredWhiteSpace::Preserve).len(),
+            MAX_TAB_WIDTH + 1
+        );
+    }
+
+    #[test]
+    fn collapses_white_space() {
+        let text = "  two\t\tcolumns\n   and  lines ";
+        assert_eq!(
+            expand_tabs(text, 8, StoredWhiteSpace::Collapse),
+            " two columns and lines "
+        );
+        assert_eq!(
+            expanded_offset(
+                text,
+                text.find("and").unwrap(),
+                8,
+                StoredWhiteSpace::Collapse
+            ),
+            13
+        );
+    }
+
+    #[test]
+    fn table_columns_line_up() {
+        let text = "id\tname\tsize\n1\tlibc\t2048\n10234\tz\t1";
+        let mut font_ctx = FontContext::new();
+        let mut layout_ctx = LayoutContext::new();
+        let layout = layout_text(
+            text,
+            1000.0,
+            Alignment::Start,
+            &mut font_ctx,
+            &mut layout_ctx,
+            "monospace",
+            14.0,
+            8,
+            StoredWhiteSpace::Preserve,
+            WordBreakStrength::Normal,
+            &[],
+            1.0,
+        );
+
+        // Where each line's second and third column start, by the first character in them.
+        let mut starts = Vec::new();
+        for line in layout.lines() {
+            let mut columns = Vec::new();
+            let mut after_space = false;
+            for item in line.items() {
+                let parley::PositionedLayoutItem::GlyphRun(glyph_run) = item else {
+                    continue;
+                };
+                let mut x = glyph_run.offset();
+                for cluster in glyph_run.run().visual_clusters() {
+                    let space = cluster.is_space_or_nbsp();
+                    if after_space && !space {
+                        columns.push(x);
+                    }
+                    after_space = space;
+                    x += cluster.advance();
+                }
+            }
+            starts.push(columns);
+        }
+        assert_eq!(starts.len(), 3);
+        for columns in &starts[1..] {
+            assert_eq!(columns.len(), 2);
+            for (x, header) in columns.iter().zip(&starts[0]) {
+                assert!((x - header).abs() < 0.01, "{:?}", starts);
+            }
+        }
+    }
+
+    #[test]
+    fn a_positive_shift_raises_the_glyphs() {
+        let mut font_ctx = FontContext::new();
+        let mut layout_ctx = LayoutContext::new();
+        let layout = layout_text(
+            "H",
+            100.0,
+            Alignment::Start,
+            &mut font_ctx,
+            &mut layout_ctx,
+            "Arial",
+            20.0,
+            8,
+            StoredWhiteSpace::Preserve,
+            WordBreakStrength::Normal,
+            &[],
+            1.0,
+        );
+        // The first row with ink in it, drawn at y 20 with the shift.
+        let top = |shift: f32| {
+            let mut surface = skia_safe::surfaces::raster_n32_premul((40, 60)).unwrap();
+            surface.canvas().clear(Color::WHITE);
+            let mut paint = Paint::default();
+            paint.set_color(Color::BLACK);
+            draw_text(
+                &layout,
+                0.0,
+                20.0,
+                surface.canvas(),
+                &paint,
+                "Arial",
+                20.0,
+                shift,
+                true,
+                None,
+                1.0,
+            )
+            .unwrap();
+            let pixels = surface.peek_pixels().unwrap();
+            (0..60)
+                .find(|&y| (0..40).any(|x| pixels.get_color((x, y)) != Color::WHITE))
+                .unwrap()
+        };
+
+        assert_eq!(top(10.0), top(0.0) - 10);
+        assert_eq!(top(-10.0), top(0.0) + 10);
+    }
+}
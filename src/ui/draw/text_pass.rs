@@ -1,15 +1,17 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use parley::FontContext;
+use parley::style::WordBreakStrength;
+use skia_safe::Color;
 use taffy::{NodeId, PrintTree, TaffyTree, TraversePartialTree};
 
 use super::cursors::RaggedCursor;
 use super::layout_pass::LayoutContext;
-use super::text::layout_text;
+use super::text::{expanded_offset, layout_text};
 
-use super::StoredAlignment;
 use super::traits::{Executor, HasStaticConfig, Intepreter};
-use super::utils::StaticConfig;
-use super::vm_state::VMState;
+use super::utils::{StaticConfig, resolve_taffy_length};
+use super::vm_state::{Scopes, VMState};
+use super::{PassTrace, StoredAlignment, StoredWhiteSpace, StoredWordBreak, TaggedWord};
 
 // ::: ---- Second Pass, Layout Text ----:::
 
@@ -24,9 +26,19 @@ pub(super) struct TextLayoutIntepreter<'a> {
     font_alignment: parley::Alignment,
     font_family: String,
     font_size: f32,
+    parent_font_size: f32, /* children inherit the font size, `FontSizeEm` is relative to it */
+    tab_width: usize,
+    white_space: StoredWhiteSpace,
+    word_break: WordBreakStrength,
+    baseline_shift: taffy::LengthPercentage,
+    color_split: Option<usize>,
+    inline_boxes: Vec<(usize, taffy::LengthPercentage, taffy::LengthPercentage)>,
 
     tree: &'a mut TaffyTree<LayoutContext>,
     node: NodeId,
+    show_if_i: usize,
+    entered: bool,                     /* the node's own `Enter` ran */
+    child_scopes: Vec<Option<Scopes>>, /* at each child's `Enter`, see `text_pass` */
 }
 
 impl<'a> TextLayoutIntepreter<'a> {
@@ -37,10 +49,16 @@ impl<'a> TextLayoutIntepreter<'a> {
         font_context: &'a mut FontContext,
         layout_context: &'a mut parley::LayoutContext<()>,
         config: StaticConfig,
+        parent_font_size: f32,
+        scopes: Option<Scopes>,
     ) -> Result<Self> {
+        let mut state = VMState::new(config.vm_limits());
+        if let Some(scopes) = scopes {
+            state.swap_scopes(scopes);
+        }
         Ok(Self {
             config,
-            state: VMState::new(),
+            state,
             cursor: RaggedCursor::new(regions)?,
 
             font_context,
@@ -48,10 +66,20 @@ impl<'a> TextLayoutIntepreter<'a> {
 
             font_alignment: parley::Alignment::Start,
             font_family: String::from("Arial"),
-            font_size: config.base_font_size(),
+            font_size: parent_font_size,
+            parent_font_size,
+            tab_width: 8,
+            white_space: StoredWhiteSpace::Preserve,
+            word_break: WordBreakStrength::Normal,
+            baseline_shift: taffy::LengthPercentage::length(0.0),
+            color_split: None,
+            inline_boxes: Vec::new(),
 
             tree,
             node,
+            show_if_i: 0,
+            entered: false,
+            child_scopes: Vec::new(),
         })
     }
 
@@ -76,12 +104,38 @@ impl<'a> Executor<VMState, RaggedCursor, StaticConfig> for TextLayoutIntepreter<
 }
 
 impl<'a> Intepreter for TextLayoutIntepreter<'a> {
+    fn handle_enter(&mut self) -> Result<()> {
+        // Only the first `Enter` is the node's own, the others are its children's.
+        if self.entered {
+            self.child_scopes.push(self.state.call_scopes());
+        }
+        self.entered = true;
+        Ok(())
+    }
+
     fn handle_text(
         &mut self,
         _x: taffy::LengthPercentage,
         _y: taffy::LengthPercentage,
         txt: &str,
     ) -> Result<()> {
+        // Sizes are like `BaselineShift`, percentages are of the font size.
+        let em = self.font_size * self.config.display_scale();
+        let mut inline_boxes = Vec::with_capacity(self.inline_boxes.len());
+        for (offset, width, height) in self.inline_boxes.iter() {
+            if !txt.is_char_boundary(*offset) {
+                return Err(anyhow!(
+                    "`InlineBox` offset {} is past the end of the text or inside a character",
+                    offset
+                ));
+            }
+            inline_boxes.push((
+                expanded_offset(txt, *offset, self.tab_width, self.white_space),
+                resolve_taffy_length(*width, em),
+                resolve_taffy_length(*height, em),
+            ));
+        }
+
         let layout = layout_text(
             &txt,
             self.tree.get_final_layout(self.node).size.width, /* TODO: why is this print tree */
@@ -90,22 +144,67 @@ impl<'a> Intepreter for TextLayoutIntepreter<'a> {
             self.layout_context,
             &self.font_family,
             self.font_size,
+            self.tab_width,
+            self.white_space,
+            self.word_break,
+            &inline_boxes,
             self.config.display_scale(),
         );
 
-        self.tree
+        // Percentages are of the font size. The box grows by the shift so raised or lowered
+        // glyphs still sit inside it. For a shift up the glyphs stay at the top and the
+        // baseline we report moves down, for a shift down the glyphs move down instead.
+        let shift = resolve_taffy_length(self.baseline_shift, em);
+
+        let ctx = self
+            .tree
             .get_node_context_mut(self.node)
-            .ok_or(anyhow!("All nodes must have context"))?
-            .maybe_font_layout = Some(layout.clone());
+            .ok_or(anyhow!("All nodes must have context"))?;
+        ctx.maybe_font_layout = Some(layout.clone());
+        ctx.text_shift = shift;
+        ctx.text_color_split = match self.color_split {
+            Some(offset) if !txt.is_char_boundary(offset) => {
+                return Err(anyhow!(
+                    "`TextColorRun` offset {} is past the end of the text or inside a character",
+                    offset
+                ));
+            }
+            Some(offset) => Some(expanded_offset(
+                txt,
+                offset,
+                self.tab_width,
+                self.white_space,
+            )),
+            None => None,
+        };
+        ctx.baseline = layout
+            .lines()
+            .next()
+            .map(|line| line.metrics().baseline + shift.max(0.0));
         let mut style = self.tree.style(self.node)?.clone();
         style.size = taffy::Size {
             width: taffy::prelude::length(layout.width()),
-            height: taffy::prelude::length(layout.height()),
+            height: taffy::prelude::length(layout.height() + shift.abs()),
         };
         self.tree.set_style(self.node, style)?;
         Ok(())
     }
 
+    fn handle_show_if(&mut self, rel_ptr: usize, _condition: TaggedWord) -> Result<()> {
+        let show = *self
+            .tree
+            .get_node_context(self.node)
+            .ok_or(anyhow!("All nodes must have context"))?
+            .show_if
+            .get(self.show_if_i)
+            .ok_or(anyhow!("`ShowIf` wasn't seen by the layout pass"))?;
+        self.show_if_i += 1;
+        if !show {
+            self.cursor.cursor = unsafe { self.cursor.cursor.add(rel_ptr) };
+        }
+        Ok(())
+    }
+
     fn handle_font_alignment(&mut self, alignment: StoredAlignment) -> Result<()> {
         self.font_alignment = match alignment {
             /* we need the separate stored alignment to make sure it is usize */
@@ -128,6 +227,50 @@ impl<'a> Intepreter for TextLayoutIntepreter<'a> {
         self.font_size = size;
         Ok(())
     }
+
+    fn handle_font_size_em(&mut self, factor: f32) -> Result<()> {
+        self.font_size = self.parent_font_size * factor;
+        Ok(())
+    }
+
+    fn handle_tab_width(&mut self, columns: usize) -> Result<()> {
+        self.tab_width = columns;
+        Ok(())
+    }
+
+    fn handle_white_space(&mut self, white_space: StoredWhiteSpace) -> Result<()> {
+        self.white_space = white_space;
+        Ok(())
+    }
+
+    fn handle_text_color_run(&mut self, offset: usize, _color: Color) -> Result<()> {
+        self.color_split = Some(offset);
+        Ok(())
+    }
+
+    fn handle_word_break(&mut self, word_break: StoredWordBreak) -> Result<()> {
+        self.word_break = match word_break {
+            StoredWordBreak::Normal => WordBreakStrength::Normal,
+            StoredWordBreak::BreakAll => WordBreakStrength::BreakAll,
+            StoredWordBreak::KeepAll => WordBreakStrength::KeepAll,
+        };
+        Ok(())
+    }
+
+    fn handle_inline_box(
+        &mut self,
+        offset: usize,
+        width: taffy::LengthPercentage,
+        height: taffy::LengthPercentage,
+    ) -> Result<()> {
+        self.inline_boxes.push((offset, width, height));
+        Ok(())
+    }
+
+    fn handle_baseline_shift(&mut self, shift: taffy::LengthPercentage) -> Result<()> {
+        self.baseline_shift = shift;
+        Ok(())
+    }
 }
 pub(super) fn text_pass(
     tree: &mut TaffyTree<LayoutContext>,
@@ -135,20 +278,174 @@ pub(super) fn text_pass(
     font_context: &mut FontContext,
     layout_context: &mut parley::LayoutContext<()>,
     config: StaticConfig,
+    parent_font_size: f32,
+    scopes: Option<Scopes>,
 ) -> Result<()> {
     let ctx = tree
         .get_node_context(node)
         .ok_or(anyhow!("Each node in the taffy tree must have a context"))?;
     let regions = ctx.ragged_members.clone();
-    let mut intepreter =
-        TextLayoutIntepreter::new(tree, node, regions, font_context, layout_context, config)?;
+    let mut intepreter = TextLayoutIntepreter::new(
+        tree,
+        node,
+        regions,
+        font_context,
+        layout_context,
+        config,
+        parent_font_size,
+        scopes,
+    )?;
 
     let mut trace = Vec::new();
-    while let Some(_) = intepreter.advance(&mut trace)? {}
+    while let Some(_) = intepreter
+        .advance(&mut trace)
+        .with_context(|| PassTrace::new("Text Pass", &trace, 10))?
+    {}
+    let font_size = intepreter.font_size;
+    let mut child_scopes = std::mem::take(&mut intepreter.child_scopes).into_iter();
 
+    // The node's regions run before its children, so a library body's `LibraryReturn` has
+    // already popped its registers. Children entered inside the body get them back.
     let children: Vec<_> = tree.child_ids(node).collect();
     for child in children {
-        text_pass(tree, child, font_context, layout_context, config)?;
+        let scopes = child_scopes.next().flatten();
+        text_pass(
+            tree,
+            child,
+            font_context,
+            layout_context,
+            config,
+            font_size,
+            scopes,
+        )?;
     }
     Ok(())
 }
+
+// ::: ---- Between text and drawing, align flex rows on their text baselines ----:::
+// Taffy's leaves report no baselines of their own, so it lays out `AlignItems::Baseline` as if
+// every child's baseline were its bottom edge. Now that the text is laid out we know better and
+// shift each child down until the first baselines in the row line up.
+
+/// The baseline of the first text in `node` or its descendants, relative to the top edge of `node`.
+fn text_baseline(tree: &TaffyTree<LayoutContext>, node: NodeId) -> Result<Option<f32>> {
+    let ctx = tree
+        .get_node_context(node)
+        .ok_or(anyhow!("Each node in the taffy tree must have a context"))?;
+    if ctx.baseline.is_some() {
+        return Ok(ctx.baseline);
+    }
+    for child in tree.child_ids(node) {
+        if let Some(baseline) = text_baseline(tree, child)? {
+            let shift = tree
+                .get_node_context(child)
+                .ok_or(anyhow!("Each node in the taffy tree must have a context"))?
+                .baseline_shift;
+            return Ok(Some(
+                tree.get_final_layout(child).location.y + shift + baseline,
+            ));
+        }
+    }
+    Ok(None)
+}
+
+/// Like `text_baseline` but nodes without any text fall back to their bottom edge, as in CSS.
+fn first_baseline(tree: &TaffyTree<LayoutContext>, node: NodeId) -> Result<f32> {
+    Ok(text_baseline(tree, node)?.unwrap_or(tree.get_final_layout(node).size.height))
+}
+
+pub(super) fn align_baselines(tree: &mut TaffyTree<LayoutContext>, node: NodeId) -> Result<()> {
+    let children: Vec<_> = tree.child_ids(node).collect();
+    for child in children.iter() {
+        align_baselines(tree, *child)?;
+    }
+
+    let style = tree.style(node)?;
+    let is_baseline_row = style.display == taffy::Display::Flex
+        && style.flex_direction == taffy::FlexDirection::Row
+        && style.align_items == Some(taffy::AlignItems::Baseline);
+    if !is_baseline_row || children.is_empty() {
+        return Ok(());
+    }
+
+    let mut baselines = Vec::with_capacity(children.len());
+    for child in children.iter() {
+        baselines.push(tree.get_final_layout(*child).location.y + first_baseline(tree, *child)?);
+    }
+    let target = baselines.iter().cloned().fold(f32::MIN, f32::max);
+    for (child, baseline) in children.iter().zip(baselines) {
+        tree.get_node_context_mut(*child)
+            .ok_or(anyhow!("Each node in the taffy tree must have a context"))?
+            .baseline_shift += target - baseline;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::draw::{PassTrace, StoredWordBreak, Tag};
+    use crate::ui::testing::{Headless, Tree, px, rgb};
+
+    #[test]
+    fn tab_width_is_bounded() {
+        let tree = |columns: usize| {
+            let mut tree = Tree::new();
+            tree.enter()
+                .push((Tag::TabWidth, columns))
+                .text(px(0.0), px(0.0), "a\tb")
+                .leave();
+            tree
+        };
+        let mut headless = Headless::new(200, 100);
+
+        assert!(headless.frame(&tree(0)).is_err());
+        assert!(headless.frame(&tree(64)).is_ok());
+        let err = headless.frame(&tree(65)).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("between 1 and 64"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn text_pass_errors_say_where_they_happened() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .push((Tag::TextColorRun, 3)) /* past the end of the text */
+            .push(rgb(255, 0, 0))
+            .text(px(0.0), px(0.0), "ab")
+            .leave();
+        let mut headless = Headless::new(200, 100);
+
+        let err = headless.frame(&tree).unwrap_err();
+        let trace = err.downcast_ref::<PassTrace>().unwrap();
+        assert_eq!(trace.pass, "Text Pass");
+        assert_eq!(trace.words.last().map(|w| w.0), Some(Tag::Text));
+    }
+
+    #[test]
+    fn word_break_decides_if_a_long_token_wraps() {
+        let tree = |word_break: StoredWordBreak| {
+            let mut tree = Tree::new();
+            tree.enter()
+                .enter()
+                .op(Tag::Width, &[px(60.0)])
+                .push((Tag::WordBreak, word_break as usize))
+                .text(px(0.0), px(0.0), &"x".repeat(50))
+                .leave()
+                .leave();
+            tree
+        };
+        let mut headless = Headless::new(200, 400);
+        let mut height = |word_break: StoredWordBreak| {
+            let frame = headless.frame(&tree(word_break)).unwrap();
+            frame.drawn.boxes[1].bounds.height()
+        };
+
+        // A single line that runs out of the box, unless breaking inside words is allowed.
+        let normal = height(StoredWordBreak::Normal);
+        assert_eq!(height(StoredWordBreak::KeepAll), normal);
+        assert!(height(StoredWordBreak::BreakAll) > 2.0 * normal);
+    }
+}
@@ -5,14 +5,30 @@ use skia_safe::Color;
 use winit::window::CursorIcon;
 
 use super::utils::read_str_from_array_tagged_word;
-use super::{DisplayOption, StoredAlignment, Tag, TaggedWord};
+use super::{
+    ColorSpaceOption, DisplayOption, LengthUnits, ParamUnion, Side, StoredAlignContent,
+    StoredAlignItems, StoredAlignment, StoredWhiteSpace, StoredWordBreak, Tag, TaggedWord,
+    VmLimits,
+};
 
 pub(super) trait HasStaticConfig {
     fn file_start(&self) -> *const u8;
+    fn file_end(&self) -> *const u8;
     fn base_font_size(&self) -> f32;
     fn display_scale(&self) -> f32;
-    #[allow(dead_code)]
     fn get_dt(&self) -> Duration;
+    fn vm_limits(&self) -> VmLimits;
+    fn color_space(&self) -> ColorSpaceOption;
+    fn reduce_motion(&self) -> bool;
+    fn viewport(&self) -> (f32, f32);
+    /// What lengths in the layout are resolved with.
+    fn units(&self) -> LengthUnits {
+        LengthUnits {
+            base_font_size: self.base_font_size(),
+            display_scale: self.display_scale(),
+            viewport: self.viewport(),
+        }
+    }
 }
 
 /* :::::---- Defines the structure of multi tagged word sequences ie how an instruction demands parameters ----::::: */
@@ -29,11 +45,19 @@ impl ReadIn for TaggedWord {}
 
 pub(super) trait HasStack {
     fn stack_pop(&mut self) -> Option<TaggedWord>;
-    fn stack_push(&mut self, v: TaggedWord) -> ();
+    fn stack_push(&mut self, v: TaggedWord) -> Result<()>;
 }
 pub(super) trait HasRegister {
     fn regs_get(&mut self, k: usize) -> Option<TaggedWord>;
-    fn regs_set(&mut self, k: usize, v: TaggedWord) -> ();
+    /// Reads from the scope of the caller, ie. one library call further out.
+    fn regs_get_outer(&mut self, k: usize) -> Option<TaggedWord>;
+    fn regs_set(&mut self, k: usize, v: TaggedWord) -> Result<()>;
+    fn regs_push_scope(&mut self) -> Result<()>;
+    fn regs_pop_scope(&mut self) -> Result<()>;
+}
+pub(super) trait HasScratch {
+    fn scratch_push(&mut self, v: String) -> usize;
+    fn scratch_get(&self, i: usize) -> Option<&str>;
 }
 pub(super) trait HasCursor {
     unsafe fn read_from_cursor(&mut self) -> Option<TaggedWord>;
@@ -43,7 +67,7 @@ pub(super) trait HasCursor {
 pub(super) trait Executor<S, C, G>
 where
     Self: Intepreter,
-    S: HasRegister + HasStack,
+    S: HasRegister + HasStack + HasScratch,
     C: HasCursor,
     G: HasStaticConfig,
 {
@@ -62,12 +86,12 @@ where
             }
             Tag::PullArgOr => {
                 /* read the next word, and provide it as the default if nothing is on the stack */
-                let default = unsafe { self.get_cursor().read_from_cursor() }
-                    .ok_or(anyhow!("Unexpected EoF"))?;
+                let default = self.read_default(tagged_word.tag)?;
 
                 if let Some(pulled) = &self.get_vm_state().stack_pop() {
                     (pulled.tag, pulled.word)
                 } else {
+                    let default = self.maybe_dereference_from_vm_state(default)?;
                     (default.tag, default.word)
                 }
             }
@@ -82,16 +106,27 @@ where
 
                 (pulled.tag, pulled.word)
             }
+            Tag::FromOuterReg => {
+                let pulled = self
+                    .get_vm_state()
+                    .regs_get_outer(unsafe { tagged_word.word.word })
+                    .ok_or(anyhow!(
+                        "FromOuterReg called for register id {}, but it is empty or there is no outer scope",
+                        &unsafe { tagged_word.word.word }
+                    ))?;
+
+                (pulled.tag, pulled.word)
+            }
             Tag::FromRegOr => {
                 /* read the next word, and provide it as the default or pull if reg empty*/
-                let default = unsafe { self.get_cursor().read_from_cursor() }
-                    .ok_or(anyhow!("Unexpected EoF"))?;
+                let default = self.read_default(tagged_word.tag)?;
                 if let Some(pulled) = self
                     .get_vm_state()
                     .regs_get(unsafe { tagged_word.word.word })
                 {
                     (pulled.tag, pulled.word)
                 } else {
+                    let default = self.maybe_dereference_from_vm_state(default)?;
                     (default.tag, default.word)
                 }
             }
@@ -100,6 +135,23 @@ where
         Ok(TaggedWord { tag, word })
     }
 
+    /// The default after `PullArgOr` or `FromRegOr`. It may come from the stack or a register
+    /// itself, which is only looked up if the default is used.
+    fn read_default(&mut self, tag: Tag) -> Result<TaggedWord> {
+        let default =
+            unsafe { self.get_cursor().read_from_cursor() }.ok_or(anyhow!("Unexpected EoF"))?;
+        // Its own default would only be read when it is used, so the words after it would be
+        // misread whenever it isn't.
+        if matches!(default.tag, Tag::PullArgOr | Tag::FromRegOr) {
+            return Err(anyhow!(
+                "The default of `{}` can't be a `{}`, it has no default of its own",
+                tag,
+                default.tag
+            ));
+        }
+        Ok(default)
+    }
+
     unsafe fn read_from_cursor_with_arg(&mut self) -> Result<Option<TaggedWord>> {
         if let Some(tagged_word) = unsafe { self.get_cursor().read_from_cursor() } {
             return Ok(Some(self.maybe_dereference_from_vm_state(tagged_word)?));
@@ -117,18 +169,39 @@ where
                 Tag::Rect => self.read_as_rect()?,
                 Tag::BeginPath => self.read_as_begin_path()?,
                 Tag::Color => self.read_as_pencil_color()?,
+                Tag::StrokeWidth => self.read_as_stroke_width()?,
+                Tag::ClipRoundedRect => self.read_as_clip_rounded_rect()?,
+                Tag::BorderRadius => self.read_as_border_radius()?,
+                Tag::LinearGradient => self.read_as_linear_gradient()?,
+                Tag::ColorTransition => {
+                    self.read_as_color_transition(tagged_word.read_as_color_transition()?)?
+                }
+                Tag::ClearColor => self.read_as_clear_color()?,
                 Tag::Width => self.read_as_width()?,
                 Tag::Height => self.read_as_height()?,
                 Tag::Padding => self.read_as_padding()?,
                 Tag::Margin => self.read_as_margin()?,
+                Tag::PaddingLeft => self.read_as_padding_side(Side::Left)?,
+                Tag::PaddingTop => self.read_as_padding_side(Side::Top)?,
+                Tag::PaddingRight => self.read_as_padding_side(Side::Right)?,
+                Tag::PaddingBottom => self.read_as_padding_side(Side::Bottom)?,
+                Tag::MarginLeft => self.read_as_margin_side(Side::Left)?,
+                Tag::MarginTop => self.read_as_margin_side(Side::Top)?,
+                Tag::MarginRight => self.read_as_margin_side(Side::Right)?,
+                Tag::MarginBottom => self.read_as_margin_side(Side::Bottom)?,
                 Tag::Display => self.handle_display(tagged_word.read_as_display()?)?,
+                Tag::Key => self.handle_key(tagged_word.read_as_key()?)?,
                 Tag::RoundedRect => self.read_as_rounded_rect()?,
+                Tag::Line => self.read_as_line()?,
                 Tag::Gap => self.read_as_gap()?,
                 Tag::Hover => self.handle_hover(tagged_word.read_as_hover()?)?,
                 Tag::MousePressed => {
                     self.handle_mouse_pressed(tagged_word.read_as_mouse_pressed()?)?
                 }
                 Tag::Clicked => self.handle_clicked(tagged_word.read_as_clicked()?)?,
+                Tag::FocusVisible => {
+                    self.handle_focus_visible(tagged_word.read_as_focus_visible()?)?
+                }
                 Tag::NoJmp => self.handle_no_jmp(tagged_word.read_as_no_jmp()?)?,
                 Tag::Jmp => self.handle_jmp(tagged_word.read_as_jmp()?)?,
 
@@ -137,14 +210,65 @@ where
                     self.blanket_handle_set_reg(tagged_word.read_as_load_register()?)?
                 }
                 Tag::Event => self.handle_event(tagged_word.read_as_event()?)?,
+                Tag::OnScrollReport => {
+                    self.handle_on_scroll_report(tagged_word.read_as_on_scroll_report()?)?
+                }
+                Tag::NamedEvent => self.read_as_named_event()?,
+                Tag::EventArg => self.read_as_event_arg()?,
                 Tag::Text => self.read_as_text()?,
+                Tag::TextAt => self.read_as_text_at()?,
                 Tag::FontSize => self.handle_font_size(tagged_word.read_as_font_size()?)?,
+                Tag::FontSizeEm => self.handle_font_size_em(tagged_word.read_as_font_size_em()?)?,
+                Tag::FontSizeArg => self.read_as_font_size_arg()?,
                 Tag::FontAlignment => {
                     self.handle_font_alignment(tagged_word.read_as_font_alignment()?)?
                 }
                 Tag::FontFamily => self.read_as_font_family()?,
                 Tag::CursorDefault => self.handle_cursor(tagged_word.read_as_any_cursor()?)?,
                 Tag::CursorPointer => self.handle_cursor(tagged_word.read_as_any_cursor()?)?,
+                Tag::HoverCursor => self.handle_cursor(tagged_word.read_as_any_cursor()?)?,
+                Tag::LibraryCall => {
+                    self.blanket_handle_library_call(tagged_word.read_as_library_call()?)?
+                }
+                Tag::LibraryReturn => self.blanket_handle_library_return()?,
+                Tag::ShowIf => self.read_as_show_if(tagged_word.read_as_show_if()?)?,
+                Tag::ButtonPressed => {
+                    self.read_as_button_pressed(tagged_word.read_as_button_pressed()?)?
+                }
+                Tag::KeyDown => self.read_as_key_down(tagged_word.read_as_key_down()?)?,
+                Tag::KeyUp => self.read_as_key_up(tagged_word.read_as_key_up()?)?,
+                Tag::KeyPress => self.read_as_key_press(tagged_word.read_as_key_press()?)?,
+                Tag::Outline => self.read_as_outline()?,
+                Tag::OutlineDash => self.read_as_outline_dash()?,
+                Tag::AlignItems => self.handle_align_items(tagged_word.read_as_align_items()?)?,
+                Tag::JustifyItems => {
+                    self.handle_justify_items(tagged_word.read_as_justify_items()?)?
+                }
+                Tag::JustifyContent => {
+                    self.handle_justify_content(tagged_word.read_as_justify_content()?)?
+                }
+                Tag::AlignContent => {
+                    self.handle_align_content(tagged_word.read_as_align_content()?)?
+                }
+                Tag::GridColumns => {
+                    self.handle_grid_columns(tagged_word.read_as_grid_columns()?)?
+                }
+                Tag::GridRows => self.handle_grid_rows(tagged_word.read_as_grid_rows()?)?,
+                Tag::TabWidth => self.handle_tab_width(tagged_word.read_as_tab_width()?)?,
+                Tag::BaselineShift => self.read_as_baseline_shift()?,
+                Tag::WordBreak => self.handle_word_break(tagged_word.read_as_word_break()?)?,
+                Tag::WhiteSpace => self.handle_white_space(tagged_word.read_as_white_space()?)?,
+                Tag::Opacity => self.handle_opacity(tagged_word.read_as_opacity()?)?,
+                Tag::TextColorRun => {
+                    self.read_as_text_color_run(tagged_word.read_as_text_color_run()?)?
+                }
+                Tag::InlineBox => self.read_as_inline_box(tagged_word.read_as_inline_box()?)?,
+                Tag::PixelSnapText => {
+                    self.handle_pixel_snap_text(tagged_word.read_as_pixel_snap_text()? != 0)?
+                }
+                Tag::FormatNumber => {
+                    self.blanket_handle_format_number(tagged_word.read_as_format_number()?)?
+                }
                 _ => {
                     return Err(anyhow!(
                         "Found Tag `{:?}` in illegal position",
@@ -158,13 +282,41 @@ where
         Ok(Some(()))
     }
 
+    /// Calls `f` with a string either from the shared memory (`TextPtr`) or one the VM made this frame (`ScratchStr`).
+    // Strings in the file are borrowed rather than copied every frame. The borrow ends with `f`,
+    // while the pass still holds the file (see `read_str_from_array_tagged_word`).
+    fn with_text<R>(
+        &mut self,
+        tagged_word: TaggedWord,
+        f: impl FnOnce(&mut Self, &str) -> Result<R>,
+    ) -> Result<R> {
+        match tagged_word.tag {
+            Tag::ScratchStr => {
+                let i = unsafe { tagged_word.word.word };
+                let txt = self
+                    .get_vm_state()
+                    .scratch_get(i)
+                    .map(|x| x.to_owned())
+                    .ok_or(anyhow!("No VM made string with index {}", i))?;
+                f(self, &txt)
+            }
+            _ => {
+                let txt = unsafe {
+                    read_str_from_array_tagged_word(
+                        tagged_word.read_as_text_ptr()?,
+                        self.get_config().file_start(),
+                        self.get_config().file_end(),
+                    )
+                }?;
+                f(self, txt)
+            }
+        }
+    }
+
     fn read_as_width(&mut self) -> Result<()> {
         let width = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         self.handle_width(width)?;
         Ok(())
     }
@@ -172,10 +324,7 @@ where
     fn read_as_height(&mut self) -> Result<()> {
         let height = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         self.handle_height(height)?;
         Ok(())
     }
@@ -183,28 +332,16 @@ where
     fn read_as_margin(&mut self) -> Result<()> {
         let left = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         let top = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         let right = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         let bottom = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         self.handle_margin(left, top, right, bottom)?;
         Ok(())
     }
@@ -212,45 +349,43 @@ where
     fn read_as_padding(&mut self) -> Result<()> {
         let left = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
         let top = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
         let right = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
         let bottom = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
         self.handle_padding(left, top, right, bottom)?;
         Ok(())
     }
 
+    fn read_as_padding_side(&mut self, side: Side) -> Result<()> {
+        let length = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_size_pct(self.get_config().units())?;
+        self.handle_padding_side(side, length)?;
+        Ok(())
+    }
+
+    fn read_as_margin_side(&mut self, side: Side) -> Result<()> {
+        let length = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
+        self.handle_margin_side(side, length)?;
+        Ok(())
+    }
+
     fn read_as_gap(&mut self) -> Result<()> {
         let width = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
         let height = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
         self.handle_gap(width, height)?;
         Ok(())
     }
@@ -258,60 +393,70 @@ where
     fn read_as_text(&mut self) -> Result<()> {
         let x = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pct(self.get_config().units())?;
         let y = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pct(self.get_config().units())?;
 
-        let ptr = unsafe { self.read_from_cursor_with_arg() }?
+        let text = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.with_text(text, |this, txt| this.handle_text(x, y, txt))?;
+        Ok(())
+    }
+
+    fn read_as_text_at(&mut self) -> Result<()> {
+        let x = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        let y = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        let max_width = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_text_ptr()?;
-        let txt = read_str_from_array_tagged_word(ptr, self.get_config().file_start())?;
-        self.handle_text(x, y, &txt)?;
+            .read_as_taffy_size_pct(self.get_config().units())?;
+
+        let text = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.with_text(text, |this, txt| this.handle_text_at(x, y, max_width, txt))?;
         Ok(())
     }
 
     fn read_as_font_family(&mut self) -> Result<()> {
-        let ptr = unsafe { self.read_from_cursor_with_arg() }?
-            .ok_or(anyhow!("Early EOF"))?
-            .read_as_text_ptr()?;
-        let txt = read_str_from_array_tagged_word(ptr, self.get_config().file_start())?;
-        self.handle_font_family(&txt)?;
+        let text = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.with_text(text, |this, txt| this.handle_font_family(txt))?;
+        Ok(())
+    }
+
+    fn read_as_named_event(&mut self) -> Result<()> {
+        let name = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.with_text(name, |this, name| this.handle_named_event(name))?;
+        Ok(())
+    }
+
+    fn read_as_event_arg(&mut self) -> Result<()> {
+        let id = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.handle_event(unsafe { id.word.word })?;
+        Ok(())
+    }
+
+    fn read_as_font_size_arg(&mut self) -> Result<()> {
+        let size = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.handle_font_size(size.read_as_any_font_size()?)?;
         Ok(())
     }
 
     fn read_as_rect(&mut self) -> Result<()> {
         let x = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pct(self.get_config().units())?;
         let y = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pct(self.get_config().units())?;
 
         let w = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         let h = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         self.handle_rect(x, y, w, h)?;
         Ok(())
     }
@@ -319,39 +464,142 @@ where
     fn read_as_rounded_rect(&mut self) -> Result<()> {
         let x = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pct(self.get_config().units())?;
         let y = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pct(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pct(self.get_config().units())?;
         let w = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         let h = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
         let r = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
-            .read_as_taffy_length_pctauto(
-                self.get_config().base_font_size(),
-                self.get_config().display_scale(),
-            )?;
+            .read_as_taffy_length_pctauto(self.get_config().units())?;
 
         self.handle_rounded_rect(x, y, w, h, r)?;
         Ok(())
     }
 
+    fn read_as_border_radius(&mut self) -> Result<()> {
+        let mut corners = [taffy::LengthPercentageAuto::auto(); 4];
+        for corner in corners.iter_mut() {
+            *corner = unsafe { self.read_from_cursor_with_arg() }?
+                .ok_or(anyhow!("Early EOF"))?
+                .read_as_taffy_length_pctauto(self.get_config().units())?;
+        }
+        self.handle_border_radius(corners)?;
+        Ok(())
+    }
+
+    fn read_as_line(&mut self) -> Result<()> {
+        let x0 = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        let y0 = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        let x1 = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        let y1 = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        self.handle_line(x0, y0, x1, y1)?;
+        Ok(())
+    }
+
+    fn read_as_show_if(&mut self, rel_ptr: usize) -> Result<()> {
+        let condition = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.handle_show_if(rel_ptr, condition)?;
+        Ok(())
+    }
+
+    fn read_as_button_pressed(&mut self, rel_ptr: usize) -> Result<()> {
+        let button = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.handle_button_pressed(rel_ptr, unsafe { button.word.word })?;
+        Ok(())
+    }
+
+    fn read_as_key_down(&mut self, rel_ptr: usize) -> Result<()> {
+        let key = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.handle_key_down(rel_ptr, unsafe { key.word.word })?;
+        Ok(())
+    }
+
+    fn read_as_key_up(&mut self, rel_ptr: usize) -> Result<()> {
+        let key = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        self.handle_key_up(rel_ptr, unsafe { key.word.word })?;
+        Ok(())
+    }
+
+    fn read_as_key_press(&mut self, rel_ptr: usize) -> Result<()> {
+        let ch = unsafe { self.read_from_cursor_with_arg() }?.ok_or(anyhow!("Early EOF"))?;
+        let ch = unsafe { ch.word.word };
+        let ch = u32::try_from(ch)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(anyhow!(
+                "`KeyPress` expects a unicode scalar value, got 0x{:x}",
+                ch
+            ))?;
+        self.handle_key_press(rel_ptr, ch)?;
+        Ok(())
+    }
+
+    fn read_as_outline(&mut self) -> Result<()> {
+        let width = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_size_pct(self.get_config().units())?;
+        let color = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_any_color()?;
+        let offset = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        self.handle_outline(width, color, offset)?;
+        Ok(())
+    }
+
+    fn read_as_outline_dash(&mut self) -> Result<()> {
+        let mut lengths = [taffy::LengthPercentage::length(0.0); 2];
+        for length in lengths.iter_mut() {
+            *length = unsafe { self.read_from_cursor_with_arg() }?
+                .ok_or(anyhow!("Early EOF"))?
+                .read_as_taffy_size_pct(self.get_config().units())?;
+        }
+        self.handle_outline_dash(lengths[0], lengths[1])?;
+        Ok(())
+    }
+
+    fn read_as_baseline_shift(&mut self) -> Result<()> {
+        let shift = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_length_pct(self.get_config().units())?;
+        self.handle_baseline_shift(shift)?;
+        Ok(())
+    }
+
+    fn read_as_text_color_run(&mut self, offset: usize) -> Result<()> {
+        let color = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_any_color()?;
+        self.handle_text_color_run(offset, color)?;
+        Ok(())
+    }
+
+    fn read_as_inline_box(&mut self, offset: usize) -> Result<()> {
+        let mut sizes = [taffy::LengthPercentage::length(0.0); 2];
+        for size in sizes.iter_mut() {
+            *size = unsafe { self.read_from_cursor_with_arg() }?
+                .ok_or(anyhow!("Early EOF"))?
+                .read_as_taffy_size_pct(self.get_config().units())?;
+        }
+        self.handle_inline_box(offset, sizes[0], sizes[1])?;
+        Ok(())
+    }
+
     fn read_as_pencil_color(&mut self) -> Result<()> {
         let color = unsafe { self.read_from_cursor_with_arg() }?
             .ok_or(anyhow!("Early EOF"))?
@@ -360,6 +608,56 @@ where
         Ok(())
     }
 
+    fn read_as_color_transition(&mut self, ms: usize) -> Result<()> {
+        let color = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_any_color()?;
+        self.handle_color_transition(Duration::from_millis(ms as u64), color)?;
+        Ok(())
+    }
+
+    fn read_as_stroke_width(&mut self) -> Result<()> {
+        let width = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_size_pct(self.get_config().units())?;
+        self.handle_stroke_width(width)?;
+        Ok(())
+    }
+
+    fn read_as_clip_rounded_rect(&mut self) -> Result<()> {
+        let radius = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_taffy_size_pct(self.get_config().units())?;
+        self.handle_clip_rounded_rect(radius)?;
+        Ok(())
+    }
+
+    fn read_as_linear_gradient(&mut self) -> Result<()> {
+        let mut points = [taffy::LengthPercentage::length(0.0); 4];
+        for point in points.iter_mut() {
+            *point = unsafe { self.read_from_cursor_with_arg() }?
+                .ok_or(anyhow!("Early EOF"))?
+                .read_as_taffy_length_pct(self.get_config().units())?;
+        }
+        let from = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_any_color()?;
+        let to = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_any_color()?;
+        let [x0, y0, x1, y1] = points;
+        self.handle_linear_gradient(x0, y0, x1, y1, from, to)?;
+        Ok(())
+    }
+
+    fn read_as_clear_color(&mut self) -> Result<()> {
+        let color = unsafe { self.read_from_cursor_with_arg() }?
+            .ok_or(anyhow!("Early EOF"))?
+            .read_as_any_color()?;
+        self.handle_clear_color(color)?;
+        Ok(())
+    }
+
     fn read_as_begin_path(&mut self) -> Result<()> {
         self.handle_begin_path()?;
         while let Some(tagged_word) = unsafe { self.get_cursor().read_from_cursor() } {
@@ -369,130 +667,73 @@ where
                 Tag::MoveTo => {
                     let x = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let y = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     self.handle_move_to(x, y)?;
                 }
                 Tag::LineTo => {
                     let x = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let y = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     self.handle_line_to(x, y)?;
                 }
                 Tag::QuadTo => {
                     let cx = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let cy = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let x = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let y = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     self.handle_quad_to(cx, cy, x, y)?;
                 }
                 Tag::CubicTo => {
                     let cx1 = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let cy1 = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let cx2 = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let cy2 = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let x = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let y = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     self.handle_cubic_to(cx1, cy1, cx2, cy2, x, y)?;
                 }
                 Tag::ArcTo => {
                     let tx = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let ty = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let x = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let y = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     let r = unsafe { self.read_from_cursor_with_arg() }?
                         .ok_or(anyhow!("Early EOF"))?
-                        .read_as_taffy_length_pct(
-                            self.get_config().base_font_size(),
-                            self.get_config().display_scale(),
-                        )?;
+                        .read_as_taffy_length_pct(self.get_config().units())?;
                     self.handle_arc_to(tx, ty, x, y, r)?;
                 }
                 Tag::ClosePath => self.handle_close_path()?,
@@ -517,7 +758,40 @@ where
         let tagged_word =
             unsafe { self.get_cursor().read_from_cursor() }.ok_or(anyhow!("Unexpected EOF"))?;
         let tagged_word = self.maybe_dereference_from_vm_state(tagged_word)?;
-        self.get_vm_state().stack_push(tagged_word);
+        self.get_vm_state().stack_push(tagged_word)?;
+        Ok(())
+    }
+
+    fn blanket_handle_library_call(&mut self, id: usize) -> Result<()> {
+        // Registers are scoped to the call, so a library can't clobber the caller's.
+        self.get_vm_state().regs_push_scope()?;
+        self.handle_library_call(id)
+    }
+
+    fn blanket_handle_library_return(&mut self) -> Result<()> {
+        self.get_vm_state().regs_pop_scope()?;
+        self.handle_library_return()
+    }
+
+    fn blanket_handle_format_number(&mut self, precision: usize) -> Result<()> {
+        if precision > 16 {
+            return Err(anyhow!(
+                "FormatNumber precision must be at most 16, got {}",
+                precision
+            ));
+        }
+        let number = self
+            .get_vm_state()
+            .stack_pop()
+            .ok_or(anyhow!("FormatNumber called with empty stack."))?
+            .read_as_number()?;
+        let i = self
+            .get_vm_state()
+            .scratch_push(format!("{:.*}", precision, number));
+        self.get_vm_state().stack_push(TaggedWord {
+            tag: Tag::ScratchStr,
+            word: ParamUnion { word: i },
+        })?;
         Ok(())
     }
 
@@ -525,7 +799,7 @@ where
         let tagged_word =
             unsafe { self.get_cursor().read_from_cursor() }.ok_or(anyhow!("Unexpected EOF"))?;
         let tagged_word = self.maybe_dereference_from_vm_state(tagged_word)?;
-        self.get_vm_state().regs_set(id, tagged_word);
+        self.get_vm_state().regs_set(id, tagged_word)?;
         Ok(())
     }
 }
@@ -561,13 +835,27 @@ pub(super) trait Intepreter {
     ) -> Result<()> {
         Ok(())
     }
+    fn handle_padding_side(&mut self, _side: Side, _length: taffy::LengthPercentage) -> Result<()> {
+        Ok(())
+    }
+    fn handle_margin_side(
+        &mut self,
+        _side: Side,
+        _length: taffy::LengthPercentageAuto,
+    ) -> Result<()> {
+        Ok(())
+    }
+    // Only the layout pass reads it, the later passes find it in `LayoutContext::state_key`.
+    fn handle_key(&mut self, _key: usize) -> Result<()> {
+        Ok(())
+    }
     fn handle_display(&mut self, _display: DisplayOption) -> Result<()> {
         Ok(())
     }
     fn handle_gap(
         &mut self,
-        _width: taffy::LengthPercentage,
-        _height: taffy::LengthPercentage,
+        _main: taffy::LengthPercentage,
+        _cross: taffy::LengthPercentage,
     ) -> Result<()> {
         Ok(())
     }
@@ -580,12 +868,32 @@ pub(super) trait Intepreter {
     fn handle_clicked(&mut self, _rel_ptr: usize) -> Result<()> {
         Ok(())
     }
+    fn handle_focus_visible(&mut self, _rel_ptr: usize) -> Result<()> {
+        Ok(())
+    }
+    fn handle_button_pressed(&mut self, _rel_ptr: usize, _button: usize) -> Result<()> {
+        Ok(())
+    }
+    /// `key` is numbered like in `ui::keys`.
+    fn handle_key_down(&mut self, _rel_ptr: usize, _key: usize) -> Result<()> {
+        Ok(())
+    }
+    fn handle_key_up(&mut self, _rel_ptr: usize, _key: usize) -> Result<()> {
+        Ok(())
+    }
+    fn handle_key_press(&mut self, _rel_ptr: usize, _ch: char) -> Result<()> {
+        Ok(())
+    }
     fn handle_no_jmp(&mut self, _rel_ptr: usize) -> Result<()> {
         Ok(())
     }
     fn handle_jmp(&mut self, _rel_ptr: usize) -> Result<()> {
         Ok(())
     }
+    // The layout pass decides, the later passes replay its decision (see `LayoutContext::show_if`).
+    fn handle_show_if(&mut self, _rel_ptr: usize, _condition: TaggedWord) -> Result<()> {
+        Ok(())
+    }
     fn handle_text(
         &mut self,
         _x: taffy::LengthPercentage,
@@ -594,6 +902,15 @@ pub(super) trait Intepreter {
     ) -> Result<()> {
         Ok(())
     }
+    fn handle_text_at(
+        &mut self,
+        _x: taffy::LengthPercentage,
+        _y: taffy::LengthPercentage,
+        _max_width: taffy::LengthPercentage,
+        _txt: &str,
+    ) -> Result<()> {
+        Ok(())
+    }
     fn handle_font_alignment(&mut self, _alignment: StoredAlignment) -> Result<()> {
         Ok(())
     }
@@ -604,6 +921,10 @@ pub(super) trait Intepreter {
         Ok(())
     }
 
+    fn handle_font_size_em(&mut self, _factor: f32) -> Result<()> {
+        Ok(())
+    }
+
     fn handle_rect(
         &mut self,
         _x: taffy::LengthPercentage,
@@ -614,9 +935,119 @@ pub(super) trait Intepreter {
         Ok(())
     }
 
+    fn handle_align_items(&mut self, _align_items: StoredAlignItems) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_justify_items(&mut self, _justify_items: StoredAlignItems) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_justify_content(&mut self, _justify_content: StoredAlignContent) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_align_content(&mut self, _align_content: StoredAlignContent) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_grid_columns(&mut self, _count: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_grid_rows(&mut self, _count: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_tab_width(&mut self, _columns: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_baseline_shift(&mut self, _shift: taffy::LengthPercentage) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_word_break(&mut self, _word_break: StoredWordBreak) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_white_space(&mut self, _white_space: StoredWhiteSpace) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_pixel_snap_text(&mut self, _snap: bool) -> Result<()> {
+        Ok(())
+    }
+
+    // The text pass finds where in the laid out text the colour changes, the draw pass paints it.
+    fn handle_text_color_run(&mut self, _offset: usize, _color: Color) -> Result<()> {
+        Ok(())
+    }
+
+    // Only the text pass cares, the box is drawn along with the text.
+    fn handle_inline_box(
+        &mut self,
+        _offset: usize,
+        _width: taffy::LengthPercentage,
+        _height: taffy::LengthPercentage,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_outline(
+        &mut self,
+        _width: taffy::LengthPercentage,
+        _color: Color,
+        _offset: taffy::LengthPercentage,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_outline_dash(
+        &mut self,
+        _dash: taffy::LengthPercentage,
+        _gap: taffy::LengthPercentage,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     fn handle_pencil_color(&mut self, _color: Color) -> Result<()> {
         Ok(())
     }
+    fn handle_color_transition(&mut self, _duration: Duration, _color: Color) -> Result<()> {
+        Ok(())
+    }
+    fn handle_stroke_width(&mut self, _width: taffy::LengthPercentage) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_clip_rounded_rect(&mut self, _radius: taffy::LengthPercentage) -> Result<()> {
+        Ok(())
+    }
+
+    /// Top left, top right, bottom right, bottom left.
+    fn handle_border_radius(&mut self, _corners: [taffy::LengthPercentageAuto; 4]) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_opacity(&mut self, _opacity: f32) -> Result<()> {
+        Ok(())
+    }
+    fn handle_linear_gradient(
+        &mut self,
+        _x0: taffy::LengthPercentage,
+        _y0: taffy::LengthPercentage,
+        _x1: taffy::LengthPercentage,
+        _y1: taffy::LengthPercentage,
+        _from: Color,
+        _to: Color,
+    ) -> Result<()> {
+        Ok(())
+    }
+    // Handed back from the layout pass to whoever clears the window.
+    fn handle_clear_color(&mut self, _color: Color) -> Result<()> {
+        Ok(())
+    }
 
     fn handle_cursor(&mut self, _cursor: CursorIcon) -> Result<()> {
         Ok(())
@@ -625,6 +1056,24 @@ pub(super) trait Intepreter {
     fn handle_event(&mut self, _id: usize) -> Result<()> {
         Ok(())
     }
+    fn handle_named_event(&mut self, _name: &str) -> Result<()> {
+        Ok(())
+    }
+    fn handle_on_scroll_report(&mut self, _id: usize) -> Result<()> {
+        Ok(())
+    }
+
+    // Library bodies run inline, as part of the element that called them; only the layout pass
+    // follows the jump, the later passes see the body spliced into the element's ragged regions.
+    // Calling convention: the caller `PushArg`s the arguments, the body `PullArg`s them, and a
+    // body can leave results on the stack before `LibraryReturn` for the caller to `PullArg`.
+    fn handle_library_call(&mut self, _id: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_library_return(&mut self) -> Result<()> {
+        Ok(())
+    }
 
     fn handle_begin_path(&mut self) -> Result<()> {
         Ok(())
@@ -697,4 +1146,14 @@ pub(super) trait Intepreter {
     ) -> Result<()> {
         Ok(())
     }
+
+    fn handle_line(
+        &mut self,
+        _x0: taffy::LengthPercentage,
+        _y0: taffy::LengthPercentage,
+        _x1: taffy::LengthPercentage,
+        _y1: taffy::LengthPercentage,
+    ) -> Result<()> {
+        Ok(())
+    }
 }
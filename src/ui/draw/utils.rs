@@ -1,31 +1,45 @@
 use std::time::Duration;
 
-use super::TaggedWord;
 use super::traits::{HasStaticConfig, ReadIn};
-use anyhow::{Context, Result};
+use super::{ColorSpaceOption, TaggedWord, VmLimits};
+use anyhow::{Context, Result, anyhow};
 
 /* :---- Book keeping and utils ---- */
 
 #[derive(Debug, Clone, Copy)]
 pub(super) struct StaticConfig {
     file_start: *const u8,
+    file_end: *const u8,
     base_font_size: f32,
     display_scale: f32,
-    #[allow(dead_code)]
     dt: Duration,
+    vm_limits: VmLimits,
+    color_space: ColorSpaceOption,
+    reduce_motion: bool,
+    viewport: (f32, f32), /* the window, in the same pixels as the layout */
 }
 impl StaticConfig {
     pub fn new(
         file_start: *const u8,
+        file_end: *const u8,
         base_font_size: f32,
         display_scale: f32,
         dt: Duration,
+        vm_limits: VmLimits,
+        color_space: ColorSpaceOption,
+        reduce_motion: bool,
+        viewport: (f32, f32),
     ) -> Self {
         Self {
             file_start,
+            file_end,
             base_font_size,
             display_scale,
             dt,
+            vm_limits,
+            color_space,
+            reduce_motion,
+            viewport,
         }
     }
 }
@@ -35,6 +49,10 @@ impl HasStaticConfig for StaticConfig {
         self.file_start
     }
 
+    fn file_end(&self) -> *const u8 {
+        self.file_end
+    }
+
     fn base_font_size(&self) -> f32 {
         self.base_font_size
     }
@@ -46,6 +64,22 @@ impl HasStaticConfig for StaticConfig {
     fn get_dt(&self) -> Duration {
         self.dt
     }
+
+    fn vm_limits(&self) -> VmLimits {
+        self.vm_limits
+    }
+
+    fn color_space(&self) -> ColorSpaceOption {
+        self.color_space
+    }
+
+    fn reduce_motion(&self) -> bool {
+        self.reduce_motion
+    }
+
+    fn viewport(&self) -> (f32, f32) {
+        self.viewport
+    }
 }
 
 pub(super) trait IntoCompactLength {
@@ -78,12 +112,41 @@ where
     }
 }
 
-pub fn read_str_from_array_tagged_word(ptr: usize, file_start: *const u8) -> Result<String> {
+/// Borrows the string at `ptr` straight out of the file, without copying it.
+///
+/// # Safety
+/// The file has to stay mapped and locked for as long as the string is used, which the passes
+/// guarantee by only holding it while handling the tag it belongs to.
+pub unsafe fn read_str_from_array_tagged_word<'f>(
+    ptr: usize,
+    file_start: *const u8,
+    file_end: *const u8,
+) -> Result<&'f str> {
+    let len = file_end as usize - file_start as usize;
+    if ptr % size_of::<usize>() != 0
+        || ptr
+            .checked_add(size_of::<TaggedWord>())
+            .is_none_or(|end| end > len)
+    {
+        return Err(anyhow!(
+            "String at loc {:x} is unaligned or outside the {} bytes of the file",
+            ptr,
+            len
+        ));
+    }
     let mut str_cursor = unsafe { file_start.add(ptr) };
     let size = unsafe { TaggedWord::read_in(&mut str_cursor) }
         .read_as_array()
         .with_context(|| format!("Reading string at loc {:x} failed.", ptr))?;
+    if size > len - ptr - size_of::<TaggedWord>() {
+        return Err(anyhow!(
+            "String at loc {:x} is {} bytes long and runs past the end of the file",
+            ptr,
+            size
+        ));
+    }
 
-    let str = std::str::from_utf8(unsafe { std::slice::from_raw_parts(str_cursor, size) })?;
-    Ok(str.to_owned())
+    Ok(std::str::from_utf8(unsafe {
+        std::slice::from_raw_parts(str_cursor, size)
+    })?)
 }
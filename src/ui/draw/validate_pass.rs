@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+
+use super::TaggedWord;
+use super::cursors::LinearCursor;
+use super::traits::{Executor, HasStaticConfig, Intepreter, ReadIn};
+use super::utils::StaticConfig;
+use super::vm_state::VMState;
+
+// ::: ---- Dry run, checks a tree without laying it out ---- :::
+// Walks the bytecode like the layout pass but builds nothing, so a client can find mistakes
+// before it sets the root. No state is active, so no jump is taken and every branch is read.
+
+// Where to pick up again once a library body hits `LibraryReturn`.
+struct CallFrame {
+    return_ptr: *const u8,
+    region: (*const u8, *const u8),
+}
+
+struct ValidateIntepreter<'a> {
+    config: StaticConfig,
+    state: VMState,
+    cursor: LinearCursor,
+    depth: usize, /* elements still open, the root's `Leave` brings it to zero */
+
+    library: &'a HashMap<usize, Vec<usize>>,
+    call_stack: Vec<CallFrame>,
+}
+
+impl<'a> Executor<VMState, LinearCursor, StaticConfig> for ValidateIntepreter<'a> {
+    fn get_config(&self) -> StaticConfig {
+        self.config
+    }
+
+    fn get_cursor(&mut self) -> &mut LinearCursor {
+        &mut self.cursor
+    }
+
+    fn get_vm_state(&mut self) -> &mut VMState {
+        &mut self.state
+    }
+}
+
+impl<'a> Intepreter for ValidateIntepreter<'a> {
+    fn handle_enter(&mut self) -> Result<()> {
+        self.cursor.add_depth();
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn handle_leave(&mut self) -> Result<()> {
+        self.cursor.sub_depth();
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn handle_library_call(&mut self, id: usize) -> Result<()> {
+        let code = self
+            .library
+            .get(&id)
+            .ok_or(anyhow!("Library with id {} not found", id))?;
+        self.call_stack.push(CallFrame {
+            return_ptr: self.cursor.cursor,
+            region: self.cursor.region(),
+        });
+
+        let start = code.as_ptr() as *const u8;
+        let end = unsafe { start.add(code.len() * size_of::<usize>()) };
+        self.cursor.set_region(start, end);
+        Ok(())
+    }
+
+    fn handle_library_return(&mut self) -> Result<()> {
+        let frame = self
+            .call_stack
+            .pop()
+            .ok_or(anyhow!("`LibraryReturn` without a matching `LibraryCall`"))?;
+        self.cursor.set_region(frame.region.0, frame.region.1);
+        self.cursor.cursor = frame.return_ptr;
+        Ok(())
+    }
+}
+
+/// Runs the tree at `region_start` through the interpreter. On error, also returns the offset
+/// from `file_start` of the tagged word it happened at; inside a library body that is the
+/// outermost `LibraryCall`, since the body isn't in the file.
+pub(super) fn validate_pass(
+    region_start: *const u8,
+    region_end: *const u8,
+    config: StaticConfig,
+    library: &HashMap<usize, Vec<usize>>,
+) -> Result<(), (anyhow::Error, usize)> {
+    let file_start = config.file_start() as usize;
+    let mut cursor = LinearCursor::new(region_start, region_end);
+    /* the caller made sure this is an `Enter` */
+    unsafe { TaggedWord::read_in(&mut cursor.cursor) };
+    cursor.add_depth();
+
+    let mut intepreter = ValidateIntepreter {
+        config,
+        state: VMState::new(config.vm_limits()),
+        cursor,
+        depth: 1,
+        library,
+        call_stack: Vec::new(),
+    };
+
+    let offset = |intepreter: &ValidateIntepreter| match intepreter.call_stack.first() {
+        Some(frame) => frame.return_ptr as usize - size_of::<TaggedWord>() - file_start,
+        None => intepreter.cursor.cursor as usize - file_start,
+    };
+
+    let mut trace = Vec::new();
+    loop {
+        let at = offset(&intepreter);
+        match intepreter.advance(&mut trace) {
+            Ok(Some(())) => (),
+            Ok(None) => break,
+            Err(err) => return Err((err, at)),
+        }
+    }
+
+    let end = offset(&intepreter);
+    if !intepreter.call_stack.is_empty() {
+        return Err((
+            anyhow!(
+                "Library body ended without `LibraryReturn` ({} call(s) still open)",
+                intepreter.call_stack.len()
+            ),
+            end,
+        ));
+    }
+    if intepreter.depth != 0 {
+        return Err((
+            anyhow!(
+                "The tree ends with {} element(s) still open, every `Enter` needs a `Leave`",
+                intepreter.depth
+            ),
+            end,
+        ));
+    }
+    Ok(())
+}
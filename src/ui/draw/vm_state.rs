@@ -1,27 +1,81 @@
-use super::TaggedWord;
-use super::traits::{HasRegister, HasStack};
+use super::traits::{HasRegister, HasScratch, HasStack};
+use super::{TaggedWord, VmLimits};
+use anyhow::{Result, anyhow};
 use std::{collections::HashMap, usize};
 
 // ::: ---- Basic VM State Implementation --- ::
+/// The register scopes, the caller's first.
+pub(super) type Scopes = Vec<HashMap<usize, TaggedWord>>;
+
 pub(super) struct VMState {
-    regs: HashMap<usize, TaggedWord>,
+    regs: Scopes, /* one scope per active library call, innermost last */
     stack: Vec<TaggedWord>,
+    scratch: Vec<String>, /* strings made by the VM, live for one pass */
+    limits: VmLimits,
 }
 impl VMState {
-    pub fn new() -> Self {
+    pub fn new(limits: VmLimits) -> Self {
         VMState {
-            regs: HashMap::new(),
+            regs: vec![HashMap::new()],
             stack: Vec::new(),
+            scratch: Vec::new(),
+            limits,
         }
     }
+
+    /// The scopes of the library calls that are open, `None` outside of any.
+    pub fn call_scopes(&self) -> Option<Scopes> {
+        (self.regs.len() > 1).then(|| self.regs.clone())
+    }
+
+    /// Puts `scopes` in place of the register scopes, returning the ones there were.
+    pub fn swap_scopes(&mut self, scopes: Scopes) -> Scopes {
+        std::mem::replace(&mut self.regs, scopes)
+    }
 }
 impl HasRegister for VMState {
     fn regs_get(&mut self, k: usize) -> Option<TaggedWord> {
-        self.regs.get(&k).cloned()
+        self.regs.last().and_then(|scope| scope.get(&k)).cloned()
+    }
+
+    fn regs_get_outer(&mut self, k: usize) -> Option<TaggedWord> {
+        let n = self.regs.len();
+        if n < 2 {
+            return None;
+        }
+        self.regs[n - 2].get(&k).cloned()
+    }
+
+    fn regs_set(&mut self, k: usize, v: TaggedWord) -> Result<()> {
+        if let Some(scope) = self.regs.last_mut() {
+            if scope.len() >= self.limits.max_registers && !scope.contains_key(&k) {
+                return Err(anyhow!(
+                    "VM register limit reached, at most {} registers can be set per scope",
+                    self.limits.max_registers
+                ));
+            }
+            scope.insert(k, v);
+        }
+        Ok(())
     }
 
-    fn regs_set(&mut self, k: usize, v: TaggedWord) -> () {
-        self.regs.insert(k, v);
+    fn regs_push_scope(&mut self) -> Result<()> {
+        if self.regs.len() > self.limits.max_call_depth {
+            return Err(anyhow!(
+                "VM call depth exceeded, libraries can be nested at most {} deep",
+                self.limits.max_call_depth
+            ));
+        }
+        self.regs.push(HashMap::new());
+        Ok(())
+    }
+
+    fn regs_pop_scope(&mut self) -> Result<()> {
+        if self.regs.len() <= 1 {
+            return Err(anyhow!("`LibraryReturn` without a matching `LibraryCall`"));
+        }
+        self.regs.pop();
+        Ok(())
     }
 }
 impl HasStack for VMState {
@@ -29,7 +83,24 @@ impl HasStack for VMState {
         self.stack.pop()
     }
 
-    fn stack_push(&mut self, v: TaggedWord) -> () {
+    fn stack_push(&mut self, v: TaggedWord) -> Result<()> {
+        if self.stack.len() >= self.limits.max_stack {
+            return Err(anyhow!(
+                "VM stack overflow, at most {} arguments can be pushed",
+                self.limits.max_stack
+            ));
+        }
         self.stack.push(v);
+        Ok(())
+    }
+}
+impl HasScratch for VMState {
+    fn scratch_push(&mut self, v: String) -> usize {
+        self.scratch.push(v);
+        self.scratch.len() - 1
+    }
+
+    fn scratch_get(&self, i: usize) -> Option<&str> {
+        self.scratch.get(i).map(|x| x.as_str())
     }
 }
@@ -0,0 +1,120 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result, anyhow};
+use serde_json::{Value, json};
+
+use super::Vdom;
+use super::draw::{CarriedState, StateKey};
+
+// ::: ---- Snapshots of what the window is drawing, for bug reports ---- :::
+// A dump is two files. `<path>` holds the bytes of the tree's memory, the same as a "tree"
+// message or the data region of the shared file, so `--tree <path>` draws it again. Next to
+// it `<path>.json` has the root, the registered libraries and the state carried between
+// frames, which `--tree` reads the libraries back from.
+
+/// The state carried into the next frame, each entry by where it is kept: its offset in the
+/// tree, `null` if it is in a library body, or the element's "Key". Sorted, since the map's
+/// order changes from run to run.
+pub(super) fn state_json(
+    frame_state: &HashMap<StateKey, CarriedState>,
+    file_start: *const u8,
+    file_end: *const u8,
+) -> Vec<Value> {
+    let mut state = frame_state
+        .iter()
+        .map(|(key, state)| {
+            let at = match key {
+                StateKey::Ptr(ptr) => {
+                    let in_tree = (file_start..file_end).contains(ptr);
+                    json!({"at": in_tree.then(|| *ptr as usize - file_start as usize)})
+                }
+                StateKey::Key(key) => json!({"key": key}),
+                StateKey::Jump(key, offset) => json!({"key": key, "jump": offset}),
+            };
+            json!({
+                "where": at,
+                "active": state.is_jmp,
+                "scroll_y": state.scroll_y,
+                "captured": state.captured,
+            })
+        })
+        .collect::<Vec<_>>();
+    state.sort_by_key(|x| x.to_string());
+    state
+}
+
+/// Writes the dump of `vdom` to `path` and `<path>.json`.
+pub(super) fn write_dump(
+    path: &Path,
+    vdom: &Vdom,
+    libraries: &HashMap<usize, Vec<usize>>,
+    frame_state: &HashMap<StateKey, CarriedState>,
+) -> Result<()> {
+    let (Some(root), Some(buffer)) = vdom.get() else {
+        return Err(anyhow!("There is no tree to dump, set a root first"));
+    };
+    let state = buffer.with_bytes(|file_start, file_end| {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(file_start, file_end as usize - file_start as usize)
+        };
+        std::fs::write(path, bytes)
+            .with_context(|| format!("Couldn't write the tree to {}", path.display()))?;
+        Ok(state_json(frame_state, file_start, file_end))
+    })?;
+
+    let sidecar = json!({
+        "root": root,
+        "libraries": libraries
+            .iter()
+            .map(|(id, words)| (id.to_string(), json!(words)))
+            .collect::<serde_json::Map<_, _>>(),
+        "state": state,
+    });
+    let sidecar_path = sidecar_path(path);
+    std::fs::write(&sidecar_path, serde_json::to_string_pretty(&sidecar)?)
+        .with_context(|| format!("Couldn't write {}", sidecar_path.display()))?;
+    Ok(())
+}
+
+/// The libraries in the sidecar of the dump at `path`, if it has one.
+pub fn read_dump_libraries(path: &Path) -> Result<Option<HashMap<usize, Vec<usize>>>> {
+    let sidecar_path = sidecar_path(path);
+    if !sidecar_path.exists() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(&sidecar_path)
+        .with_context(|| format!("Couldn't read {}", sidecar_path.display()))?;
+    let sidecar: Value = serde_json::from_str(&text)?;
+    let Some(libraries) = sidecar.get("libraries").and_then(|x| x.as_object()) else {
+        return Ok(None);
+    };
+    libraries
+        .iter()
+        .map(|(id, words)| {
+            let id = id.parse::<usize>()?;
+            let words = words
+                .as_array()
+                .ok_or(anyhow!(
+                    "Library {} in {} isn't a list of words",
+                    id,
+                    sidecar_path.display()
+                ))?
+                .iter()
+                .map(|w| w.as_u64().map(|w| w as usize))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(anyhow!(
+                    "Library {} in {} isn't a list of words",
+                    id,
+                    sidecar_path.display()
+                ))?;
+            Ok((id, words))
+        })
+        .collect::<Result<HashMap<_, _>>>()
+        .map(Some)
+}
+
+fn sidecar_path(path: &Path) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".json");
+    name.into()
+}
@@ -0,0 +1,127 @@
+// ::: ---- Gamepads, for a UI on the couch ---- :::
+// With `--gamepad` the controllers are read on a thread of their own, which queues what happened
+// and wakes the window. The window folds the queue into its input before each frame: the d-pad
+// and A press the arrow keys and Enter, so whatever the keyboard does the controller does too,
+// held buttons are kept for `ButtonPressed`, and every event is passed on to the client.
+// Buttons and axes are numbered like the W3C "standard gamepad" mapping, 0 is A (the bottom
+// face button), 12 to 15 are the d-pad, 16 the home button.
+
+/// Something that happened on gamepad `gamepad`, numbered in the order they were plugged in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub struct GamepadEvent {
+    pub gamepad: usize,
+    pub kind: GamepadEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub enum GamepadEventKind {
+    Connected,
+    Disconnected,
+    Pressed(usize),
+    Released(usize),
+    Axis { axis: usize, value: f32 }, /* -1 to 1, up is positive */
+}
+
+#[cfg(feature = "gamepad")]
+pub use thread::spawn_gamepad_thread;
+
+#[cfg(feature = "gamepad")]
+mod thread {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use gilrs::{Axis, Button, EventType, Gilrs};
+    use tracing::{error, info};
+    use winit::window::Window;
+
+    use super::{GamepadEvent, GamepadEventKind};
+
+    /// Reads the gamepads until the window is gone, queueing their events in `queue`.
+    pub fn spawn_gamepad_thread(window: Arc<Window>, queue: Arc<Mutex<Vec<GamepadEvent>>>) {
+        let window = Arc::downgrade(&window);
+        let spawned = std::thread::Builder::new()
+            .name("gamepad".into())
+            .spawn(move || {
+                let mut gilrs = match Gilrs::new() {
+                    Ok(gilrs) => gilrs,
+                    Err(err) => {
+                        error!("Couldn't read gamepads. {}", err);
+                        return;
+                    }
+                };
+                for (id, gamepad) in gilrs.gamepads() {
+                    info!("Gamepad {} is {}", usize::from(id), gamepad.name());
+                }
+                loop {
+                    /* the timeout only lets the thread notice the window closed */
+                    let event = gilrs.next_event_blocking(Some(Duration::from_millis(500)));
+                    let Some(window) = window.upgrade() else {
+                        return;
+                    };
+                    let Some(event) = event else {
+                        continue;
+                    };
+                    let kind = match event.event {
+                        EventType::Connected => GamepadEventKind::Connected,
+                        EventType::Disconnected => GamepadEventKind::Disconnected,
+                        EventType::ButtonPressed(button, _) => match standard_button(button) {
+                            Some(button) => GamepadEventKind::Pressed(button),
+                            None => continue,
+                        },
+                        EventType::ButtonReleased(button, _) => match standard_button(button) {
+                            Some(button) => GamepadEventKind::Released(button),
+                            None => continue,
+                        },
+                        EventType::AxisChanged(axis, value, _) => match standard_axis(axis) {
+                            Some(axis) => GamepadEventKind::Axis { axis, value },
+                            None => continue,
+                        },
+                        _ => continue,
+                    };
+                    queue.lock().unwrap().push(GamepadEvent {
+                        gamepad: usize::from(event.id),
+                        kind,
+                    });
+                    window.request_redraw();
+                }
+            });
+        if let Err(err) = spawned {
+            error!("Couldn't start the gamepad thread. {}", err);
+        }
+    }
+
+    fn standard_button(button: Button) -> Option<usize> {
+        Some(match button {
+            Button::South => 0,
+            Button::East => 1,
+            Button::West => 2,
+            Button::North => 3,
+            Button::LeftTrigger => 4,
+            Button::RightTrigger => 5,
+            Button::LeftTrigger2 => 6,
+            Button::RightTrigger2 => 7,
+            Button::Select => 8,
+            Button::Start => 9,
+            Button::LeftThumb => 10,
+            Button::RightThumb => 11,
+            Button::DPadUp => 12,
+            Button::DPadDown => 13,
+            Button::DPadLeft => 14,
+            Button::DPadRight => 15,
+            Button::Mode => 16,
+            _ => return None,
+        })
+    }
+
+    fn standard_axis(axis: Axis) -> Option<usize> {
+        Some(match axis {
+            Axis::LeftStickX => 0,
+            Axis::LeftStickY => 1,
+            Axis::RightStickX => 2,
+            Axis::RightStickY => 3,
+            _ => return None,
+        })
+    }
+}
@@ -1,40 +1,441 @@
 pub mod assembler;
+pub mod command;
 pub mod context;
 pub mod debug;
 pub mod draw;
+pub mod dump;
+pub mod gamepad;
+pub mod keys;
 pub mod renderer;
+pub mod script;
+pub mod testing;
 
 use anyhow::{Result, anyhow};
+use command::UiCommand;
 use context::VulkanRenderContext;
-use draw::{CarriedState, draw};
+use draw::{
+    CarriedState, Damage, DrawnFrame, ElementBox, ElementId, FiredEvent, PaintLog, PassTrace,
+    StateKey, Viewport, VmLimits, draw, hit_test,
+};
+use gamepad::{GamepadEvent, GamepadEventKind};
 use memmap2::MmapMut;
 use parley::{FontContext, LayoutContext};
-use renderer::VulkanRenderer;
-use skia_safe::{Color, Color4f, Font, FontMgr, FontStyle, Paint};
+use renderer::{RendererLost, VulkanRenderer};
+use skia_safe::{
+    AlphaType, BlendMode, Canvas, Color, Color4f, ColorType, Data, Font, FontMgr, FontStyle, Image,
+    ImageInfo, Paint, PictureRecorder, Rect, Surface, gpu, image::CachingHint,
+};
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     time::{Duration, Instant},
 };
-use tokio::{sync::mpsc::Receiver, task::JoinHandle};
-use tracing::error;
+use tokio::{
+    sync::mpsc::{Receiver, UnboundedReceiver},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
 
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalPosition,
-    event::{ElementState, MouseButton, WindowEvent},
-    event_loop::{ActiveEventLoop, EventLoop},
-    window::{CursorIcon, Window},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, MouseButton, StartCause, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{CursorIcon, Icon, Window, WindowLevel},
 };
 
 use crate::shm::{DATA_OFF, LEN, SemMutex};
 
-#[derive(Default, Clone, Copy)]
+// Options fixed at startup which shape the window and how it is presented.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub transparent: bool,
+    pub decorations: bool,
+    pub window_level: WindowLevel,
+    pub icon: Option<Icon>,
+    pub min_size: Option<(u32, u32)>,
+    pub max_size: Option<(u32, u32)>,
+    pub logical_coordinates: bool, /* the VM works in logical pixels, see `RedrawRequested` */
+    pub msaa: usize,               /* samples per pixel of the backbuffer */
+    pub color_space: ColorSpaceOption,
+    pub scroll_speed: f32,    /* multiplies every wheel and trackpad scroll */
+    pub natural_scroll: bool, /* invert the scroll direction */
+    pub reduce_motion: bool,  /* no animations, changes show up in the very next frame */
+    pub vm_limits: VmLimits,
+    pub base_font_size: f32, /* what "Rems" are relative to, and the root's font size */
+    pub profile: bool,       /* log how long each pass of a frame took */
+    pub debug_layout: bool,  /* outline every element's box on top of the frame */
+    pub gamepad: bool,       /* read gamepads, needs the `gamepad` feature */
+}
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            transparent: false,
+            decorations: true,
+            window_level: WindowLevel::Normal,
+            icon: None,
+            min_size: None,
+            max_size: None,
+            logical_coordinates: false,
+            msaa: 1,
+            color_space: ColorSpaceOption::Srgb,
+            scroll_speed: 1.0,
+            natural_scroll: false,
+            reduce_motion: false,
+            vm_limits: VmLimits::default(),
+            base_font_size: 16.0,
+            profile: false,
+            debug_layout: false,
+            gamepad: false,
+        }
+    }
+}
+
+// The colour space of the swapchain, and the one colours in the layout are read in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpaceOption {
+    #[default]
+    Srgb,
+    DisplayP3,
+}
+impl ColorSpaceOption {
+    /// The space to tag surfaces and colours with, `None` for sRGB which skia assumes anyway.
+    pub fn to_skia(self) -> Option<skia_safe::ColorSpace> {
+        match self {
+            ColorSpaceOption::Srgb => None,
+            ColorSpaceOption::DisplayP3 => Some(skia_safe::ColorSpace::new_rgb(
+                &skia_safe::named_transfer_fn::SRGB,
+                &skia_safe::named_gamut::DISPLAY_P3,
+            )),
+        }
+    }
+}
+
+/// Decodes the image at `path` into a window icon.
+pub fn load_icon(path: &Path) -> Result<Icon> {
+    let bytes = std::fs::read(path)?;
+    let image = Image::from_encoded(Data::new_copy(&bytes)).ok_or(anyhow!(
+        "`{}` isn't an image format we can decode.",
+        path.display()
+    ))?;
+
+    let info = ImageInfo::new(
+        image.dimensions(),
+        ColorType::RGBA8888,
+        AlphaType::Unpremul,
+        None,
+    );
+    let mut rgba = vec![0u8; info.compute_min_byte_size()];
+    if !image.read_pixels(
+        &info,
+        rgba.as_mut_slice(),
+        info.min_row_bytes(),
+        (0, 0),
+        CachingHint::Disallow,
+    ) {
+        return Err(anyhow!("Couldn't convert `{}` to RGBA.", path.display()));
+    }
+    Ok(Icon::from_rgba(
+        rgba,
+        image.width() as u32,
+        image.height() as u32,
+    )?)
+}
+
+/// A surface for `canvas` to draw frames into, with `samples` per pixel if the GPU can do that.
+fn new_backbuffer(canvas: &Canvas, info: &ImageInfo, samples: usize) -> Option<Surface> {
+    if samples > 1 {
+        /* skia lowers the count to the most the GPU supports for the format */
+        let surface = canvas.recording_context().and_then(|mut context| {
+            gpu::surfaces::render_target(
+                &mut context,
+                gpu::Budgeted::Yes,
+                info,
+                samples,
+                gpu::SurfaceOrigin::TopLeft,
+                None,
+                false,
+                None,
+            )
+        });
+        if surface.is_some() {
+            return surface;
+        }
+        warn!("Couldn't make a multisampled backbuffer, drawing without MSAA.");
+    }
+    canvas.new_surface(info, None)
+}
+
+// Where the bytecode tree lives, the shared memory arena or a copy pushed over the socket.
+// Either way pointers in the tree are offsets from the start of the buffer.
+#[derive(Debug, Clone)]
+pub enum TreeBuffer {
+    Shm(Arc<SemMutex<MmapMut>>),
+    Owned(Arc<Vec<usize>>), /* Vec<usize> so the tree is word aligned */
+}
+impl TreeBuffer {
+    /// A copy of `bytes`, laid out like the data region of the shared file, with the root at `root`.
+    pub fn from_bytes(bytes: &[u8], root: usize) -> Result<Self> {
+        if root % size_of::<usize>() != 0 || root >= bytes.len() {
+            return Err(anyhow!(
+                "Root {} must be word aligned and inside the {} bytes of the tree",
+                root,
+                bytes.len()
+            ));
+        }
+        // Copy into words so the tree is aligned the same as in shared memory.
+        let words = bytes
+            .chunks(size_of::<usize>())
+            .map(|chunk| {
+                let mut word = [0u8; size_of::<usize>()];
+                word[..chunk.len()].copy_from_slice(chunk);
+                usize::from_ne_bytes(word)
+            })
+            .collect::<Vec<usize>>();
+        Ok(TreeBuffer::Owned(Arc::new(words)))
+    }
+
+    /// Calls `f` with the start and end of the tree's memory, holding the shm lock while it runs.
+    pub fn with_bytes<R>(&self, f: impl FnOnce(*const u8, *const u8) -> Result<R>) -> Result<R> {
+        match self {
+            TreeBuffer::Shm(vdom) => {
+                let file_lock = vdom
+                    .lock()
+                    .map_err(|_| anyhow!("Failed to acquire lock on shared memory."))?;
+                let file_start = unsafe { file_lock.data.as_ptr().add(DATA_OFF) };
+                let file_end = unsafe { file_lock.data.as_ptr().add(LEN) };
+                f(file_start, file_end)
+            }
+            TreeBuffer::Owned(words) => {
+                let file_start = words.as_ptr() as *const u8;
+                let file_end = unsafe { file_start.add(words.len() * size_of::<usize>()) };
+                f(file_start, file_end)
+            }
+        }
+    }
+}
+
+/// The tree to draw, its root and the memory the root points into. They only change together,
+/// through the methods below, so drawing never pairs a root with memory it wasn't meant for.
+/// Also where the elements ended up in the last frame that was drawn, and the roots the client
+/// gave a name to.
+#[derive(Debug, Default)]
+pub struct Vdom {
+    root: Option<usize>,
+    buffer: Option<TreeBuffer>,
+    boxes: Vec<ElementBox<ElementId>>,   /* in layout pixels */
+    named_roots: HashMap<String, usize>, /* offsets in the shared file */
+}
+impl Vdom {
+    pub fn new(root: usize, buffer: TreeBuffer) -> Self {
+        Self {
+            root: Some(root),
+            buffer: Some(buffer),
+            boxes: Vec::new(),
+            named_roots: HashMap::new(),
+        }
+    }
+
+    /// The elements under `(x, y)` in layout pixels, the topmost first and then the ones it
+    /// sits in. Empty if nothing was drawn there.
+    pub fn hit_test(&self, x: f32, y: f32) -> Vec<ElementBox<ElementId>> {
+        hit_test(&self.boxes, (x, y).into())
+            .into_iter()
+            .map(|i| self.boxes[i])
+            .collect()
+    }
+
+    pub fn get(&self) -> (Option<usize>, Option<&TreeBuffer>) {
+        (self.root, self.buffer.as_ref())
+    }
+
+    /// A root set with `set_root`, which is always in the shared file.
+    pub fn set_shm_root(&mut self, root: usize, shm: Arc<SemMutex<MmapMut>>) {
+        self.root = Some(root);
+        self.buffer = Some(TreeBuffer::Shm(shm));
+    }
+
+    /// A whole tree sent in a "tree" message.
+    pub fn set_tree(&mut self, root: usize, tree: TreeBuffer) {
+        self.root = Some(root);
+        self.buffer = Some(tree);
+    }
+
+    /// The client signalled it wrote to the shared file. A tree sent over the socket is kept
+    /// until the client sets a root in the file again, its root means nothing in the file.
+    pub fn shm_written(&mut self, shm: Arc<SemMutex<MmapMut>>) {
+        if !matches!(self.buffer, Some(TreeBuffer::Owned(_))) {
+            self.buffer = Some(TreeBuffer::Shm(shm));
+        }
+    }
+
+    /// The root, if it is in the shared file.
+    pub fn shm_root_mut(&mut self) -> Option<&mut usize> {
+        match self.buffer {
+            Some(TreeBuffer::Shm(_)) => self.root.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Remembers `root` in the shared file as `name` for `set_root_named`, replacing the root
+    /// that had the name before.
+    pub fn register_root(&mut self, name: &str, root: usize) {
+        self.named_roots.insert(name.to_owned(), root);
+    }
+
+    pub fn named_root(&self, name: &str) -> Option<usize> {
+        self.named_roots.get(name).copied()
+    }
+
+    pub fn named_roots_mut(&mut self) -> impl Iterator<Item = &mut usize> {
+        self.named_roots.values_mut()
+    }
+}
+
+pub type Vdoms = Arc<Mutex<Vdom>>;
+// Library bodies by id, the target of `LibraryCall`. Stored as words so the code is aligned.
+pub type Libraries = Arc<Mutex<HashMap<usize, Vec<usize>>>>;
+
+/* most keys and characters kept for the next frame, they pile up while none is drawn */
+const MAX_QUEUED_KEYS: usize = 64;
+
+/// A key that went down since the last frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyPress {
+    key: KeyCode,
+    repeat: bool, /* sent again by the OS because the key is held, not pressed anew */
+}
+
+#[derive(Default, Clone, PartialEq)]
 pub struct InputState {
     cursor_pos: PhysicalPosition<f64>,
     mouse_down: bool,
+    mouse_just_pressed: bool, /* starts a pointer capture, see `DrawIntepreter::handle_enter` */
     mouse_just_released: bool,
     scroll_action: (f32, f32),
+    keys_pressed: Vec<KeyPress>,
+    keys_released: Vec<KeyCode>,
+    text_typed: Vec<char>, /* the text the pressed keys made, for `KeyPress` */
+    keyboard_modality: bool, /* the last key or button pressed was on the keyboard, for `FocusVisible` */
+    gamepad_buttons: u32,    /* held on any gamepad, bit `n` is button `n` */
+}
+impl InputState {
+    /// The same input with positions and distances multiplied by `factor`.
+    fn scaled(&self, factor: f64) -> Self {
+        Self {
+            cursor_pos: PhysicalPosition::new(
+                self.cursor_pos.x * factor,
+                self.cursor_pos.y * factor,
+            ),
+            scroll_action: (
+                self.scroll_action.0 * factor as f32,
+                self.scroll_action.1 * factor as f32,
+            ),
+            ..self.clone()
+        }
+    }
+
+    fn gamepad_button_down(&self, button: usize) -> bool {
+        button < 32 && self.gamepad_buttons & (1 << button) != 0
+    }
+
+    /// Whether the key numbered `usage` (see `keys`) went down since the last frame, held keys
+    /// repeating count too.
+    fn key_went_down(&self, usage: usize) -> bool {
+        self.keys_pressed
+            .iter()
+            .any(|press| keys::hid_usage(press.key) == Some(usage))
+    }
+
+    fn key_went_up(&self, usage: usize) -> bool {
+        self.keys_released
+            .iter()
+            .any(|&key| keys::hid_usage(key) == Some(usage))
+    }
+
+    /// Drops the oldest keys and text past `MAX_QUEUED_KEYS`. Only a frame uses them up, and
+    /// none is drawn while the window is minimised.
+    fn bound_keys(&mut self) {
+        fn bound<T>(queue: &mut Vec<T>) {
+            let over = queue.len().saturating_sub(MAX_QUEUED_KEYS);
+            queue.drain(..over);
+        }
+        bound(&mut self.keys_pressed);
+        bound(&mut self.keys_released);
+        bound(&mut self.text_typed);
+    }
+
+    /// Folds a gamepad event into the input. The d-pad and A press the arrow keys and Enter.
+    fn gamepad(&mut self, event: GamepadEvent) {
+        match event.kind {
+            GamepadEventKind::Pressed(button) => {
+                if button < 32 {
+                    self.gamepad_buttons |= 1 << button;
+                }
+                let Some(key) = gamepad_key(button) else {
+                    return;
+                };
+                self.keyboard_modality = true; /* a focus ring is as useful on the couch */
+                self.keys_pressed.push(KeyPress { key, repeat: false });
+                self.bound_keys();
+            }
+            GamepadEventKind::Released(button) if button < 32 => {
+                self.gamepad_buttons &= !(1 << button);
+                if let Some(key) = gamepad_key(button) {
+                    self.keys_released.push(key);
+                    self.bound_keys();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// How far the keys pressed this frame scroll, `line` per arrow key and `page` per page key.
+    // Holding a key repeats it at the OS's rate. Arrows step with every repeat so a held arrow
+    // scrolls smoothly, a page jump only happens on the initial press since a page per repeat
+    // would race through the content.
+    fn key_scroll(&self, line: f32, page: f32) -> f32 {
+        self.keys_pressed
+            .iter()
+            .map(|press| match (press.key, press.repeat) {
+                (KeyCode::ArrowUp, _) => line,
+                (KeyCode::ArrowDown, _) => -line,
+                (KeyCode::PageUp, false) => page,
+                (KeyCode::PageDown, false) => -page,
+                _ => 0.0,
+            })
+            .sum()
+    }
+}
+
+/// The key a gamepad button stands in for.
+fn gamepad_key(button: usize) -> Option<KeyCode> {
+    Some(match button {
+        0 => KeyCode::Enter,
+        12 => KeyCode::ArrowUp,
+        13 => KeyCode::ArrowDown,
+        14 => KeyCode::ArrowLeft,
+        15 => KeyCode::ArrowRight,
+        _ => return None,
+    })
+}
+
+/// What a frame is drawn from besides the tree, the libraries and the carried state.
+// A frame drawn from the same inputs as the last one, which carried the same state into the next
+// frame as it was given and had no colour still fading, comes out exactly the same. Such a frame
+// isn't drawn again, the last one is presented as it is. Anything the client sends wakes the window
+// through `rx` and marks the tree as touched, which always draws.
+#[derive(PartialEq)]
+struct FrameInputs {
+    size: PhysicalSize<u32>,
+    scale_factor: f64,
+    cursor: Option<CursorIcon>,
+    input_state: InputState, /* with the just pressed, scroll and keys of the last frame cleared */
 }
 
 // Used to render atleast n seconds of output before letting the loop go to sleep so that animation can be smooth
@@ -79,42 +480,60 @@ impl AnimationGuard {
 
 struct WGpuBackedApp<F>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
 {
     width: u32,
     height: u32,
-    title: &'static str,
-    vdoms: Arc<Mutex<(Option<usize>, Option<Arc<SemMutex<MmapMut>>>)>>,
+    title: String,
+    cursor: Option<CursorIcon>, /* set by the client, wins over what the elements ask for */
+    config: AppConfig,
+    vdoms: Vdoms,
+    libraries: Libraries,
     cb_push_evt: F,
 
+    window: Option<Arc<Window>>,
     render_ctx: VulkanRenderContext,
-    renderer: Option<VulkanRenderer>,
+    renderer: Option<VulkanRenderer>, /* `None` while the GPU is lost, see `restore_renderer` */
+    renderer_retry: Option<Instant>,  /* when to try making a lost renderer again */
+    render_failing: bool,             /* logged why frames aren't presented, to avoid spam */
 
     font_context: FontContext,
     layout_context: LayoutContext<()>,
 
     input_state: InputState,
-    last_fram_jmps: HashMap<*const u8, CarriedState>,
+    last_fram_jmps: HashMap<StateKey, CarriedState>,
+    backbuffer: Option<Surface>, /* keeps the last frame so only damaged parts are repainted */
+    last_paint_log: Option<PaintLog>,
+    last_clear_color: Option<Color4f>, /* a new one repaints everything */
+    last_viewport: Option<Viewport>,   /* sent to the client when it changes */
+    settled: Option<FrameInputs>,      /* drawing the last frame again would change nothing */
+    tree_touched: Arc<AtomicBool>,     /* the client woke the window since the last frame */
     rx: Option<Receiver<()>>,
     rx_task: Option<JoinHandle<()>>,
+    rx_ui: UnboundedReceiver<UiCommand>,
+    gamepad_queue: Arc<Mutex<Vec<GamepadEvent>>>, /* filled by the gamepad thread */
 
     animate_guard: AnimationGuard,
     last_frame_time: Instant,
 
     just_logged_error: bool, /* to avoid spam */
+    sent_ready: bool,        /* `FiredEvent::Ready` goes out once, after the first good frame */
 }
 
 impl<F> WGpuBackedApp<F>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
 {
     fn new(
         width: u32,
         height: u32,
-        title: &'static str,
-        vdoms: Arc<Mutex<(Option<usize>, Option<Arc<SemMutex<MmapMut>>>)>>,
+        title: String,
+        config: AppConfig,
+        vdoms: Vdoms,
+        libraries: Libraries,
         cb_push_evt: F,
         rx: Receiver<()>,
+        rx_ui: UnboundedReceiver<UiCommand>,
     ) -> Self {
         let font_context = FontContext::new();
 
@@ -122,59 +541,189 @@ where
             width,
             height,
             title,
+            cursor: None,
+            config,
             vdoms,
+            libraries,
             cb_push_evt,
+            window: None,
             render_ctx: VulkanRenderContext::default(),
             renderer: None,
+            renderer_retry: None,
+            render_failing: false,
             font_context,
             layout_context: LayoutContext::new(),
             input_state: InputState::default(),
             rx: Some(rx),
             rx_task: None,
+            rx_ui,
+            gamepad_queue: Arc::new(Mutex::new(Vec::new())),
             last_fram_jmps: HashMap::new(),
+            backbuffer: None,
+            last_paint_log: None,
+            last_clear_color: None,
+            last_viewport: None,
+            settled: None,
+            tree_touched: Arc::new(AtomicBool::new(true)),
             animate_guard: AnimationGuard::new(),
             last_frame_time: std::time::Instant::now(),
             just_logged_error: false,
+            sent_ready: false,
+        }
+    }
+
+    /// Checks how presenting a frame went. A lost GPU drops the renderer and everything drawn
+    /// with it, `restore_renderer` makes them again. Any other error only skips the frame.
+    fn presented(&mut self, result: Result<()>) {
+        match result {
+            Ok(()) => self.render_failing = false,
+            Err(err) if err.is::<RendererLost>() => {
+                warn!("{:#} Setting up drawing to the window again.", err);
+                self.renderer = None; /* first, it abandons skia's use of the old device */
+                self.render_ctx.reset();
+                self.backbuffer = None;
+                self.last_paint_log = None;
+                self.settled = None;
+                self.render_failing = true;
+                self.renderer_retry = Some(Instant::now());
+                if let Some(window) = self.window.as_ref() {
+                    window.request_redraw();
+                }
+            }
+            Err(err) => {
+                if !self.render_failing {
+                    error!("Couldn't present the frame. {:#}", err);
+                    self.render_failing = true;
+                }
+                if let Some(renderer) = self.renderer.as_mut() {
+                    renderer.invalidate_swapchain(); /* start over with the next frame */
+                }
+            }
+        }
+    }
+
+    /// Makes the renderer again after the GPU was lost, trying at most once a second until the
+    /// GPU is back, say once the laptop woke up. Whether there is a renderer now.
+    fn restore_renderer(&mut self, event_loop: &ActiveEventLoop, window: &Arc<Window>) -> bool {
+        if self.renderer_retry.is_some_and(|at| at > Instant::now()) {
+            return false;
+        }
+        match self.render_ctx.renderer_for_window(
+            event_loop,
+            window.clone(),
+            self.config.transparent,
+            self.config.color_space,
+        ) {
+            Ok(renderer) => {
+                info!("Drawing to the window again.");
+                self.renderer = Some(renderer);
+                self.renderer_retry = None;
+                true
+            }
+            Err(err) => {
+                debug!("Couldn't set up drawing yet. {:#}", err);
+                self.render_ctx.reset();
+                self.renderer_retry = Some(Instant::now() + Duration::from_secs(1));
+                false
+            }
         }
     }
 }
 
 impl<F> ApplicationHandler for WGpuBackedApp<F>
 where
-    F: FnMut(usize) -> () + Clone,
+    F: FnMut(FiredEvent) -> () + Clone,
 {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window = Arc::new(
-            event_loop
-                .create_window(
-                    Window::default_attributes()
-                        .with_title(self.title)
-                        .with_inner_size(winit::dpi::PhysicalSize::new(self.width, self.height))
-                        .with_resizable(true),
-                )
-                .unwrap(),
-        );
-        self.renderer = Some(
-            self.render_ctx
-                .renderer_for_window(event_loop, window.clone()),
-        ); /* the example mentions that this is particular for apps with a single window */
+        let mut attributes = Window::default_attributes()
+            .with_title(self.title.as_str())
+            .with_inner_size(winit::dpi::PhysicalSize::new(self.width, self.height))
+            .with_resizable(true)
+            .with_transparent(self.config.transparent)
+            .with_decorations(self.config.decorations)
+            .with_window_level(self.config.window_level)
+            .with_window_icon(self.config.icon.clone());
+        if let Some((width, height)) = self.config.min_size {
+            attributes = attributes.with_min_inner_size(PhysicalSize::new(width, height));
+        }
+        if let Some((width, height)) = self.config.max_size {
+            attributes = attributes.with_max_inner_size(PhysicalSize::new(width, height));
+        }
+        let window = Arc::new(event_loop.create_window(attributes).unwrap());
+        /* the example mentions that this is particular for apps with a single window */
+        match self.render_ctx.renderer_for_window(
+            event_loop,
+            window.clone(),
+            self.config.transparent,
+            self.config.color_space,
+        ) {
+            Ok(renderer) => self.renderer = Some(renderer),
+            Err(err) => {
+                error!("Couldn't draw to the window. {:#}", err);
+                event_loop.exit();
+                return;
+            }
+        }
+        self.window = Some(window.clone());
 
         //
         let mut rx = self.rx.take().unwrap();
         let window_1 = window.clone();
+        let tree_touched = self.tree_touched.clone();
         let j = tokio::spawn(async move {
             loop {
                 if let Some(_) = rx.recv().await {
+                    tree_touched.store(true, Ordering::Release);
                     window_1.request_redraw();
                 }
                 tokio::time::sleep(Duration::from_millis(11)).await; // 90fps
             }
         });
         self.rx_task = Some(j);
+
+        if self.config.gamepad {
+            #[cfg(feature = "gamepad")]
+            gamepad::spawn_gamepad_thread(window.clone(), self.gamepad_queue.clone());
+            #[cfg(not(feature = "gamepad"))]
+            warn!("Not reading gamepads, the runtime was built without the `gamepad` feature.");
+        }
+    }
+
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let (StartCause::ResumeTimeReached { .. }, Some(window)) = (cause, self.window.as_ref())
+        {
+            window.request_redraw(); /* time to try `restore_renderer` again */
+        }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        let window = self.renderer.as_ref().unwrap().window.clone();
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(window) = self.window.clone() else {
+            return;
+        };
+        event_loop.set_control_flow(match self.renderer_retry {
+            Some(at) => ControlFlow::WaitUntil(at),
+            None => ControlFlow::Wait,
+        });
+        while let Ok(command) = self.rx_ui.try_recv() {
+            match &command {
+                UiCommand::SetTitle(title) => {
+                    self.title = title.clone(); /* keep in sync in case the window is recreated */
+                }
+                UiCommand::SetCursor(cursor) => self.cursor = *cursor,
+                UiCommand::DumpState(path, done) => {
+                    let vdom = self.vdoms.lock().unwrap();
+                    let library = self.libraries.lock().unwrap();
+                    let out = dump::write_dump(path, &vdom, &library, &self.last_fram_jmps);
+                    match &out {
+                        Ok(()) => info!("Dumped the tree to {}", path.display()),
+                        Err(err) => error!("Couldn't dump the tree. {:#}", err),
+                    }
+                    let _ = done.send(out); /* the ask may have stopped waiting */
+                }
+                _ => (),
+            }
+            command.apply(&window);
+        }
         if !self.animate_guard.is_done() {
             window.request_redraw();
         }
@@ -186,7 +735,9 @@ where
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        let window = self.renderer.as_ref().unwrap().window.clone();
+        let Some(window) = self.window.clone() else {
+            return;
+        };
 
         match event {
             WindowEvent::Resized(_) => {
@@ -207,8 +758,12 @@ where
                 state,
                 button,
             } => {
+                if state == ElementState::Pressed {
+                    self.input_state.keyboard_modality = false;
+                }
                 if state == ElementState::Pressed && button == MouseButton::Left {
                     self.input_state.mouse_down = true;
+                    self.input_state.mouse_just_pressed = true;
                 } else {
                     self.input_state.mouse_down = false;
                 }
@@ -219,6 +774,34 @@ where
 
                 window.request_redraw();
             }
+            WindowEvent::KeyboardInput {
+                device_id: _,
+                event,
+                is_synthetic: _,
+            } => {
+                match (event.state, event.physical_key) {
+                    (ElementState::Pressed, PhysicalKey::Code(key)) => {
+                        self.input_state.keyboard_modality = true;
+                        self.input_state.keys_pressed.push(KeyPress {
+                            key,
+                            repeat: event.repeat,
+                        });
+                    }
+                    (ElementState::Released, PhysicalKey::Code(key)) => {
+                        self.input_state.keys_released.push(key);
+                    }
+                    _ => (),
+                }
+                // Enter, Tab, Backspace and the like come as control characters, they are
+                // only keys.
+                if let (ElementState::Pressed, Some(text)) = (event.state, event.text.as_ref()) {
+                    self.input_state
+                        .text_typed
+                        .extend(text.chars().filter(|c| !c.is_control()));
+                }
+                self.input_state.bound_keys();
+                window.request_redraw();
+            }
             WindowEvent::MouseWheel {
                 device_id: _,
                 delta,
@@ -231,8 +814,38 @@ where
                     }
                 };
 
-                self.input_state.scroll_action = (dx, dy);
-                self.animate_guard.set(Duration::from_secs(10));
+                let factor = if self.config.natural_scroll {
+                    -self.config.scroll_speed
+                } else {
+                    self.config.scroll_speed
+                };
+                self.input_state.scroll_action = (dx * factor, dy * factor);
+                // Keep drawing for a while so anything the scroll set in motion plays out, or with
+                // reduced motion just draw the one frame with the content where it ends up.
+                if self.config.reduce_motion {
+                    window.request_redraw();
+                } else {
+                    self.animate_guard.set(Duration::from_secs(10));
+                }
+            }
+
+            WindowEvent::Focused(focused) => {
+                if !focused {
+                    /* the release would go to whichever window has focus now */
+                    self.input_state.mouse_down = false;
+                }
+                (self.cb_push_evt)(FiredEvent::Focus(focused));
+            }
+
+            // Several files dragged at once come as one event each.
+            WindowEvent::HoveredFile(path) => {
+                (self.cb_push_evt)(FiredEvent::FileHover(Some(path)));
+            }
+            WindowEvent::HoveredFileCancelled => {
+                (self.cb_push_evt)(FiredEvent::FileHover(None));
+            }
+            WindowEvent::DroppedFile(path) => {
+                (self.cb_push_evt)(FiredEvent::FileDrop(path));
             }
 
             WindowEvent::CloseRequested => {
@@ -243,73 +856,218 @@ where
                 event_loop.exit();
             }
             WindowEvent::RedrawRequested => {
+                // Minimised or resized to nothing. There is nothing to lay out, and a swapchain
+                // can't be zero sized so there is nothing to present to either. A resize to a
+                // real size requests the next redraw.
+                let window_size = window.inner_size();
+                if window_size.width == 0 || window_size.height == 0 {
+                    /* no frame uses up the keys, they'd all fire once the window is back */
+                    self.input_state.keys_pressed.clear();
+                    self.input_state.keys_released.clear();
+                    self.input_state.text_typed.clear();
+                    return;
+                }
+
+                // Gamepad input since the last frame. The client hears of it first, and one more
+                // frame follows so a `ButtonPressed` jump shows the button as it is now.
+                let gamepad_events = std::mem::take(&mut *self.gamepad_queue.lock().unwrap());
+                for event in gamepad_events.iter() {
+                    self.input_state.gamepad(*event);
+                    (self.cb_push_evt)(FiredEvent::Gamepad(*event));
+                }
+                if !gamepad_events.is_empty() {
+                    window.request_redraw();
+                }
+
+                // Nothing can be drawn while the GPU is lost.
+                if self.renderer.is_none() && !self.restore_renderer(event_loop, &window) {
+                    return;
+                }
+
+                // Nothing changed since a frame that settled, present it again without laying
+                // out or drawing anything. Idle redraws, like those of the `animate_guard` after
+                // a scroll, end up here.
+                let inputs = FrameInputs {
+                    size: window_size,
+                    scale_factor: window.scale_factor(),
+                    cursor: self.cursor,
+                    input_state: self.input_state.clone(),
+                };
+                let touched = self.tree_touched.swap(false, Ordering::Acquire);
+                if !touched && self.settled.as_ref() == Some(&inputs) {
+                    if let (Some(renderer), Some(backbuffer), Some(clear_color)) = (
+                        self.renderer.as_mut(),
+                        self.backbuffer.as_mut(),
+                        self.last_clear_color,
+                    ) {
+                        let display_scale = inputs.scale_factor as f32;
+                        let presented = renderer.prepare_swapchain().and_then(|()| {
+                            renderer.draw_and_present(|canvas, _| {
+                                let mut clear_paint = Paint::default();
+                                clear_paint.set_blend_mode(BlendMode::Src);
+                                clear_paint.set_color4f(clear_color, None);
+                                canvas.save();
+                                canvas.scale((1.0 / display_scale, 1.0 / display_scale));
+                                canvas.draw_paint(&clear_paint);
+                                canvas.draw_image(backbuffer.image_snapshot(), (0.0, 0.0), None);
+                                canvas.restore();
+                            })
+                        });
+                        self.presented(presented);
+                        self.animate_guard.update(self.last_frame_time.elapsed());
+                        self.last_frame_time = std::time::Instant::now();
+                        return;
+                    }
+                }
+                self.settled = None;
+
                 if let Some(renderer) = self.renderer.as_mut() {
-                    renderer.prepare_swapchain();
+                    if let Err(err) = renderer.prepare_swapchain() {
+                        self.presented(Err(err));
+                        return;
+                    }
 
                     let display_scale = window.scale_factor() as f32;
-                    let base_font_size = 16.0;
+                    let base_font_size = self.config.base_font_size;
+                    // Pixels the VM works in per logical pixel. By default these are physical pixels
+                    // and every length is scaled as it is read. With `logical_coordinates` the VM
+                    // works in logical pixels and the display scale is only applied when the frame
+                    // is played back below, so the client never deals with the display's DPI.
+                    let vm_scale = if self.config.logical_coordinates {
+                        1.0
+                    } else {
+                        display_scale
+                    };
+                    let input_state = self.input_state.scaled((vm_scale / display_scale) as f64);
+                    let color_space = renderer.color_space();
 
                     /* Window state resets */
-                    window.set_cursor(CursorIcon::Default);
+                    window.set_cursor(self.cursor.unwrap_or(CursorIcon::Default));
                     let dt = self.last_frame_time.elapsed();
 
                     /* User geometry */
-                    renderer.draw_and_present(|canvas, size| {
-                        canvas.clear(Color4f::new(0.95, 0.95, 0.95, 1.0));
-                        /* Handle scaling */
-                        canvas.save();
-                        canvas.scale((1.0 / display_scale, 1.0 / display_scale));
+                    let mut settled = false;
+                    let clear_alpha = if self.config.transparent { 0.0 } else { 1.0 };
+
+                    let presented = renderer.draw_and_present(|canvas, size| {
+                        // The frame is recorded first and only played back where it differs from the
+                        // last one, into a backbuffer that outlives the swapchain images.
+                        let mut recorder = PictureRecorder::new();
+                        let recording = recorder.begin_recording(
+                            Rect::from_wh(size.width * vm_scale, size.height * vm_scale),
+                            false,
+                        );
+                        let mut paint_log = PaintLog::default();
 
-                        let r: Result<HashMap<*const u8, CarriedState>> = {
+                        let mut has_tree = false; /* otherwise there is nothing to tell the client */
+                        let r: Result<DrawnFrame> = {
                             let guard = self.vdoms.lock().unwrap();
-                            let loc = guard.0;
-                            if let Some(vdom) = &guard.1 {
-                                if let Some(loc) = loc {
-                                    if let Ok(file_lock) = vdom.lock() {
-                                        let file_start =
-                                            unsafe { file_lock.data.as_ptr().add(DATA_OFF) };
-                                        let file_end = unsafe { file_lock.data.as_ptr().add(LEN) };
-
-                                        unsafe {
-                                            let out = draw(
-                                                loc,
-                                                file_start,
-                                                file_end,
-                                                size.width * display_scale,
-                                                size.height * display_scale,
-                                                canvas,
-                                                window.clone(),
-                                                self.cb_push_evt.clone(),
-                                                &self.input_state,
-                                                &mut self.font_context,
-                                                &mut self.layout_context,
-                                                display_scale,
-                                                base_font_size,
-                                                &self.last_fram_jmps,
-                                                dt,
-                                            );
-                                            if out.is_ok() {
-                                                self.just_logged_error = false;
-                                            }
-                                            out
+                            let library = self.libraries.lock().unwrap();
+                            match guard.get() {
+                                (Some(loc), Some(buffer)) => {
+                                    has_tree = true;
+                                    buffer.with_bytes(|file_start, file_end| unsafe {
+                                        let out = draw(
+                                            loc,
+                                            file_start,
+                                            file_end,
+                                            size.width * vm_scale,
+                                            size.height * vm_scale,
+                                            recording,
+                                            window.clone(),
+                                            self.cb_push_evt.clone(),
+                                            &input_state,
+                                            &mut self.font_context,
+                                            &mut self.layout_context,
+                                            vm_scale,
+                                            base_font_size,
+                                            &self.last_fram_jmps,
+                                            &library,
+                                            self.config.vm_limits,
+                                            color_space,
+                                            self.config.reduce_motion,
+                                            dt,
+                                            &mut paint_log,
+                                            self.config.profile,
+                                            self.config.debug_layout,
+                                        );
+                                        if out.is_ok() {
+                                            self.just_logged_error = false;
                                         }
-                                    } else {
-                                        Err(anyhow!("Failed to acquire lock on shared memory."))
-                                    }
-                                } else {
+                                        out
+                                    })
+                                }
+                                (None, Some(_)) => {
                                     Err(anyhow!("Location for ui not yet defined in memory."))
                                 }
-                            } else {
-                                Err(anyhow!("Shared memory has not yet been read."))
+                                (_, None) => Err(anyhow!("Shared memory has not yet been read.")),
                             }
                         };
+                        if let Some(cursor) = self.cursor {
+                            window.set_cursor(cursor); /* undo whatever the elements set */
+                        }
 
+                        // `ClearColor` from the layout, the light grey otherwise. A paint
+                        // rather than `clear` so the colour is read in the window's colour space.
+                        let mut clear_paint = Paint::default();
+                        clear_paint.set_blend_mode(BlendMode::Src);
+                        match &r {
+                            Ok(DrawnFrame {
+                                clear_color: Some(color),
+                                ..
+                            }) => {
+                                clear_paint
+                                    .set_color4f(Color4f::from(*color), color_space.to_skia().as_ref());
+                            }
+                            _ => {
+                                clear_paint
+                                    .set_color4f(Color4f::new(0.95, 0.95, 0.95, clear_alpha), None);
+                            }
+                        }
+                        let clear_changed = self.last_clear_color != Some(clear_paint.color4f());
+                        self.last_clear_color = Some(clear_paint.color4f());
+
+                        let frame_ok = r.is_ok();
                         match r {
-                            Ok(jmps) => self.last_fram_jmps = jmps,
+                            Ok(frame) => {
+                                settled = !frame.animating && frame.state == self.last_fram_jmps;
+                                self.last_fram_jmps = frame.state;
+                                self.vdoms.lock().unwrap().boxes = frame.boxes;
+                                if frame.animating {
+                                    window.request_redraw(); /* a `ColorTransition` isn't there yet */
+                                }
+                                if self.last_viewport != Some(frame.viewport) {
+                                    (self.cb_push_evt)(FiredEvent::Viewport(frame.viewport));
+                                    self.last_viewport = Some(frame.viewport);
+                                }
+                                if !self.sent_ready {
+                                    let size = window.inner_size();
+                                    (self.cb_push_evt)(FiredEvent::Ready {
+                                        width: size.width,
+                                        height: size.height,
+                                    });
+                                    self.sent_ready = true;
+                                }
+                            }
                             Err(err) => {
                                 if !self.just_logged_error {
                                     error!("Error when generating frame. {:#}", err);
                                     self.just_logged_error = true;
+                                    if has_tree {
+                                        // The message without the trace, which goes separately.
+                                        let trace = err.downcast_ref::<PassTrace>().cloned();
+                                        let trace_str = trace.as_ref().map(|t| t.to_string());
+                                        let message = err
+                                            .chain()
+                                            .map(|e| e.to_string())
+                                            .filter(|e| Some(e) != trace_str.as_ref())
+                                            .collect::<Vec<_>>()
+                                            .join(": ");
+                                        (self.cb_push_evt)(FiredEvent::RenderError {
+                                            message,
+                                            trace,
+                                        });
+                                    }
                                 }
 
                                 let fmgr = FontMgr::default();
@@ -323,19 +1081,85 @@ where
                                 paint.set_anti_alias(true);
 
                                 let err_str = format!("{:#}", err);
-                                canvas.draw_str(err_str, (10.0, 30.0), &font, &paint);
+                                recording.draw_str(err_str, (10.0, 30.0), &font, &paint);
+                            }
+                        }
+                        let picture = recorder.finish_recording_as_picture(None);
+
+                        /* a new backbuffer (first frame, resize) starts out blank */
+                        let info = canvas.image_info();
+                        let reuse = self.backbuffer.as_ref().is_some_and(|b| {
+                            b.width() == info.width() && b.height() == info.height()
+                        });
+                        if !reuse {
+                            self.backbuffer = new_backbuffer(canvas, &info, self.config.msaa);
+                        }
+
+                        // Error frames and structural changes repaint everything, and so does the
+                        // layout overlay, which the paint log doesn't see.
+                        let partial = reuse && frame_ok && !clear_changed && !self.config.debug_layout;
+                        let damage = match (&self.last_paint_log, partial) {
+                            (Some(last), true) => paint_log.damage_since(last),
+                            _ => Damage::Full,
+                        };
+                        self.last_paint_log = frame_ok.then_some(paint_log);
+
+                        /* Handle scaling */
+                        canvas.save();
+                        canvas.scale((1.0 / display_scale, 1.0 / display_scale));
+                        match self.backbuffer.as_mut() {
+                            Some(backbuffer) => {
+                                let back = backbuffer.canvas();
+                                back.save();
+                                back.scale((display_scale / vm_scale, display_scale / vm_scale));
+                                if let Damage::Partial(rect) = damage {
+                                    back.clip_rect(rect, None, None);
+                                }
+                                if !matches!(damage, Damage::None) {
+                                    back.draw_paint(&clear_paint);
+                                    if let Some(picture) = picture.as_ref() {
+                                        back.draw_picture(picture, None, None);
+                                    }
+                                }
+                                back.restore();
+
+                                canvas.draw_paint(&clear_paint);
+                                canvas.draw_image(backbuffer.image_snapshot(), (0.0, 0.0), None);
+                            }
+                            None => {
+                                /* couldn't make a backbuffer, draw straight to the swapchain */
+                                canvas.draw_paint(&clear_paint);
+                                canvas.scale((display_scale / vm_scale, display_scale / vm_scale));
+                                if let Some(picture) = picture.as_ref() {
+                                    canvas.draw_picture(picture, None, None);
+                                }
                             }
                         }
                         canvas.restore();
                     });
 
-                    // Just released is only for that frame.
-                    if self.input_state.mouse_just_released {
+                    // Just pressed and just released are only for that frame. The jumps they set
+                    // off take effect in the next one.
+                    let keyed = !self.input_state.keys_pressed.is_empty()
+                        || !self.input_state.keys_released.is_empty()
+                        || !self.input_state.text_typed.is_empty();
+                    if self.input_state.mouse_just_released || keyed {
                         window.request_redraw();
                     }
+                    self.input_state.mouse_just_pressed = false;
                     self.input_state.mouse_just_released = false;
                     self.input_state.scroll_action = (0.0, 0.0);
+                    self.input_state.keys_pressed.clear();
+                    self.input_state.keys_released.clear();
+                    self.input_state.text_typed.clear();
+                    if settled {
+                        self.settled = Some(FrameInputs {
+                            input_state: self.input_state.clone(),
+                            ..inputs
+                        });
+                    }
 
+                    self.presented(presented);
                     self.animate_guard.update(dt);
 
                     self.last_frame_time = std::time::Instant::now();
@@ -349,16 +1173,29 @@ where
 pub fn start<F>(
     width: u32,
     height: u32,
-    title: &'static str,
-    vdoms: Arc<Mutex<(Option<usize>, Option<Arc<SemMutex<MmapMut>>>)>>,
+    title: &str,
+    config: AppConfig,
+    vdoms: Vdoms,
+    libraries: Libraries,
     cb_push_evt: F,
     rx: Receiver<()>,
+    rx_ui: UnboundedReceiver<UiCommand>,
 ) where
-    F: FnMut(usize) -> () + Clone + Send + Sync + 'static,
+    F: FnMut(FiredEvent) -> () + Clone + Send + Sync + 'static,
 {
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
 
-    let mut app = WGpuBackedApp::new(width, height, title, vdoms, cb_push_evt, rx);
+    let mut app = WGpuBackedApp::new(
+        width,
+        height,
+        title.to_owned(),
+        config,
+        vdoms,
+        libraries,
+        cb_push_evt,
+        rx,
+        rx_ui,
+    );
     event_loop.run_app(&mut app).unwrap();
 }
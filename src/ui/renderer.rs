@@ -3,16 +3,19 @@ Copy of example at:
 https://github.com/rust-skia/rust-skia/blob/master/skia-safe/examples/vulkan-window/renderer.rs
 */
 
+use anyhow::{Context, Result, anyhow};
 use ash::vk::Handle;
 use std::{ptr, sync::Arc};
+use tracing::warn;
 use vulkano::{
     Validated, VulkanError, VulkanObject,
     device::Queue,
+    format::Format,
     image::{ImageUsage, view::ImageView},
     render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass},
     swapchain::{
-        PresentMode, Surface, Swapchain, SwapchainAcquireFuture, SwapchainCreateInfo,
-        SwapchainPresentInfo, acquire_next_image,
+        ColorSpace, CompositeAlpha, PresentMode, Surface, Swapchain, SwapchainAcquireFuture,
+        SwapchainCreateInfo, SwapchainPresentInfo, acquire_next_image,
     },
     sync::{self, GpuFuture},
 };
@@ -24,6 +27,30 @@ use skia_safe::{
 
 use winit::{dpi::LogicalSize, dpi::PhysicalSize, window::Window};
 
+use super::ColorSpaceOption;
+
+/// The device or the window's surface went away, after a driver reset, a suspend, or when the
+/// GPU the window was on got unplugged. Nothing made with the old device works anymore, so the
+/// renderer, and the device under it, have to be made again.
+#[derive(Debug)]
+pub struct RendererLost(VulkanError);
+impl std::fmt::Display for RendererLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lost the GPU. {}", self.0)
+    }
+}
+impl std::error::Error for RendererLost {}
+
+/// `RendererLost` for the errors that mean it, the error as it is otherwise.
+fn lost_or(err: Validated<VulkanError>, what: &str) -> anyhow::Error {
+    match err {
+        Validated::Error(err @ (VulkanError::DeviceLost | VulkanError::SurfaceLost)) => {
+            RendererLost(err).into()
+        }
+        err => anyhow!("{} {}", what, err),
+    }
+}
+
 pub struct VulkanRenderer {
     pub window: Arc<Window>,
     queue: Arc<Queue>,
@@ -33,6 +60,7 @@ pub struct VulkanRenderer {
     last_render: Option<Box<dyn GpuFuture>>,
     skia_ctx: gpu::DirectContext,
     swapchain_is_valid: bool,
+    color_space: ColorSpaceOption, /* what we got, which may not be what was asked for */
 }
 
 impl Drop for VulkanRenderer {
@@ -43,7 +71,12 @@ impl Drop for VulkanRenderer {
 }
 
 impl VulkanRenderer {
-    pub fn new(window: Arc<Window>, queue: Arc<Queue>) -> Self {
+    pub fn new(
+        window: Arc<Window>,
+        queue: Arc<Queue>,
+        transparent: bool,
+        color_space: ColorSpaceOption,
+    ) -> Result<Self> {
         // Extract references to key structs from the queue
         let library = queue.device().instance().library();
         let instance = queue.device().instance();
@@ -53,25 +86,62 @@ impl VulkanRenderer {
         // Before we can render to a window, we must first create a `vulkano::swapchain::Surface`
         // object from it, which represents the drawable surface of a window. For that we must wrap
         // the `winit::window::Window` in an `Arc`.
-        let surface = Surface::from_window(instance.clone(), window.clone()).unwrap();
+        let surface = Surface::from_window(instance.clone(), window.clone())
+            .context("Failed to create a surface for the window")?;
         let window_size = window.inner_size();
 
         // Before we can draw on the surface, we have to create what is called a swapchain.
         // Creating a swapchain allocates the color buffers that will contain the image that will
         // ultimately be visible on the screen. These images are returned alongside the swapchain.
+        let mut got_color_space = ColorSpaceOption::Srgb;
         let (swapchain, _images) = {
             // Querying the capabilities of the surface. When we create the swapchain we can only
             // pass values that are allowed by the capabilities.
             let surface_capabilities = device
                 .physical_device()
                 .surface_capabilities(&surface, Default::default())
-                .unwrap();
+                .map_err(|err| lost_or(err, "Failed to read the surface's capabilities."))?;
 
             // Choosing the internal format that the images will have.
-            let (image_format, _) = device
+            let surface_formats = device
                 .physical_device()
                 .surface_formats(&surface, Default::default())
-                .unwrap()[0];
+                .map_err(|err| lost_or(err, "Failed to read the surface's formats."))?;
+            let (image_format, image_color_space) = match color_space {
+                ColorSpaceOption::Srgb => None,
+                ColorSpaceOption::DisplayP3 => surface_formats.iter().copied().find(|format| {
+                    /* the one format `surface_for_framebuffer` can wrap */
+                    *format == (Format::B8G8R8A8_UNORM, ColorSpace::DisplayP3NonLinear)
+                }),
+            }
+            .inspect(|_| got_color_space = color_space)
+            .unwrap_or_else(|| {
+                if color_space != ColorSpaceOption::Srgb {
+                    warn!(
+                        "The display doesn't offer {:?}, drawing in sRGB.",
+                        color_space
+                    );
+                }
+                surface_formats[0]
+            });
+
+            // A transparent window needs the compositor to respect our alpha channel, so prefer
+            // one of the blending modes if the surface offers it and fall back to whatever is first.
+            let supported_composite_alpha: Vec<CompositeAlpha> = surface_capabilities
+                .supported_composite_alpha
+                .into_iter()
+                .collect();
+            let composite_alpha = supported_composite_alpha
+                .iter()
+                .copied()
+                .find(|alpha| {
+                    transparent
+                        && matches!(
+                            alpha,
+                            CompositeAlpha::PreMultiplied | CompositeAlpha::PostMultiplied
+                        )
+                })
+                .unwrap_or(supported_composite_alpha[0]);
 
             // Please take a look at the docs for the meaning of the parameters we didn't mention.
             Swapchain::new(
@@ -101,6 +171,7 @@ impl VulkanRenderer {
                     image_usage: ImageUsage::COLOR_ATTACHMENT,
 
                     image_format,
+                    image_color_space,
 
                     // The present_mode affects what is commonly known as "vertical sync" or "vsync" for short.
                     // The `Immediate` mode is equivalent to disabling vertical sync, while the others enable
@@ -115,16 +186,12 @@ impl VulkanRenderer {
                     // The alpha mode indicates how the alpha value of the final image will behave.
                     // For example, you can choose whether the window will be
                     // opaque or transparent.
-                    composite_alpha: surface_capabilities
-                        .supported_composite_alpha
-                        .into_iter()
-                        .next()
-                        .unwrap(),
+                    composite_alpha,
 
                     ..Default::default()
                 },
             )
-            .unwrap()
+            .map_err(|err| lost_or(err, "Failed to create the swapchain."))?
         };
 
         // The next step is to create a *render pass*, which is an object that describes where the
@@ -161,7 +228,7 @@ impl VulkanRenderer {
                 depth_stencil: {},
             },
         )
-        .unwrap();
+        .map_err(|err| lost_or(err, "Failed to create the render pass."))?;
 
         // The render pass we created above only describes the layout of our framebuffers. Before
         // we can draw we also need to create the actual framebuffers.
@@ -232,12 +299,12 @@ impl VulkanRenderer {
                 ),
                 None,
             )
-            .unwrap();
+            .ok_or(anyhow!("Skia couldn't draw with the Vulkan device"))?;
 
             direct_context
         };
 
-        VulkanRenderer {
+        Ok(VulkanRenderer {
             skia_ctx,
             queue,
             window,
@@ -246,7 +313,13 @@ impl VulkanRenderer {
             render_pass,
             framebuffers,
             last_render,
-        }
+            color_space: got_color_space,
+        })
+    }
+
+    /// The colour space the swapchain ended up in.
+    pub fn color_space(&self) -> ColorSpaceOption {
+        self.color_space
     }
 
     pub fn invalidate_swapchain(&mut self) {
@@ -254,7 +327,7 @@ impl VulkanRenderer {
         self.swapchain_is_valid = false;
     }
 
-    pub fn prepare_swapchain(&mut self) {
+    pub fn prepare_swapchain(&mut self) -> Result<()> {
         // It is important to call this function from time to time, otherwise resources
         // will keep accumulating and you will eventually reach an out of memory error.
         // Calling this function polls various fences in order to determine what the GPU
@@ -274,7 +347,7 @@ impl VulkanRenderer {
                     image_extent: window_size.into(),
                     ..self.swapchain.create_info()
                 })
-                .expect("failed to recreate swapchain");
+                .map_err(|err| lost_or(err, "Failed to recreate the swapchain."))?;
 
             self.swapchain = new_swapchain;
 
@@ -284,7 +357,8 @@ impl VulkanRenderer {
             self.framebuffers = new_images
                 .iter()
                 .map(|image| {
-                    let view = ImageView::new_default(image.clone()).unwrap();
+                    let view = ImageView::new_default(image.clone())
+                        .map_err(|err| lost_or(err, "Failed to view a swapchain image."))?;
 
                     Framebuffer::new(
                         self.render_pass.clone(),
@@ -293,25 +367,26 @@ impl VulkanRenderer {
                             ..Default::default()
                         },
                     )
-                    .unwrap()
+                    .map_err(|err| lost_or(err, "Failed to create a framebuffer."))
                 })
-                .collect::<Vec<_>>();
+                .collect::<Result<Vec<_>>>()?;
 
             self.swapchain_is_valid = true;
         }
+        Ok(())
     }
 
-    fn get_next_frame(&mut self) -> Option<(u32, SwapchainAcquireFuture)> {
+    fn get_next_frame(&mut self) -> Result<Option<(u32, SwapchainAcquireFuture)>> {
         // prepare to render by identifying the next framebuffer to draw to and acquiring the
         // GpuFuture that we'll be replacing `last_render` with once we submit the frame
         let (image_index, suboptimal, acquire_future) =
-            match acquire_next_image(self.swapchain.clone(), None).map_err(Validated::unwrap) {
+            match acquire_next_image(self.swapchain.clone(), None) {
                 Ok(r) => r,
-                Err(VulkanError::OutOfDate) => {
+                Err(Validated::Error(VulkanError::OutOfDate)) => {
                     self.swapchain_is_valid = false;
-                    return None;
+                    return Ok(None);
                 }
-                Err(e) => panic!("failed to acquire next image: {e}"),
+                Err(err) => return Err(lost_or(err, "Failed to acquire the next image.")),
             };
 
         // `acquire_next_image` can be successful, but suboptimal. This means that the
@@ -323,27 +398,36 @@ impl VulkanRenderer {
         }
 
         if self.swapchain_is_valid {
-            Some((image_index, acquire_future))
+            Ok(Some((image_index, acquire_future)))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    pub fn draw_and_present<F>(&mut self, f: F)
+    /// Draws a frame with `f` and presents it. A swapchain that is out of date is made again and
+    /// the frame retried once, if that doesn't work either the frame is skipped. A lost device
+    /// comes back as `RendererLost`, nothing but a new renderer fixes that.
+    pub fn draw_and_present<F>(&mut self, f: F) -> Result<()>
     where
         F: FnOnce(&skia_safe::Canvas, LogicalSize<f32>),
     {
         // find the next framebuffer to render into and acquire a new GpuFuture to block on
-        let next_frame = self.get_next_frame().or_else(|| {
-            // if suboptimal or out-of-date, recreate the swapchain and try once more
-            self.prepare_swapchain();
-            self.get_next_frame()
-        });
+        let next_frame = match self.get_next_frame()? {
+            Some(next_frame) => Some(next_frame),
+            None => {
+                // if suboptimal or out-of-date, recreate the swapchain and try once more
+                self.prepare_swapchain()?;
+                self.get_next_frame()?
+            }
+        };
 
         if let Some((image_index, acquire_future)) = next_frame {
             // pull the appropriate framebuffer from the swapchain and attach a skia Surface to it
             let framebuffer = self.framebuffers[image_index as usize].clone();
-            let mut surface = surface_for_framebuffer(&mut self.skia_ctx, framebuffer.clone());
+            /* skia can't wrap the image once its context is abandoned, after a device loss */
+            let mut surface =
+                surface_for_framebuffer(&mut self.skia_ctx, framebuffer.clone(), self.color_space)
+                    .ok_or(RendererLost(VulkanError::DeviceLost))?;
             let canvas = surface.canvas();
 
             // use the display's DPI to convert the window size to logical coords and pre-scale the
@@ -365,10 +449,10 @@ impl VulkanRenderer {
             self.skia_ctx.flush_and_submit();
 
             // send the framebuffer to the gpu and display it on screen
-            self.last_render = self
+            let presented = self
                 .last_render
                 .take()
-                .unwrap()
+                .unwrap_or_else(|| sync::now(self.queue.device().clone()).boxed())
                 .join(acquire_future)
                 .then_swapchain_present(
                     self.queue.clone(),
@@ -377,10 +461,17 @@ impl VulkanRenderer {
                         image_index,
                     ),
                 )
-                .then_signal_fence_and_flush()
-                .map(|f| Box::new(f) as _)
-                .ok();
+                .then_signal_fence_and_flush();
+            match presented {
+                Ok(future) => self.last_render = Some(Box::new(future) as _),
+                Err(Validated::Error(VulkanError::OutOfDate)) => {
+                    /* the frame is lost, the next one goes to a new swapchain */
+                    self.swapchain_is_valid = false;
+                }
+                Err(err) => return Err(lost_or(err, "Failed to present the frame.")),
+            }
         }
+        Ok(())
     }
 }
 
@@ -388,7 +479,8 @@ impl VulkanRenderer {
 fn surface_for_framebuffer(
     skia_ctx: &mut gpu::DirectContext,
     framebuffer: Arc<Framebuffer>,
-) -> skia_safe::Surface {
+    color_space: ColorSpaceOption,
+) -> Option<skia_safe::Surface> {
     let [width, height] = framebuffer.extent();
     let image_access = &framebuffer.attachments()[0];
     let image_object = image_access.image().handle().as_raw();
@@ -429,8 +521,7 @@ fn surface_for_framebuffer(
         render_target,
         gpu::SurfaceOrigin::TopLeft,
         color_type,
-        None,
+        color_space.to_skia(),
         None,
     )
-    .unwrap()
 }
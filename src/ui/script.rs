@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use parley::{FontContext, LayoutContext};
+use serde_json::{Value, json};
+use winit::{dpi::PhysicalSize, keyboard::KeyCode, window::CursorIcon};
+
+use super::draw::{CarriedState, FiredEvent, PaintLog, StateKey, Viewport, WindowLike, draw};
+use super::dump::state_json;
+use super::{AppConfig, InputState, KeyPress, Libraries, Vdoms};
+
+// ::: ---- Scripted input, runs a tree frame by frame without a window ---- :::
+// Every line of a script is one frame and says what the input did before it. The frames are
+// drawn to a raster surface at a scale of 1, so layout pixels are window pixels, with a fixed
+// time step; the same tree and script always give the same output. That makes hover jumps,
+// clicks and scrolling checkable without a GPU or anyone moving a mouse.
+
+/// The input of one frame, everything not given stays as it was.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptStep {
+    cursor: Option<(f64, f64)>,
+    press: bool,   /* the left button goes down */
+    release: bool, /* and comes up, both in one step is a click within a frame */
+    scroll: f32,   /* like a trackpad reports it, negative scrolls down */
+    keys: Vec<KeyCode>,
+    keys_up: Vec<KeyCode>,
+    text: String, /* typed, for `KeyPress` */
+    dt: Duration,
+}
+
+/// Reads a script, one JSON object per line such as `{"cursor": [40, 12], "press": true}`.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn parse_script(text: &str) -> Result<Vec<ScriptStep>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| parse_step(line).with_context(|| format!("Line {} of the script", i + 1)))
+        .collect()
+}
+
+fn parse_step(line: &str) -> Result<ScriptStep> {
+    let obj: Value = serde_json::from_str(line)?;
+    let obj = obj
+        .as_object()
+        .ok_or(anyhow!("Expected a JSON object for each frame"))?;
+
+    let cursor = match obj.get("cursor") {
+        None => None,
+        Some(pos) => {
+            let xy = pos
+                .as_array()
+                .and_then(|xy| Some((xy.first()?.as_f64()?, xy.get(1)?.as_f64()?)))
+                .ok_or(anyhow!("'cursor' expects '[x, y]' in pixels"))?;
+            Some(xy)
+        }
+    };
+    let flag = |name: &str| -> Result<bool> {
+        match obj.get(name) {
+            None => Ok(false),
+            Some(v) => v
+                .as_bool()
+                .ok_or(anyhow!("'{}' expects true or false", name)),
+        }
+    };
+    let scroll = match obj.get("scroll") {
+        None => 0.0,
+        Some(v) => v
+            .as_f64()
+            .ok_or(anyhow!("'scroll' expects a number of pixels"))? as f32,
+    };
+    let keys = |name: &str| -> Result<Vec<KeyCode>> {
+        match obj.get(name) {
+            None => Ok(Vec::new()),
+            Some(v) => v
+                .as_array()
+                .ok_or(anyhow!("'{}' expects a list of key names", name))?
+                .iter()
+                .map(|k| {
+                    k.as_str()
+                        .ok_or(anyhow!("Key names are strings"))
+                        .and_then(key_code)
+                })
+                .collect(),
+        }
+    };
+    let text = match obj.get("text") {
+        None => String::new(),
+        Some(v) => v
+            .as_str()
+            .ok_or(anyhow!("'text' expects a string"))?
+            .to_owned(),
+    };
+    let dt_ms = match obj.get("dt_ms") {
+        None => 16,
+        Some(v) => v
+            .as_u64()
+            .ok_or(anyhow!("'dt_ms' expects whole milliseconds"))?,
+    };
+
+    Ok(ScriptStep {
+        cursor,
+        press: flag("press")?,
+        release: flag("release")?,
+        scroll,
+        keys: keys("keys")?,
+        keys_up: keys("keys_up")?,
+        text,
+        dt: Duration::from_millis(dt_ms),
+    })
+}
+
+/// The keys the runtime reacts to, the names are winit's.
+fn key_code(name: &str) -> Result<KeyCode> {
+    Ok(match name {
+        "ArrowUp" => KeyCode::ArrowUp,
+        "ArrowDown" => KeyCode::ArrowDown,
+        "ArrowLeft" => KeyCode::ArrowLeft,
+        "ArrowRight" => KeyCode::ArrowRight,
+        "PageUp" => KeyCode::PageUp,
+        "PageDown" => KeyCode::PageDown,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "Tab" => KeyCode::Tab,
+        "Enter" => KeyCode::Enter,
+        "Space" => KeyCode::Space,
+        "Escape" => KeyCode::Escape,
+        "Backspace" => KeyCode::Backspace,
+        "Delete" => KeyCode::Delete,
+        _ => return Err(anyhow!("Unknown key '{}'", name)),
+    })
+}
+
+/// Stands in for the window, remembers the cursor the elements asked for.
+struct ScriptWindow {
+    size: PhysicalSize<u32>,
+    cursor: Mutex<CursorIcon>,
+}
+impl WindowLike for ScriptWindow {
+    fn set_cursor(&self, cursor: CursorIcon) {
+        *self.cursor.lock().unwrap() = cursor;
+    }
+
+    fn inner_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+}
+
+/// Draws the tree in `vdoms` once per step. Fired events go to `cb_push_evt` as they happen,
+/// after each frame `on_frame` gets a summary of it: the frame's index, its error if it
+/// failed, the cursor, and the state carried into the next frame.
+pub fn run_script<F>(
+    width: u32,
+    height: u32,
+    config: &AppConfig,
+    vdoms: Vdoms,
+    libraries: Libraries,
+    steps: &[ScriptStep],
+    mut cb_push_evt: F,
+    mut on_frame: impl FnMut(Value),
+) -> Result<()>
+where
+    F: FnMut(FiredEvent) -> () + Clone,
+{
+    let mut surface = skia_safe::surfaces::raster_n32_premul((width as i32, height as i32)).ok_or(
+        anyhow!("Couldn't create a {}x{} raster surface", width, height),
+    )?;
+    let window = Arc::new(ScriptWindow {
+        size: PhysicalSize::new(width, height),
+        cursor: Mutex::new(CursorIcon::Default),
+    });
+    let mut font_context = FontContext::new();
+    let mut layout_context = LayoutContext::new();
+
+    let mut input_state = InputState::default();
+    let mut frame_state: HashMap<StateKey, CarriedState> = HashMap::new();
+    let mut last_viewport: Option<Viewport> = None;
+
+    for (i, step) in steps.iter().enumerate() {
+        // The same bookkeeping the window does with its events.
+        if let Some((x, y)) = step.cursor {
+            input_state.cursor_pos = (x, y).into();
+        }
+        if step.press {
+            input_state.mouse_down = true;
+            input_state.mouse_just_pressed = true;
+            input_state.keyboard_modality = false;
+        }
+        if step.release {
+            input_state.mouse_down = false;
+            input_state.mouse_just_released = true;
+        }
+        let factor = if config.natural_scroll {
+            -config.scroll_speed
+        } else {
+            config.scroll_speed
+        };
+        input_state.scroll_action = (0.0, step.scroll * factor);
+        input_state.keys_pressed = step
+            .keys
+            .iter()
+            .map(|&key| KeyPress { key, repeat: false })
+            .collect();
+        input_state.keys_released = step.keys_up.clone();
+        input_state.text_typed = step.text.chars().collect();
+        if !step.keys.is_empty() {
+            input_state.keyboard_modality = true;
+        }
+        *window.cursor.lock().unwrap() = CursorIcon::Default;
+
+        let canvas = surface.canvas();
+        canvas.clear(skia_safe::Color::WHITE);
+        let mut paint_log = PaintLog::default();
+        let guard = vdoms.lock().unwrap();
+        let library = libraries.lock().unwrap();
+        let (Some(loc), Some(buffer)) = guard.get() else {
+            return Err(anyhow!("There is no tree to run the script against"));
+        };
+        let r = buffer.with_bytes(|file_start, file_end| {
+            let frame = unsafe {
+                draw(
+                    loc,
+                    file_start,
+                    file_end,
+                    width as f32,
+                    height as f32,
+                    canvas,
+                    window.clone(),
+                    cb_push_evt.clone(),
+                    &input_state,
+                    &mut font_context,
+                    &mut layout_context,
+                    1.0,
+                    config.base_font_size,
+                    &frame_state,
+                    &library,
+                    config.vm_limits,
+                    config.color_space,
+                    config.reduce_motion,
+                    step.dt,
+                    &mut paint_log,
+                    config.profile,
+                    config.debug_layout,
+                )
+            }?;
+            let state = state_json(&frame.state, file_start, file_end);
+            Ok((frame, state))
+        });
+        drop((guard, library));
+
+        match r {
+            Ok((frame, state)) => {
+                frame_state = frame.state;
+                if last_viewport != Some(frame.viewport) {
+                    cb_push_evt(FiredEvent::Viewport(frame.viewport));
+                    last_viewport = Some(frame.viewport);
+                }
+                on_frame(json!({
+                    "kind": "frame",
+                    "frame": i,
+                    "cursor": window.cursor.lock().unwrap().name(),
+                    "state": state,
+                }));
+            }
+            Err(err) => on_frame(json!({
+                "kind": "frame",
+                "frame": i,
+                "error": format!("{:#}", err),
+            })),
+        }
+
+        input_state.mouse_just_pressed = false;
+        input_state.mouse_just_released = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{parse_script, run_script};
+    use crate::ui::draw::{FiredEvent, Tag};
+    use crate::ui::testing::{Tree, px};
+    use crate::ui::{AppConfig, TreeBuffer, Vdom};
+
+    #[test]
+    fn clicks_a_button() {
+        let mut tree = Tree::new();
+        tree.enter()
+            .op(Tag::Width, &[px(100.0)])
+            .op(Tag::Height, &[px(40.0)])
+            .jump(Tag::Clicked, &[], |t| {
+                t.push((Tag::Event, 3));
+            })
+            .leave();
+        let (memory, root) = tree.memory();
+        let vdoms = Arc::new(Mutex::new(Vdom::new(
+            root,
+            TreeBuffer::Owned(Arc::new(memory)),
+        )));
+        let libraries = Arc::new(Mutex::new(HashMap::new()));
+        let steps = parse_script(
+            "# click the button, then wait\n\
+             {\"cursor\": [20, 10]}\n\
+             {\"press\": true, \"release\": true}\n\
+             {}\n\
+             \n\
+             {}\n",
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 4);
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let events = log.clone();
+        let frames = log.clone();
+        run_script(
+            200,
+            100,
+            &AppConfig::default(),
+            vdoms,
+            libraries,
+            &steps,
+            move |evt| {
+                if let FiredEvent::Id(id) = evt {
+                    events.lock().unwrap().push(format!("event {}", id));
+                }
+            },
+            move |frame| {
+                assert!(frame.get("error").is_none(), "{}", frame);
+                frames
+                    .lock()
+                    .unwrap()
+                    .push(format!("frame {}", frame["frame"]));
+            },
+        )
+        .unwrap();
+
+        // The click is seen in frame 1 and its jump taken in frame 2, once.
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["frame 0", "frame 1", "event 3", "frame 2", "frame 3"]
+        );
+    }
+
+    #[test]
+    fn rejects_an_unknown_key() {
+        let err = parse_script("{}\n{\"keys\": [\"Hyper\"]}").unwrap_err();
+        assert!(format!("{:#}", err).contains("Line 2"));
+    }
+}
@@ -0,0 +1,347 @@
+// ::: ---- Test harness, trees drawn without a window or GPU ---- :::
+// Tests write small trees with `Tree` the way a client writes them into the shared file, draw
+// them with `Headless` into a raster surface at a fixed size and scale, and check the events,
+// the cursor, the state carried to the next frame or the pixels. The golden-image tests in
+// `tests/golden.rs` assemble their trees with `Tree::assembled` and compare `snapshot` to PNGs.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use parley::{FontContext, LayoutContext};
+use skia_safe::{Color, Image, Surface};
+use winit::{dpi::PhysicalSize, keyboard::KeyCode, window::CursorIcon};
+
+use super::assembler::assemble;
+use super::draw::{
+    CarriedState, DrawnFrame, FiredEvent, PaintLog, ParamUnion, StateKey, Tag, VmLimits,
+    WindowLike, draw,
+};
+use super::{ColorSpaceOption, InputState, KeyPress};
+
+/// A tagged word as a test writes it, see `px`, `rgb` and the like.
+pub type Word = (Tag, usize);
+
+pub fn real(tag: Tag, value: f32) -> Word {
+    let mut word = ParamUnion { word: 0 };
+    word.real = value;
+    (tag, unsafe { word.word })
+}
+
+pub fn px(value: f32) -> Word {
+    real(Tag::Pxs, value)
+}
+
+pub fn frac(value: f32) -> Word {
+    real(Tag::Frac, value)
+}
+
+pub fn auto() -> Word {
+    (Tag::Auto, 0)
+}
+
+pub fn rgb(r: u8, g: u8, b: u8) -> Word {
+    let mut word = ParamUnion { word: 0 };
+    word.short_color = (r, g, b);
+    (Tag::Rgb, unsafe { word.word })
+}
+
+pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Word {
+    let mut word = ParamUnion { word: 0 };
+    word.long_color = (r, g, b, a);
+    (Tag::Rgba, unsafe { word.word })
+}
+
+pub fn reg(id: usize) -> Word {
+    (Tag::FromReg, id)
+}
+
+pub fn pull() -> Word {
+    (Tag::PullArg, 0)
+}
+
+/// A tree as a client would write it. Strings go in front of the layout, which starts with the
+/// first `Enter` written, and library bodies are kept apart like the `register_library` ask
+/// keeps them.
+#[derive(Debug, Clone, Default)]
+pub struct Tree {
+    strings: Vec<usize>,
+    words: Vec<usize>,
+    pub libraries: HashMap<usize, Vec<usize>>,
+}
+impl Tree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A layout written in the assembler's text, see `assemble`.
+    pub fn assembled(src: &str) -> Result<Self> {
+        let words = assemble(src)?
+            .chunks_exact(size_of::<usize>())
+            .map(|word| usize::from_ne_bytes(word.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            words,
+            ..Self::default()
+        })
+    }
+
+    /// Where the next word of the layout goes, in bytes from the start of the layout.
+    pub fn offset(&self) -> usize {
+        self.words.len() * size_of::<usize>()
+    }
+
+    /// Writes `text` like `aloc_tagged_str` in `client.py`, returns its offset for a `TextPtr`.
+    pub fn string(&mut self, text: &str) -> usize {
+        let at = self.strings.len() * size_of::<usize>();
+        self.strings.extend([Tag::Array as usize, text.len()]);
+        for chunk in text.as_bytes().chunks(size_of::<usize>()) {
+            let mut word = [0u8; size_of::<usize>()];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.strings.push(usize::from_ne_bytes(word));
+        }
+        if self.strings.len() % 2 != 0 {
+            self.strings.push(0); /* the next string starts on a tagged word too */
+        }
+        at
+    }
+
+    pub fn push(&mut self, (tag, word): Word) -> &mut Self {
+        self.words.extend([tag as usize, word]);
+        self
+    }
+
+    /// An instruction and its arguments.
+    pub fn op(&mut self, tag: Tag, args: &[Word]) -> &mut Self {
+        self.push((tag, 0));
+        for arg in args {
+            self.push(*arg);
+        }
+        self
+    }
+
+    pub fn enter(&mut self) -> &mut Self {
+        self.push((Tag::Enter, 0))
+    }
+
+    pub fn leave(&mut self) -> &mut Self {
+        self.push((Tag::Leave, 0))
+    }
+
+    pub fn text(&mut self, x: Word, y: Word, text: &str) -> &mut Self {
+        let at = self.string(text);
+        self.op(Tag::Text, &[x, y, (Tag::TextPtr, at)])
+    }
+
+    /// A jump like `Hover` over whatever `body` writes, `args` go between the two like the key
+    /// of a `KeyDown`.
+    pub fn jump(&mut self, tag: Tag, args: &[Word], body: impl FnOnce(&mut Self)) -> &mut Self {
+        self.push((tag, 0));
+        let word = self.words.len() - 1;
+        for arg in args {
+            self.push(*arg);
+        }
+        let start = self.offset();
+        body(self);
+        self.words[word] = self.offset() - start;
+        self
+    }
+
+    /// Writes what `body` writes as the library `id` instead of into the layout.
+    pub fn library(&mut self, id: usize, body: impl FnOnce(&mut Self)) -> &mut Self {
+        let layout = std::mem::take(&mut self.words);
+        body(self);
+        let code = std::mem::replace(&mut self.words, layout);
+        self.libraries.insert(id, code);
+        self
+    }
+
+    /// The memory the tree is drawn from and the offset of its root in it.
+    pub fn memory(&self) -> (Vec<usize>, usize) {
+        let mut memory = self.strings.clone();
+        memory.extend(&self.words);
+        (memory, self.strings.len() * size_of::<usize>())
+    }
+}
+
+/// Stands in for the window, remembers every cursor the elements asked for in a frame.
+pub struct MockWindow {
+    size: PhysicalSize<u32>,
+    pub cursors: Mutex<Vec<CursorIcon>>,
+}
+impl WindowLike for MockWindow {
+    fn set_cursor(&self, cursor: CursorIcon) {
+        self.cursors.lock().unwrap().push(cursor);
+    }
+
+    fn inner_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+}
+
+/// What drawing one frame did.
+pub struct Frame {
+    pub events: Vec<FiredEvent>,
+    pub cursors: Vec<CursorIcon>, /* every `set_cursor`, in order */
+    pub drawn: DrawnFrame,
+}
+impl Frame {
+    /// The ids of the `Event`s and `EventArg`s that fired, in order.
+    pub fn event_ids(&self) -> Vec<usize> {
+        self.events
+            .iter()
+            .filter_map(|evt| match evt {
+                FiredEvent::Id(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Draws trees frame by frame like the window does, with input the test sets in between.
+pub struct Headless {
+    width: u32,
+    height: u32,
+    pub scale: f32,
+    pub base_font_size: f32,
+    pub reduce_motion: bool,
+    pub dt: Duration,
+    pub input: InputState,
+    pub state: HashMap<StateKey, CarriedState>, /* carried from the last frame */
+    surface: Surface,
+    window: Arc<MockWindow>,
+    font_context: FontContext,
+    layout_context: LayoutContext,
+}
+impl Headless {
+    /// A white `width` by `height` window at a scale of 1.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            scale: 1.0,
+            base_font_size: 16.0,
+            reduce_motion: false,
+            dt: Duration::from_millis(16),
+            input: InputState::default(),
+            state: HashMap::new(),
+            surface: skia_safe::surfaces::raster_n32_premul((width as i32, height as i32))
+                .expect("a raster surface"),
+            window: Arc::new(MockWindow {
+                size: PhysicalSize::new(width, height),
+                cursors: Mutex::new(Vec::new()),
+            }),
+            font_context: FontContext::new(),
+            layout_context: LayoutContext::new(),
+        }
+    }
+
+    /// Draws `tree` once. The state carries over to the next frame and the input that only
+    /// lasts a frame, presses, releases, scrolls and keys, is used up.
+    pub fn frame(&mut self, tree: &Tree) -> Result<Frame> {
+        let (memory, root) = tree.memory();
+        let file_start = memory.as_ptr() as *const u8;
+        let file_end = unsafe { file_start.add(memory.len() * size_of::<usize>()) };
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        self.window.cursors.lock().unwrap().clear();
+
+        let canvas = self.surface.canvas();
+        canvas.clear(Color::WHITE);
+        let drawn = unsafe {
+            draw(
+                root,
+                file_start,
+                file_end,
+                self.width as f32,
+                self.height as f32,
+                canvas,
+                self.window.clone(),
+                move |evt| sink.borrow_mut().push(evt),
+                &self.input,
+                &mut self.font_context,
+                &mut self.layout_context,
+                self.scale,
+                self.base_font_size,
+                &self.state,
+                &tree.libraries,
+                VmLimits::default(),
+                ColorSpaceOption::Srgb,
+                self.reduce_motion,
+                self.dt,
+                &mut PaintLog::default(),
+                false,
+                false,
+            )
+        }?;
+        self.state = drawn.state.clone();
+
+        self.input.mouse_just_pressed = false;
+        self.input.mouse_just_released = false;
+        self.input.scroll_action = (0.0, 0.0);
+        self.input.keys_pressed.clear();
+        self.input.keys_released.clear();
+        self.input.text_typed.clear();
+        Ok(Frame {
+            events: events.take(),
+            cursors: self.window.cursors.lock().unwrap().clone(),
+            drawn,
+        })
+    }
+
+    /// Moves the pointer, in the window's pixels.
+    pub fn move_to(&mut self, x: f64, y: f64) -> &mut Self {
+        self.input.cursor_pos = (x, y).into();
+        self
+    }
+
+    pub fn press(&mut self) -> &mut Self {
+        self.input.mouse_down = true;
+        self.input.mouse_just_pressed = true;
+        self.input.keyboard_modality = false;
+        self
+    }
+
+    pub fn release(&mut self) -> &mut Self {
+        self.input.mouse_down = false;
+        self.input.mouse_just_released = true;
+        self
+    }
+
+    /// Negative scrolls down, like a trackpad reports it.
+    pub fn scroll(&mut self, dy: f32) -> &mut Self {
+        self.input.scroll_action = (0.0, dy);
+        self
+    }
+
+    pub fn key(&mut self, key: KeyCode) -> &mut Self {
+        self.input
+            .keys_pressed
+            .push(KeyPress { key, repeat: false });
+        self.input.keyboard_modality = true;
+        self
+    }
+
+    pub fn type_text(&mut self, text: &str) -> &mut Self {
+        self.input.text_typed.extend(text.chars());
+        self
+    }
+
+    /// The colour of a pixel of the last frame.
+    pub fn pixel(&mut self, x: i32, y: i32) -> Color {
+        self.surface
+            .peek_pixels()
+            .expect("raster surfaces have pixels")
+            .get_color((x, y))
+    }
+
+    /// The last frame.
+    pub fn snapshot(&mut self) -> Image {
+        self.surface.image_snapshot()
+    }
+}
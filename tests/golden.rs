@@ -0,0 +1,164 @@
+// ::: ---- Golden images ---- :::
+// Small trees written in the assembler's text, drawn headlessly at a fixed size and scale and
+// compared to the PNGs in `tests/golden` with a small tolerance for anti-aliasing. A golden that
+// isn't there fails the test. After a change that is meant to change how things look,
+// `Z71200_BLESS=1 cargo test --test golden` writes every golden anew, look at the PNGs before
+// committing them.
+use std::path::PathBuf;
+
+use skia_safe::{AlphaType, Color, ColorType, Data, EncodedImageFormat, Image, ImageInfo};
+use z71200_runtime::ui::testing::{Headless, Tree};
+
+/* how far a channel may be off before a pixel counts as different, for anti-aliasing */
+const CHANNEL_TOLERANCE: u8 = 2;
+/* and how many pixels in a thousand may differ, for text drawn with another font version */
+const PIXELS_PER_MILLE: usize = 2;
+
+/// A root filling the window with `body` inside it.
+fn root(body: &str) -> Tree {
+    let src = format!(
+        "Enter
+        Width
+        Frac, f1
+        Height
+        Frac, f1
+        {}
+        Leave",
+        body
+    );
+    Tree::assembled(&src).unwrap()
+}
+
+/// The pixels of `image`, four bytes each, unpremultiplied.
+fn pixels_of(image: &Image) -> Vec<u8> {
+    let info = ImageInfo::new(
+        image.dimensions(),
+        ColorType::RGBA8888,
+        AlphaType::Unpremul,
+        None,
+    );
+    let mut pixels = vec![0u8; info.compute_min_byte_size()];
+    assert!(
+        image.read_pixels(
+            &info,
+            pixels.as_mut_slice(),
+            info.min_row_bytes(),
+            (0, 0),
+            skia_safe::image::CachingHint::Disallow,
+        ),
+        "Couldn't read the pixels of the frame"
+    );
+    pixels
+}
+
+/// Compares the last frame to `tests/golden/<name>.png`, see the top of the file.
+fn assert_golden(headless: &mut Headless, name: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name));
+    let image = headless.snapshot();
+    if std::env::var_os("Z71200_BLESS").is_some() {
+        let png = image
+            .encode(None, EncodedImageFormat::PNG, None)
+            .expect("the frame encodes as a PNG");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, png.as_bytes()).unwrap();
+        return;
+    }
+
+    let bytes = std::fs::read(&path).unwrap_or_else(|_| {
+        panic!(
+            "There is no golden `{}`, run with `Z71200_BLESS=1` to write it",
+            path.display()
+        )
+    });
+    let golden = Image::from_encoded(Data::new_copy(&bytes))
+        .unwrap_or_else(|| panic!("`{}` isn't a PNG", path.display()));
+    assert_eq!(
+        golden.dimensions(),
+        image.dimensions(),
+        "The golden `{}` is a different size than the frame",
+        path.display()
+    );
+    let (frame, golden) = (pixels_of(&image), pixels_of(&golden));
+    let off = frame
+        .chunks(4)
+        .zip(golden.chunks(4))
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(b.iter())
+                .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let pixels = frame.len() / 4;
+    assert!(
+        off * 1000 <= pixels * PIXELS_PER_MILLE,
+        "{} of {} pixels differ from the golden `{}`, run with `Z71200_BLESS=1` if that's intended",
+        off,
+        pixels,
+        path.display()
+    );
+}
+
+#[test]
+fn rect() {
+    let tree = root(
+        "Color
+        Rgb, bc81e1e
+        Rect
+        Pxs, f10
+        Pxs, f10
+        Pxs, f40
+        Pxs, f20",
+    );
+    let mut headless = Headless::new(64, 48);
+    headless.frame(&tree).unwrap();
+    assert_eq!(headless.pixel(30, 20), Color::from_rgb(200, 30, 30));
+    assert_eq!(headless.pixel(5, 5), Color::WHITE);
+    assert_golden(&mut headless, "rect");
+}
+
+#[test]
+fn rounded_rect() {
+    let tree = root(
+        "Color
+        Rgb, b1e1ec8
+        RoundedRect
+        Pxs, f8
+        Pxs, f8
+        Pxs, f48
+        Pxs, f32
+        Pxs, f10",
+    );
+    let mut headless = Headless::new(64, 48);
+    headless.frame(&tree).unwrap();
+    assert_eq!(headless.pixel(32, 24), Color::from_rgb(30, 30, 200));
+    assert_eq!(headless.pixel(9, 9), Color::WHITE, "the corner is cut off");
+    assert_golden(&mut headless, "rounded_rect");
+}
+
+#[test]
+fn frac_lengths() {
+    // A child half as wide as the window with a rect a quarter of its own size.
+    let tree = root(
+        "Enter
+        Width
+        Frac, f0.5
+        Height
+        Frac, f1
+        Color
+        Rgb, b009600
+        Rect
+        Pxs, f0
+        Pxs, f0
+        Frac, f0.5
+        Frac, f0.5
+        Leave",
+    );
+    let mut headless = Headless::new(80, 40);
+    headless.frame(&tree).unwrap();
+    assert_eq!(headless.pixel(18, 18), Color::from_rgb(0, 150, 0));
+    assert_eq!(headless.pixel(22, 10), Color::WHITE);
+    assert_eq!(headless.pixel(10, 22), Color::WHITE);
+    assert_golden(&mut headless, "frac_lengths");
+}